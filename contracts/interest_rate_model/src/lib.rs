@@ -60,7 +60,12 @@
 //! | R_max | 100% | Maximum rate at 100% utilization |
 //! | U* | 80% | Optimal/target utilization |
 
-use soroban_sdk::{contract, contractimpl, contracttype, Env};
+use soroban_sdk::{
+    contract, contracterror, contractimpl, contracttype, panic_with_error, symbol_short, Address, Env, Symbol, Vec,
+};
+
+mod math;
+use math::mul_div_floor;
 
 // ============================================================================
 // CONSTANTS
@@ -72,8 +77,20 @@ use soroban_sdk::{contract, contractimpl, contracttype, Env};
 /// - 0.01% = 1_000 (1 basis point)
 const SCALE: i128 = 10_000_000;
 
-/// Seconds per year (365.25 days)
-const SECONDS_PER_YEAR: i128 = 31_557_600;
+/// Default seconds per year (365.25 days)
+const DEFAULT_SECONDS_PER_YEAR: i128 = 31_557_600;
+
+/// Scale of the compound growth factor (1e9, matching the pool's borrow index)
+const INDEX_SCALE: i128 = 1_000_000_000;
+
+/// Internal precision for compound interest (1e18)
+const COMPOUND_PRECISION: i128 = 1_000_000_000_000_000_000;
+/// Largest `rate_per_second * t` compounded by one expansion step (4%, at
+/// COMPOUND_PRECISION); longer intervals are split into equal steps
+const MAX_COMPOUND_STEP: i128 = COMPOUND_PRECISION / 25;
+
+/// Reserve factor assumed by `get_supply_rate_default` (10%)
+const DEFAULT_RESERVE_FACTOR: i128 = 1_000_000;
 
 /// Utilization thresholds (scaled by SCALE)
 const U_85: i128 = 8_500_000;  // 85%
@@ -81,14 +98,54 @@ const U_90: i128 = 9_000_000;  // 90%
 const U_95: i128 = 9_500_000;  // 95%
 const U_99: i128 = 9_900_000;  // 99%
 
+/// Maximum number of points returned by `get_rate_curve`
+const MAX_CURVE_POINTS: u32 = 100;
+
+/// One basis point (0.01%) in SCALE units
+const BPS: i128 = 1_000;
+
+/// Smoothing state TTL is bumped once it falls below this many ledgers (~15 days)
+const SMOOTHING_TTL_THRESHOLD: u32 = 259_200;
+/// Ledgers a smoothing state entry lives for after an update (~30 days)
+const SMOOTHING_TTL_EXTEND: u32 = 518_400;
+
 // ============================================================================
 // STORAGE
 // ============================================================================
 
+/// Errors returned by the interest rate model
+///
+/// Codes are part of the contract interface: existing variants are never
+/// renumbered, new ones are appended.
+#[contracterror]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
+#[repr(u32)]
+pub enum RateModelError {
+    /// The model was already initialized
+    AlreadyInitialized = 1,
+    /// Optimal utilization must be strictly between 0 and 100%
+    InvalidOptimalUtilization = 2,
+    /// Rates must satisfy R_min <= R_opt <= R_max
+    RateOrdering = 3,
+    /// Reserve factor must be between 0 and 100%
+    InvalidReserveFactor = 4,
+    /// Rate curve must have between 2 and 100 points
+    InvalidCurvePoints = 5,
+    /// Smoothed rate change limit must be non-negative
+    InvalidRateChangeLimit = 6,
+    /// Seconds per year must be positive
+    InvalidSecondsPerYear = 7,
+    /// A compound factor calculation doesn't fit in an i128
+    MathOverflow = 8,
+}
+
 /// Storage keys for the interest rate model parameters
 #[derive(Clone)]
 #[contracttype]
 pub enum DataKey {
+    /// Admin address allowed to retune the curve
+    Admin,
+
     /// Minimum interest rate (floor)
     /// Scaled by 1e7, e.g., 0% = 0
     RateMin,
@@ -104,6 +161,22 @@ pub enum DataKey {
     /// Optimal utilization rate (U*)
     /// Scaled by 1e7, e.g., 80% = 8_000_000
     OptimalUtilization,
+
+    /// Maximum move of the smoothed borrow rate, in bps per second
+    /// (0 = smoothing disabled)
+    MaxRateChange,
+
+    /// Last smoothed rate recorded by a pool for one of its markets
+    /// via `update_borrow_rate_smoothed` (scaled by 1e7). Persistent, so
+    /// any number of callers can't grow the instance entry.
+    LastRate(Address, Symbol),
+
+    /// Timestamp of `LastRate` for the same pool and market (persistent)
+    LastRateTime(Address, Symbol),
+
+    /// Length of the accrual year in seconds, used to convert annual
+    /// rates to per-second rates
+    SecondsPerYear,
 }
 
 // ============================================================================
@@ -126,44 +199,39 @@ impl InterestRateModel {
     /// Initialize the interest rate model with custom parameters
     ///
     /// # Arguments
+    /// * `admin` - Admin address allowed to update the parameters
     /// * `rate_min` - Minimum rate floor (scaled by 1e7)
     /// * `rate_opt` - Rate at optimal utilization (scaled by 1e7)
     /// * `rate_max` - Maximum rate at 100% utilization (scaled by 1e7)
     /// * `optimal_utilization` - Optimal utilization U* (scaled by 1e7)
+    /// * `seconds_per_year` - Length of the accrual year in seconds
+    ///   (31_557_600 for 365.25 days, 31_104_000 for a 360-day year)
     ///
     /// # Example
     /// ```ignore
-    /// // R_min=0%, R_opt=4%, R_max=100%, U*=80%
-    /// client.initialize(&0, &400_000, &10_000_000, &8_000_000);
+    /// // R_min=0%, R_opt=4%, R_max=100%, U*=80%, 365.25-day year
+    /// client.initialize(&admin, &0, &400_000, &10_000_000, &8_000_000, &31_557_600);
     /// ```
     pub fn initialize(
         env: Env,
+        admin: Address,
         rate_min: i128,
         rate_opt: i128,
         rate_max: i128,
         optimal_utilization: i128,
+        seconds_per_year: i128,
     ) {
         // Prevent re-initialization
         if env.storage().instance().has(&DataKey::RateMin) {
-            panic!("Already initialized");
+            panic_with_error!(&env, RateModelError::AlreadyInitialized);
         }
-
-        // Validate parameters
-        if optimal_utilization <= 0 || optimal_utilization >= SCALE {
-            panic!("Invalid optimal utilization: must be between 0 and 100%");
-        }
-        if rate_opt < rate_min {
-            panic!("Rate optimal must be >= rate min");
-        }
-        if rate_max < rate_opt {
-            panic!("Rate max must be >= rate optimal");
+        if seconds_per_year <= 0 {
+            panic_with_error!(&env, RateModelError::InvalidSecondsPerYear);
         }
 
-        // Store parameters
-        env.storage().instance().set(&DataKey::RateMin, &rate_min);
-        env.storage().instance().set(&DataKey::RateOpt, &rate_opt);
-        env.storage().instance().set(&DataKey::RateMax, &rate_max);
-        env.storage().instance().set(&DataKey::OptimalUtilization, &optimal_utilization);
+        env.storage().instance().set(&DataKey::Admin, &admin);
+        env.storage().instance().set(&DataKey::SecondsPerYear, &seconds_per_year);
+        Self::store_parameters(&env, rate_min, rate_opt, rate_max, optimal_utilization);
     }
 
     /// Initialize with default parameters for Stellend MVP
@@ -173,16 +241,116 @@ impl InterestRateModel {
     /// - R_opt: 4% (at 80% utilization)
     /// - R_max: 100% (at 100% utilization)
     /// - U*: 80%
-    pub fn initialize_default(env: Env) {
+    /// - Year: 365.25 days
+    pub fn initialize_default(env: Env, admin: Address) {
         Self::initialize(
             env,
+            admin,
             0,             // 0% minimum rate
             400_000,       // 4% optimal rate
             10_000_000,    // 100% max rate
             8_000_000,     // 80% optimal utilization
+            DEFAULT_SECONDS_PER_YEAR,
         );
     }
 
+    // ========================================================================
+    // ADMIN FUNCTIONS
+    // ========================================================================
+
+    /// Retune the rate curve (admin only)
+    ///
+    /// Applies the same validation as `initialize`. The contract address
+    /// stays the same, so pools referencing it pick up the new curve.
+    ///
+    /// # Arguments
+    /// * `rate_min` - Minimum rate floor (scaled by 1e7)
+    /// * `rate_opt` - Rate at optimal utilization (scaled by 1e7)
+    /// * `rate_max` - Maximum rate at 100% utilization (scaled by 1e7)
+    /// * `optimal_utilization` - Optimal utilization U* (scaled by 1e7)
+    ///
+    /// # Events
+    /// Emits `("params",)` with the new parameters
+    pub fn update_parameters(
+        env: Env,
+        rate_min: i128,
+        rate_opt: i128,
+        rate_max: i128,
+        optimal_utilization: i128,
+    ) {
+        let admin: Address = env.storage().instance().get(&DataKey::Admin).unwrap();
+        admin.require_auth();
+
+        Self::store_parameters(&env, rate_min, rate_opt, rate_max, optimal_utilization);
+
+        env.events().publish(
+            (symbol_short!("params"),),
+            (rate_min, rate_opt, rate_max, optimal_utilization),
+        );
+    }
+
+    /// Limit how fast the smoothed borrow rate can move (admin only)
+    ///
+    /// # Arguments
+    /// * `bps_per_second` - Maximum change of `get_borrow_rate_smoothed`
+    ///   per second of elapsed time, in basis points (0 disables smoothing)
+    ///
+    /// # Events
+    /// Emits `("ramp",)` with the new limit
+    pub fn set_max_rate_change(env: Env, bps_per_second: i128) {
+        let admin: Address = env.storage().instance().get(&DataKey::Admin).unwrap();
+        admin.require_auth();
+
+        if bps_per_second < 0 {
+            panic_with_error!(&env, RateModelError::InvalidRateChangeLimit);
+        }
+
+        env.storage().instance().set(&DataKey::MaxRateChange, &bps_per_second);
+        env.events().publish((symbol_short!("ramp"),), bps_per_second);
+    }
+
+    /// Get the smoothed borrow rate limit in bps per second
+    pub fn get_max_rate_change(env: Env) -> i128 {
+        env.storage().instance().get(&DataKey::MaxRateChange).unwrap_or(0)
+    }
+
+    /// Get the admin address
+    pub fn get_admin(env: Env) -> Address {
+        env.storage().instance().get(&DataKey::Admin).unwrap()
+    }
+
+    /// Transfer admin role
+    ///
+    /// # Arguments
+    /// * `new_admin` - New admin address
+    pub fn set_admin(env: Env, new_admin: Address) {
+        let admin: Address = env.storage().instance().get(&DataKey::Admin).unwrap();
+        admin.require_auth();
+
+        env.storage().instance().set(&DataKey::Admin, &new_admin);
+        env.events().publish((symbol_short!("new_admin"),), new_admin);
+    }
+
+    /// Internal: Validate and store the curve parameters
+    fn store_parameters(env: &Env, rate_min: i128, rate_opt: i128, rate_max: i128, optimal_utilization: i128) {
+        // Validate parameters
+        if optimal_utilization <= 0 || optimal_utilization >= SCALE {
+            panic_with_error!(env, RateModelError::InvalidOptimalUtilization);
+        }
+        if rate_opt < rate_min {
+            panic_with_error!(env, RateModelError::RateOrdering);
+        }
+        if rate_max < rate_opt {
+            panic_with_error!(env, RateModelError::RateOrdering);
+        }
+
+        // Store parameters
+        env.storage().instance().set(&DataKey::RateMin, &rate_min);
+        env.storage().instance().set(&DataKey::RateOpt, &rate_opt);
+        env.storage().instance().set(&DataKey::RateMax, &rate_max);
+        env.storage().instance().set(&DataKey::OptimalUtilization, &optimal_utilization);
+    }
+
     // ========================================================================
     // RATE CALCULATION - Multi-Kink Model (Drift Protocol inspired)
     // ========================================================================
@@ -287,6 +455,30 @@ impl InterestRateModel {
         }
     }
 
+    /// Sample the borrow rate curve in a single call
+    ///
+    /// Returns `points` evenly spaced utilization values from 0 to 100%
+    /// (both inclusive) with their borrow rates, for plotting the curve.
+    ///
+    /// # Arguments
+    /// * `points` - Number of samples (2 to 100)
+    ///
+    /// # Returns
+    /// `(utilization, borrow_rate)` pairs, both scaled by 1e7
+    pub fn get_rate_curve(env: Env, points: u32) -> Vec<(i128, i128)> {
+        if !(2..=MAX_CURVE_POINTS).contains(&points) {
+            panic_with_error!(&env, RateModelError::InvalidCurvePoints);
+        }
+
+        let mut curve = Vec::new(&env);
+        let last = (points - 1) as i128;
+        for i in 0..points {
+            let utilization = (SCALE * i as i128) / last;
+            curve.push_back((utilization, Self::get_borrow_rate(env.clone(), utilization)));
+        }
+        curve
+    }
+
     /// Get the borrow rate per second (for interest accrual)
     ///
     /// # Arguments
@@ -295,30 +487,189 @@ impl InterestRateModel {
     /// # Returns
     /// Rate per second (scaled by 1e7)
     pub fn get_borrow_rate_per_second(env: Env, utilization: i128) -> i128 {
+        let seconds_per_year = Self::get_seconds_per_year(env.clone());
         let annual_rate = Self::get_borrow_rate(env, utilization);
-        annual_rate / SECONDS_PER_YEAR
+        annual_rate / seconds_per_year
+    }
+
+    /// Get the borrow rate, moved toward the curve rate at a limited pace
+    ///
+    /// Steps from the rate `pool` last recorded for `market` toward
+    /// `get_borrow_rate(utilization)` by at most `get_max_rate_change` bps
+    /// per second elapsed, so a single large borrow can't spike the rate in
+    /// one transaction. Read-only: the pool records the rate it applies with
+    /// `update_borrow_rate_smoothed`. With no limit set, or before the first
+    /// update, the curve rate is returned unchanged.
+    ///
+    /// # Arguments
+    /// * `pool` - Pool whose smoothing state to read
+    /// * `market` - Market within the pool
+    /// * `utilization` - Current utilization rate (scaled by 1e7)
+    ///
+    /// # Returns
+    /// Annualized borrow rate (scaled by 1e7)
+    pub fn get_borrow_rate_smoothed(env: Env, pool: Address, market: Symbol, utilization: i128) -> i128 {
+        Self::smoothed_rate(&env, &pool, &market, utilization)
+    }
+
+    /// Compute the smoothed borrow rate and record it for the next step
+    ///
+    /// Returns the same rate as `get_borrow_rate_smoothed`. Only `pool`
+    /// can write its own state, so one pool's markets can't be moved by
+    /// another caller. The state lives in persistent storage and its TTL
+    /// is extended on every update; an entry that expires after a pool
+    /// goes quiet simply restarts from the curve rate.
+    ///
+    /// # Arguments
+    /// * `pool` - Calling pool (must authorize)
+    /// * `market` - Market within the pool
+    /// * `utilization` - Current utilization rate (scaled by 1e7)
+    ///
+    /// # Returns
+    /// Annualized borrow rate (scaled by 1e7)
+    pub fn update_borrow_rate_smoothed(env: Env, pool: Address, market: Symbol, utilization: i128) -> i128 {
+        pool.require_auth();
+
+        let rate = Self::smoothed_rate(&env, &pool, &market, utilization);
+        let now = env.ledger().timestamp();
+        let rate_key = DataKey::LastRate(pool.clone(), market.clone());
+        let time_key = DataKey::LastRateTime(pool, market);
+        let storage = env.storage().persistent();
+        storage.set(&rate_key, &rate);
+        storage.set(&time_key, &now);
+        storage.extend_ttl(&rate_key, SMOOTHING_TTL_THRESHOLD, SMOOTHING_TTL_EXTEND);
+        storage.extend_ttl(&time_key, SMOOTHING_TTL_THRESHOLD, SMOOTHING_TTL_EXTEND);
+        rate
+    }
+
+    /// Internal: Curve rate clamped to the pace limit around the last recorded rate
+    fn smoothed_rate(env: &Env, pool: &Address, market: &Symbol, utilization: i128) -> i128 {
+        let target = Self::get_borrow_rate(env.clone(), utilization);
+        let max_change = Self::get_max_rate_change(env.clone());
+        let now = env.ledger().timestamp();
+
+        let last_rate: Option<i128> = env
+            .storage()
+            .persistent()
+            .get(&DataKey::LastRate(pool.clone(), market.clone()));
+        match last_rate {
+            Some(last_rate) if max_change > 0 => {
+                let last_time: u64 = env
+                    .storage()
+                    .persistent()
+                    .get(&DataKey::LastRateTime(pool.clone(), market.clone()))
+                    .unwrap_or(now);
+                let elapsed = now.saturating_sub(last_time) as i128;
+                let max_step = max_change.saturating_mul(BPS).saturating_mul(elapsed);
+                target.clamp(last_rate.saturating_sub(max_step), last_rate.saturating_add(max_step))
+            }
+            _ => target,
+        }
+    }
+
+    /// Get the compound growth factor over a period
+    ///
+    /// Approximates `(1 + rate_per_second)^time_elapsed` with the first
+    /// terms of the binomial expansion, where `x = rate_per_second * t`:
+    /// `1 + x + x^2 (t-1) / 2t + x^3 (t-1)(t-2) / 6t^2`
+    ///
+    /// Intervals with `x` above 4% (a market left untouched for years, or
+    /// a steep curve after `update_parameters`) are split into equal steps
+    /// of at most that size and multiplied back together, so the expansion
+    /// stays accurate and no product can overflow.
+    ///
+    /// # Arguments
+    /// * `utilization` - Current utilization rate (scaled by 1e7)
+    /// * `time_elapsed` - Seconds since the last accrual
+    ///
+    /// # Returns
+    /// Growth factor (scaled by 1e9, 1.0 = 1_000_000_000)
+    pub fn get_compound_factor(env: Env, utilization: i128, time_elapsed: u64) -> i128 {
+        let seconds_per_year = Self::get_seconds_per_year(env.clone());
+        let annual_rate = Self::get_borrow_rate(env.clone(), utilization);
+
+        let t = time_elapsed as i128;
+        if t == 0 {
+            return INDEX_SCALE;
+        }
+
+        // Work at 1e18 precision: per-second rates are tiny
+        let x = mul_div_floor(&env, annual_rate, t * (COMPOUND_PRECISION / SCALE), seconds_per_year);
+
+        // `steps` equal intervals of `step_t` seconds, plus the leftover seconds
+        let steps = ((x - 1) / MAX_COMPOUND_STEP + 1).clamp(1, t);
+        let step_t = t / steps;
+        let rest_t = t - step_t * steps;
+
+        let step = Self::compound_step(&env, mul_div_floor(&env, x, step_t, t), step_t);
+        let mut factor = Self::compound_pow(&env, step, steps);
+        if rest_t > 0 {
+            let rest = Self::compound_step(&env, mul_div_floor(&env, x, rest_t, t), rest_t);
+            factor = mul_div_floor(&env, factor, rest, COMPOUND_PRECISION);
+        }
+
+        factor / (COMPOUND_PRECISION / INDEX_SCALE)
+    }
+
+    /// Internal: One expansion step of `get_compound_factor` for
+    /// `x = rate_per_second * t` over `t` seconds (at 1e18 precision)
+    fn compound_step(env: &Env, x: i128, t: i128) -> i128 {
+        let x2 = mul_div_floor(env, x, x, COMPOUND_PRECISION);
+        let second = mul_div_floor(env, x2, t - 1, 2 * t);
+        if t < 2 {
+            return COMPOUND_PRECISION + x + second;
+        }
+        let x3 = mul_div_floor(env, x2, x, COMPOUND_PRECISION);
+        let third = mul_div_floor(env, mul_div_floor(env, x3, t - 1, t), t - 2, 6 * t);
+
+        COMPOUND_PRECISION + x + second + third
+    }
+
+    /// Internal: `factor^n` at 1e18 precision, by repeated squaring
+    fn compound_pow(env: &Env, factor: i128, n: i128) -> i128 {
+        let mut result = COMPOUND_PRECISION;
+        let mut base = factor;
+        let mut n = n;
+        while n > 0 {
+            if n & 1 == 1 {
+                result = mul_div_floor(env, result, base, COMPOUND_PRECISION);
+            }
+            n >>= 1;
+            if n > 0 {
+                base = mul_div_floor(env, base, base, COMPOUND_PRECISION);
+            }
+        }
+        result
     }
 
     /// Get the annualized supply rate based on utilization
     ///
     /// Supply rate = Borrow rate × Utilization × (1 - Reserve Factor)
-    /// For MVP, we assume reserve_factor = 10%
     ///
     /// # Arguments
     /// * `utilization` - Current utilization rate (scaled by 1e7)
+    /// * `reserve_factor` - Share of interest kept as reserves (scaled by 1e7)
     ///
     /// # Returns
     /// Annualized supply rate (scaled by 1e7)
-    pub fn get_supply_rate(env: Env, utilization: i128) -> i128 {
+    pub fn get_supply_rate(env: Env, utilization: i128, reserve_factor: i128) -> i128 {
+        if !(0..=SCALE).contains(&reserve_factor) {
+            panic_with_error!(&env, RateModelError::InvalidReserveFactor);
+        }
         let borrow_rate = Self::get_borrow_rate(env, utilization);
-        // Supply rate = borrow_rate * utilization * 90% (10% to reserves)
-        (borrow_rate * utilization * 9) / (SCALE * 10)
+        (borrow_rate * utilization * (SCALE - reserve_factor)) / (SCALE * SCALE)
+    }
+
+    /// Get the annualized supply rate assuming the default 10% reserve factor
+    pub fn get_supply_rate_default(env: Env, utilization: i128) -> i128 {
+        Self::get_supply_rate(env, utilization, DEFAULT_RESERVE_FACTOR)
     }
 
     /// Get the supply rate per second
-    pub fn get_supply_rate_per_second(env: Env, utilization: i128) -> i128 {
-        let annual_rate = Self::get_supply_rate(env, utilization);
-        annual_rate / SECONDS_PER_YEAR
+    pub fn get_supply_rate_per_second(env: Env, utilization: i128, reserve_factor: i128) -> i128 {
+        let seconds_per_year = Self::get_seconds_per_year(env.clone());
+        let annual_rate = Self::get_supply_rate(env, utilization, reserve_factor);
+        annual_rate / seconds_per_year
     }
 
     // ========================================================================
@@ -345,6 +696,11 @@ impl InterestRateModel {
         env.storage().instance().get(&DataKey::OptimalUtilization).unwrap_or(8_000_000)
     }
 
+    /// Get the length of the accrual year in seconds
+    pub fn get_seconds_per_year(env: Env) -> i128 {
+        env.storage().instance().get(&DataKey::SecondsPerYear).unwrap_or(DEFAULT_SECONDS_PER_YEAR)
+    }
+
     // ========================================================================
     // UTILITY FUNCTIONS
     // ========================================================================
@@ -397,7 +753,10 @@ impl InterestRateModel {
 #[cfg(test)]
 mod test {
     use super::*;
-    use soroban_sdk::Env;
+    use soroban_sdk::{
+        testutils::{storage::Persistent as _, Address as _, Ledger, MockAuth, MockAuthInvoke},
+        Env, IntoVal,
+    };
 
     #[test]
     fn test_initialize() {
@@ -406,12 +765,46 @@ mod test {
         let client = InterestRateModelClient::new(&env, &contract_id);
 
         // R_min=0%, R_opt=4%, R_max=100%, U*=80%
-        client.initialize(&0, &400_000, &10_000_000, &8_000_000);
+        client.initialize(&Address::generate(&env), &0, &400_000, &10_000_000, &8_000_000, &31_557_600);
 
         assert_eq!(client.get_rate_min(), 0);
         assert_eq!(client.get_rate_opt(), 400_000);
         assert_eq!(client.get_rate_max(), 10_000_000);
         assert_eq!(client.get_optimal_utilization(), 8_000_000);
+        assert_eq!(client.get_seconds_per_year(), 31_557_600);
+    }
+
+    #[test]
+    fn test_seconds_per_year_sets_per_second_rate() {
+        let env = Env::default();
+        let calendar = InterestRateModelClient::new(&env, &env.register_contract(None, InterestRateModel));
+        let banking = InterestRateModelClient::new(&env, &env.register_contract(None, InterestRateModel));
+
+        calendar.initialize_default(&Address::generate(&env));
+        banking.initialize(&Address::generate(&env), &0, &400_000, &10_000_000, &8_000_000, &31_104_000);
+        assert_eq!(banking.get_seconds_per_year(), 31_104_000);
+
+        // Same annual curve, shorter year: each second carries more interest
+        // (visible at the compound factor's precision; per-second rates at
+        // 1e7 truncate to a few units)
+        let utilization = 9_000_000;
+        assert_eq!(calendar.get_borrow_rate(&utilization), banking.get_borrow_rate(&utilization));
+        let annual = banking.get_borrow_rate(&utilization);
+        assert_eq!(calendar.get_borrow_rate_per_second(&utilization), annual / 31_557_600);
+        assert_eq!(banking.get_borrow_rate_per_second(&utilization), annual / 31_104_000);
+        assert!(banking.get_compound_factor(&utilization, &86_400) > calendar.get_compound_factor(&utilization, &86_400));
+    }
+
+    #[test]
+    fn test_initialize_rejects_non_positive_seconds_per_year() {
+        let env = Env::default();
+        let client = InterestRateModelClient::new(&env, &env.register_contract(None, InterestRateModel));
+
+        let result = client.try_initialize(&Address::generate(&env), &0, &400_000, &10_000_000, &8_000_000, &0);
+        assert_eq!(
+            result,
+            Err(Ok(soroban_sdk::Error::from_contract_error(RateModelError::InvalidSecondsPerYear as u32)))
+        );
     }
 
     #[test]
@@ -420,7 +813,7 @@ mod test {
         let contract_id = env.register_contract(None, InterestRateModel);
         let client = InterestRateModelClient::new(&env, &contract_id);
 
-        client.initialize_default();
+        client.initialize_default(&Address::generate(&env));
 
         assert_eq!(client.get_rate_min(), 0);
         assert_eq!(client.get_rate_opt(), 400_000);      // 4%
@@ -428,12 +821,72 @@ mod test {
         assert_eq!(client.get_optimal_utilization(), 8_000_000); // 80%
     }
 
+    #[test]
+    fn test_update_parameters() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, InterestRateModel);
+        let client = InterestRateModelClient::new(&env, &contract_id);
+        client.initialize_default(&Address::generate(&env));
+
+        // Steepen the curve: R_opt 8%, R_max 150%, U* 70%
+        client.update_parameters(&100_000, &800_000, &15_000_000, &7_000_000);
+        assert_eq!(client.get_parameters(), (100_000, 800_000, 15_000_000, 7_000_000));
+        assert_eq!(client.get_borrow_rate(&7_000_000), 800_000);
+    }
+
+    #[test]
+    #[should_panic(expected = "Error(Contract, #3)")]
+    fn test_update_parameters_rejects_invalid_ordering() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, InterestRateModel);
+        let client = InterestRateModelClient::new(&env, &contract_id);
+        client.initialize_default(&Address::generate(&env));
+
+        client.update_parameters(&0, &400_000, &300_000, &8_000_000);
+    }
+
+    #[test]
+    fn test_non_admin_cannot_update_parameters() {
+        let env = Env::default();
+        let contract_id = env.register_contract(None, InterestRateModel);
+        let client = InterestRateModelClient::new(&env, &contract_id);
+        let admin = Address::generate(&env);
+        let attacker = Address::generate(&env);
+        client.initialize_default(&admin);
+        assert_eq!(client.get_admin(), admin);
+
+        let args = (0i128, 10_000_000i128, 10_000_000i128, 1_000_000i128);
+        env.mock_auths(&[MockAuth {
+            address: &attacker,
+            invoke: &MockAuthInvoke {
+                contract: &contract_id,
+                fn_name: "update_parameters",
+                args: args.into_val(&env),
+                sub_invokes: &[],
+            },
+        }]);
+        assert!(client
+            .try_update_parameters(&args.0, &args.1, &args.2, &args.3)
+            .is_err());
+        assert_eq!(client.get_rate_opt(), 400_000);
+
+        // After handing over the role, the new admin can retune the curve
+        let new_admin = Address::generate(&env);
+        env.mock_all_auths();
+        client.set_admin(&new_admin);
+        assert_eq!(client.get_admin(), new_admin);
+        client.update_parameters(&args.0, &args.1, &args.2, &args.3);
+        assert_eq!(client.get_rate_opt(), 10_000_000);
+    }
+
     #[test]
     fn test_rate_at_zero_utilization() {
         let env = Env::default();
         let contract_id = env.register_contract(None, InterestRateModel);
         let client = InterestRateModelClient::new(&env, &contract_id);
-        client.initialize_default();
+        client.initialize_default(&Address::generate(&env));
 
         // At 0% utilization, rate should be 0
         let rate = client.get_borrow_rate(&0);
@@ -445,7 +898,7 @@ mod test {
         let env = Env::default();
         let contract_id = env.register_contract(None, InterestRateModel);
         let client = InterestRateModelClient::new(&env, &contract_id);
-        client.initialize_default();
+        client.initialize_default(&Address::generate(&env));
 
         // At 80% utilization (optimal), rate should be R_opt = 4%
         let rate = client.get_borrow_rate(&8_000_000);
@@ -457,7 +910,7 @@ mod test {
         let env = Env::default();
         let contract_id = env.register_contract(None, InterestRateModel);
         let client = InterestRateModelClient::new(&env, &contract_id);
-        client.initialize_default();
+        client.initialize_default(&Address::generate(&env));
 
         // At 40% utilization (half of optimal)
         // Rate = R_opt * (40% / 80%) = 4% * 0.5 = 2%
@@ -475,7 +928,7 @@ mod test {
         let env = Env::default();
         let contract_id = env.register_contract(None, InterestRateModel);
         let client = InterestRateModelClient::new(&env, &contract_id);
-        client.initialize_default();
+        client.initialize_default(&Address::generate(&env));
 
         // At 85% utilization (end of zone 2)
         // Should be R_opt + 5% of ΔR = 4% + 5% * 96% = 4% + 4.8% = 8.8%
@@ -490,7 +943,7 @@ mod test {
         let env = Env::default();
         let contract_id = env.register_contract(None, InterestRateModel);
         let client = InterestRateModelClient::new(&env, &contract_id);
-        client.initialize_default();
+        client.initialize_default(&Address::generate(&env));
 
         // At 90% utilization (end of zone 3)
         // R_opt + (5% + 10%) of ΔR = 4% + 15% * 96% = 4% + 14.4% = 18.4%
@@ -503,7 +956,7 @@ mod test {
         let env = Env::default();
         let contract_id = env.register_contract(None, InterestRateModel);
         let client = InterestRateModelClient::new(&env, &contract_id);
-        client.initialize_default();
+        client.initialize_default(&Address::generate(&env));
 
         // At 95% utilization (end of zone 4)
         // R_opt + (5% + 10% + 15%) of ΔR = 4% + 30% * 96% = 4% + 28.8% = 32.8%
@@ -516,7 +969,7 @@ mod test {
         let env = Env::default();
         let contract_id = env.register_contract(None, InterestRateModel);
         let client = InterestRateModelClient::new(&env, &contract_id);
-        client.initialize_default();
+        client.initialize_default(&Address::generate(&env));
 
         // At 99% utilization (end of zone 5)
         // R_opt + (5% + 10% + 15% + 20%) of ΔR = 4% + 50% * 96% = 4% + 48% = 52%
@@ -529,7 +982,7 @@ mod test {
         let env = Env::default();
         let contract_id = env.register_contract(None, InterestRateModel);
         let client = InterestRateModelClient::new(&env, &contract_id);
-        client.initialize_default();
+        client.initialize_default(&Address::generate(&env));
 
         // At 100% utilization (max)
         // R_opt + 100% of ΔR = 4% + 96% = 100%
@@ -542,12 +995,198 @@ mod test {
         let env = Env::default();
         let contract_id = env.register_contract(None, InterestRateModel);
         let client = InterestRateModelClient::new(&env, &contract_id);
-        client.initialize_default();
+        client.initialize_default(&Address::generate(&env));
 
         // At 80% utilization, borrow rate = 4%
         // Supply rate = 4% * 80% * 90% = 2.88%
-        let supply_rate = client.get_supply_rate(&8_000_000);
+        let supply_rate = client.get_supply_rate_default(&8_000_000);
         assert_eq!(supply_rate, 288_000); // 2.88%
+        assert_eq!(client.get_supply_rate(&8_000_000, &1_000_000), supply_rate);
+    }
+
+    #[test]
+    fn test_higher_reserve_factor_lowers_supply_rate() {
+        let env = Env::default();
+        let contract_id = env.register_contract(None, InterestRateModel);
+        let client = InterestRateModelClient::new(&env, &contract_id);
+        client.initialize_default(&Address::generate(&env));
+
+        for utilization in [2_000_000i128, 8_000_000, 9_500_000] {
+            let at_10_percent = client.get_supply_rate(&utilization, &1_000_000);
+            let at_20_percent = client.get_supply_rate(&utilization, &2_000_000);
+            assert!(at_20_percent < at_10_percent);
+        }
+
+        // At 80% utilization, borrow rate = 4%
+        // Supply rate = 4% * 80% * 80% = 2.56%
+        assert_eq!(client.get_supply_rate(&8_000_000, &2_000_000), 256_000);
+    }
+
+    #[test]
+    fn test_supply_rate_rejects_invalid_reserve_factor() {
+        let env = Env::default();
+        let contract_id = env.register_contract(None, InterestRateModel);
+        let client = InterestRateModelClient::new(&env, &contract_id);
+        client.initialize_default(&Address::generate(&env));
+
+        assert!(client.try_get_supply_rate(&8_000_000, &(SCALE + 1)).is_err());
+        assert!(client.try_get_supply_rate(&8_000_000, &-1).is_err());
+    }
+
+    #[test]
+    fn test_smoothed_rate_lags_utilization_jump() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, InterestRateModel);
+        let client = InterestRateModelClient::new(&env, &contract_id);
+        client.initialize_default(&Address::generate(&env));
+        client.set_max_rate_change(&1); // 1 bps per second
+        assert_eq!(client.get_max_rate_change(), 1);
+        let pool = Address::generate(&env);
+        let usdc = symbol_short!("USDC");
+
+        // First reading starts at the curve: 4% at 80%
+        assert_eq!(client.update_borrow_rate_smoothed(&pool, &usdc, &8_000_000), 400_000);
+        assert_eq!(env.auths()[0].0, pool);
+
+        // Utilization jumps to 95% (32.8% on the curve) in the same second
+        assert_eq!(client.get_borrow_rate(&9_500_000), 3_280_000);
+        assert_eq!(client.update_borrow_rate_smoothed(&pool, &usdc, &9_500_000), 400_000);
+
+        // 100 seconds later the rate has moved at most 100 bps; reading it
+        // doesn't record anything
+        env.ledger().with_mut(|li| li.timestamp += 100);
+        assert_eq!(client.get_borrow_rate_smoothed(&pool, &usdc, &9_500_000), 500_000);
+        env.ledger().with_mut(|li| li.timestamp += 100);
+        assert_eq!(client.get_borrow_rate_smoothed(&pool, &usdc, &9_500_000), 600_000);
+        assert_eq!(client.update_borrow_rate_smoothed(&pool, &usdc, &9_500_000), 600_000);
+
+        // Given enough time it converges and stays on the curve
+        env.ledger().with_mut(|li| li.timestamp += 3_000);
+        assert_eq!(client.update_borrow_rate_smoothed(&pool, &usdc, &9_500_000), 3_280_000);
+        env.ledger().with_mut(|li| li.timestamp += 10);
+        assert_eq!(client.update_borrow_rate_smoothed(&pool, &usdc, &9_500_000), 3_280_000);
+
+        // Falling rates are limited the same way
+        env.ledger().with_mut(|li| li.timestamp += 60);
+        assert_eq!(client.update_borrow_rate_smoothed(&pool, &usdc, &8_000_000), 3_220_000);
+    }
+
+    #[test]
+    fn test_smoothed_rate_state_is_per_pool_and_market() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, InterestRateModel);
+        let client = InterestRateModelClient::new(&env, &contract_id);
+        client.initialize_default(&Address::generate(&env));
+        client.set_max_rate_change(&1);
+        let pool = Address::generate(&env);
+        let other_pool = Address::generate(&env);
+        let usdc = symbol_short!("USDC");
+        let xlm = symbol_short!("XLM");
+
+        client.update_borrow_rate_smoothed(&pool, &usdc, &8_000_000);
+        env.ledger().with_mut(|li| li.timestamp += 100);
+
+        // Another market or pool starts from its own curve rate
+        assert_eq!(client.get_borrow_rate_smoothed(&pool, &usdc, &9_500_000), 500_000);
+        assert_eq!(client.get_borrow_rate_smoothed(&pool, &xlm, &9_500_000), 3_280_000);
+        assert_eq!(client.update_borrow_rate_smoothed(&other_pool, &usdc, &9_500_000), 3_280_000);
+        assert_eq!(client.get_borrow_rate_smoothed(&pool, &usdc, &9_500_000), 500_000);
+
+        // Per-pool state never touches the shared instance entry
+        env.as_contract(&contract_id, || {
+            let key = DataKey::LastRate(pool.clone(), usdc.clone());
+            assert!(env.storage().persistent().has(&key));
+            assert!(!env.storage().instance().has(&key));
+            assert!(env.storage().persistent().get_ttl(&key) >= SMOOTHING_TTL_THRESHOLD);
+        });
+    }
+
+    #[test]
+    fn test_smoothed_rate_update_requires_pool_auth() {
+        let env = Env::default();
+        let contract_id = env.register_contract(None, InterestRateModel);
+        let client = InterestRateModelClient::new(&env, &contract_id);
+        client.initialize_default(&Address::generate(&env));
+        let pool = Address::generate(&env);
+
+        assert!(client
+            .try_update_borrow_rate_smoothed(&pool, &symbol_short!("USDC"), &8_000_000)
+            .is_err());
+    }
+
+    #[test]
+    fn test_smoothed_rate_without_limit_follows_curve() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, InterestRateModel);
+        let client = InterestRateModelClient::new(&env, &contract_id);
+        client.initialize_default(&Address::generate(&env));
+        let pool = Address::generate(&env);
+        let usdc = symbol_short!("USDC");
+
+        assert_eq!(client.update_borrow_rate_smoothed(&pool, &usdc, &8_000_000), 400_000);
+        assert_eq!(client.update_borrow_rate_smoothed(&pool, &usdc, &9_500_000), 3_280_000);
+    }
+
+    #[test]
+    fn test_get_rate_curve() {
+        let env = Env::default();
+        let contract_id = env.register_contract(None, InterestRateModel);
+        let client = InterestRateModelClient::new(&env, &contract_id);
+        client.initialize_default(&Address::generate(&env));
+
+        let curve = client.get_rate_curve(&21);
+        assert_eq!(curve.len(), 21);
+        assert_eq!(curve.get(0).unwrap(), (0, client.get_borrow_rate(&0)));
+        assert_eq!(curve.get(20).unwrap(), (SCALE, client.get_borrow_rate(&SCALE)));
+
+        for i in 1..curve.len() {
+            assert!(curve.get(i).unwrap().1 >= curve.get(i - 1).unwrap().1);
+        }
+
+        assert!(client.try_get_rate_curve(&101).is_err());
+    }
+
+    #[test]
+    fn test_compound_factor() {
+        let env = Env::default();
+        let contract_id = env.register_contract(None, InterestRateModel);
+        let client = InterestRateModelClient::new(&env, &contract_id);
+        client.initialize_default(&Address::generate(&env));
+
+        // No time, no growth
+        assert_eq!(client.get_compound_factor(&8_000_000, &0), 1_000_000_000);
+
+        // 4% APR for a year: simple 1.04, compound ~e^0.04 = 1.0408108
+        let factor = client.get_compound_factor(&8_000_000, &31_557_600);
+        assert!(factor > 1_040_000_000);
+        assert_eq!(factor, 1_040_810_666);
+    }
+
+    #[test]
+    fn test_compound_factor_multi_year_gap_at_rate_max() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, InterestRateModel);
+        let client = InterestRateModelClient::new(&env, &contract_id);
+        client.initialize_default(&Address::generate(&env));
+
+        // 100% APR for six untouched years: x = 6, ~e^6 = 403.43
+        let factor = client.get_compound_factor(&SCALE, &(6 * 31_557_600));
+        assert!((403_000_000_000..=403_428_794_000).contains(&factor));
+
+        // A steep retune only shortens the horizon, it can't trap: 1000% APR for a year
+        client.update_parameters(&0, &400_000, &(10 * SCALE), &8_000_000);
+        let factor = client.get_compound_factor(&SCALE, &31_557_600);
+        assert!((22_000_000_000_000..=22_026_466_000_000).contains(&factor));
+
+        // Growth too large for an i128 is a named error
+        assert_eq!(
+            client.try_get_compound_factor(&SCALE, &(10 * 31_557_600)),
+            Err(Ok(soroban_sdk::Error::from_contract_error(RateModelError::MathOverflow as u32)))
+        );
     }
 
     #[test]
@@ -570,7 +1209,7 @@ mod test {
         let env = Env::default();
         let contract_id = env.register_contract(None, InterestRateModel);
         let client = InterestRateModelClient::new(&env, &contract_id);
-        client.initialize_default();
+        client.initialize_default(&Address::generate(&env));
 
         // Verify rate always increases with utilization
         let mut prev_rate: i128 = 0;
@@ -0,0 +1,72 @@
+//! Overflow-safe fixed-point helper for the compound factor
+//!
+//! Mirrors the pool's `math` module: `a * b / denominator` is computed at
+//! 256-bit width when the product doesn't fit in an i128, so only a result
+//! that is itself out of range panics, and then with
+//! `RateModelError::MathOverflow` instead of a trap.
+
+use soroban_sdk::{panic_with_error, Env};
+
+use crate::RateModelError;
+
+const LOW_MASK: u128 = u64::MAX as u128;
+
+/// `a * b / denominator` for non-negative inputs, rounded down
+pub(crate) fn mul_div_floor(env: &Env, a: i128, b: i128, denominator: i128) -> i128 {
+    if a < 0 || b < 0 || denominator <= 0 {
+        panic_with_error!(env, RateModelError::MathOverflow);
+    }
+
+    // Fast path: the product fits
+    if let Some(product) = a.checked_mul(b) {
+        return product / denominator;
+    }
+
+    let (high, low) = wide_mul(a as u128, b as u128);
+    match wide_div(high, low, denominator as u128) {
+        Some(quotient) if quotient <= i128::MAX as u128 => quotient as i128,
+        _ => panic_with_error!(env, RateModelError::MathOverflow),
+    }
+}
+
+/// Full 256-bit product of two u128s as `(high, low)` halves
+fn wide_mul(a: u128, b: u128) -> (u128, u128) {
+    let (a_high, a_low) = (a >> 64, a & LOW_MASK);
+    let (b_high, b_low) = (b >> 64, b & LOW_MASK);
+
+    let low_low = a_low * b_low;
+    let low_high = a_low * b_high;
+    let high_low = a_high * b_low;
+    let high_high = a_high * b_high;
+
+    // Middle 64-bit column plus the carry out of the lowest one
+    let middle = (low_low >> 64) + (low_high & LOW_MASK) + (high_low & LOW_MASK);
+
+    let low = (low_low & LOW_MASK) | (middle << 64);
+    let high = high_high + (low_high >> 64) + (high_low >> 64) + (middle >> 64);
+    (high, low)
+}
+
+/// Divide a 256-bit `(high, low)` value by a u128, or `None` if the
+/// quotient needs more than 128 bits
+fn wide_div(high: u128, low: u128, divisor: u128) -> Option<u128> {
+    if high >= divisor {
+        return None;
+    }
+
+    // Schoolbook long division, one bit of `low` at a time. The running
+    // remainder stays below `divisor`; `carry` catches the bit shifted out.
+    let mut remainder = high;
+    let mut quotient: u128 = 0;
+    for bit in (0..128).rev() {
+        let carry = remainder >> 127;
+        remainder = (remainder << 1) | ((low >> bit) & 1);
+        quotient <<= 1;
+        if carry == 1 || remainder >= divisor {
+            remainder = remainder.wrapping_sub(divisor);
+            quotient |= 1;
+        }
+    }
+
+    Some(quotient)
+}
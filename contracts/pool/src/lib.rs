@@ -1,9 +1,13 @@
 #![no_std]
 
 use soroban_sdk::{
-    contract, contractimpl, contracttype, symbol_short, token, Address, Env, Symbol,
+    contract, contractclient, contracterror, contractimpl, contracttype, panic_with_error,
+    symbol_short, token, Address, Env, IntoVal, Symbol, Val, Vec,
 };
 
+mod math;
+use math::{mul_div_ceil, mul_div_floor};
+
 // ============================================================================
 // CONSTANTS
 // ============================================================================
@@ -16,11 +20,43 @@ const SCALE: i128 = 10_000_000;
 /// Scaled by 1e9 for precision
 const INITIAL_EXCHANGE_RATE: i128 = 1_000_000_000;
 
-/// Liquidation parameters
+/// Default liquidation parameters (configurable per collateral asset)
 /// Close factor: Maximum portion of debt that can be liquidated (50%)
-const CLOSE_FACTOR: i128 = 5_000_000; // 50% (scaled by SCALE)
+const DEFAULT_CLOSE_FACTOR: i128 = 5_000_000; // 50% (scaled by SCALE)
 /// Liquidation bonus: Extra collateral given to liquidator (5%)
-const LIQUIDATION_BONUS: i128 = 500_000; // 5% (scaled by SCALE)
+const DEFAULT_LIQUIDATION_BONUS: i128 = 500_000; // 5% (scaled by SCALE)
+/// Upper bound for the liquidation bonus (50%)
+const MAX_LIQUIDATION_BONUS: i128 = 5_000_000;
+/// Protocol fee: share of the liquidation bonus kept as reserves (10%)
+const DEFAULT_LIQUIDATION_PROTOCOL_FEE: i128 = 1_000_000; // 10% (scaled by SCALE)
+
+/// Token decimals assumed for markets listed without a stored value
+/// (XLM and USDC Stellar asset contracts use 7)
+const DEFAULT_TOKEN_DECIMALS: u32 = 7;
+/// Upper bound for a listed token's decimals
+const MAX_TOKEN_DECIMALS: u32 = 18;
+
+/// Seconds per year used to convert annual rates (365.25 days) when the
+/// pool's built-in curve is active; the rate model supplies its own otherwise
+const SECONDS_PER_YEAR: i128 = 31_557_600;
+
+/// Internal precision for compound interest (1e18)
+const COMPOUND_PRECISION: i128 = 1_000_000_000_000_000_000;
+/// Largest `rate_per_second * t` compounded by one expansion step (4%, at
+/// COMPOUND_PRECISION); longer intervals are split into equal steps
+const MAX_COMPOUND_STEP: i128 = COMPOUND_PRECISION / 25;
+
+/// Default flash loan fee: 0.09% (9 bps, scaled by SCALE)
+const DEFAULT_FLASH_LOAN_FEE: i128 = 9_000;
+
+/// Default delay before a queued parameter change can execute (48 hours)
+const DEFAULT_TIMELOCK_DELAY: u64 = 172_800;
+/// Upper bound for the timelock delay (30 days)
+const MAX_TIMELOCK_DELAY: u64 = 2_592_000;
+
+/// Schema version of the operation event payloads, published as the
+/// second topic so indexers can tell payload layouts apart
+pub const EVENT_VERSION: u32 = 5;
 
 /// Asset symbols
 const XLM: Symbol = symbol_short!("XLM");
@@ -31,14 +67,25 @@ const USDC: Symbol = symbol_short!("USDC");
 // ============================================================================
 
 /// Storage keys for the lending pool
+/// 
+/// Not exported to the contract spec: keys are internal, and the spec caps
+/// union types at 50 cases.
 #[derive(Clone)]
-#[contracttype]
+#[contracttype(export = false)]
 pub enum DataKey {
     // ========== CONFIGURATION ==========
-    /// Admin address
+    /// Admin address (owner: external contracts, treasury, roles)
     Admin,
+    /// Admin proposed via `propose_admin`, pending acceptance
+    PendingAdmin,
+    /// Risk admin address (risk parameters, caps, market freezes)
+    RiskAdmin,
+    /// Treasury address that owns protocol reserves and fees
+    Treasury,
     /// Token contract address for an asset
     TokenAddress(Symbol),
+    /// Token decimals for an asset (absent = `DEFAULT_TOKEN_DECIMALS`)
+    Decimals(Symbol),
     /// Price oracle contract address
     PriceOracle,
     /// Interest rate model contract address
@@ -51,13 +98,67 @@ pub enum DataKey {
     CollateralEnabled(Symbol),
     /// Whether an asset is enabled for borrowing
     BorrowEnabled(Symbol),
+    /// Whether a market is frozen (no new supply, collateral or borrows)
+    Frozen(Symbol),
+    /// Close factor per collateral asset (scaled by SCALE, 50% = 5_000_000)
+    CloseFactor(Symbol),
+    /// Liquidation bonus per collateral asset (scaled by SCALE, 5% = 500_000)
+    LiquidationBonus(Symbol),
+    /// Share of the liquidation bonus kept by the protocol per collateral asset (scaled by SCALE, 10% = 1_000_000)
+    LiquidationProtocolFee(Symbol),
+    /// Maximum total supply per asset (0 = uncapped)
+    SupplyCap(Symbol),
+    /// Maximum total borrows per asset (0 = uncapped)
+    BorrowCap(Symbol),
+    /// Minimum debt position per asset (0 = no minimum)
+    MinBorrow(Symbol),
+    /// Default per-user debt limit per asset (0 = unlimited)
+    DefaultBorrowLimit(Symbol),
+    /// Whether a collateral asset is isolated (cannot be combined with other collateral)
+    Isolated(Symbol),
+    /// Max USD debt (scaled by SCALE) for users backed solely by an isolated asset
+    IsolationDebtCeiling(Symbol),
+    /// Flash loan fee (scaled by SCALE, 0.09% = 9_000)
+    FlashLoanFee,
+    /// Whether stale oracle prices are rejected and the fallback is never used
+    StrictOracle,
+    /// Maximum accepted price age in seconds in strict mode (0 = oracle's own check only)
+    PoolMaxPriceAge,
+    /// Whether a stale price of this asset also blocks liquidations in strict mode
+    StaleBlocksLiquidation(Symbol),
+    /// Whether `FallbackPrice` may stand in for a missing oracle price (local testing)
+    AllowFallback,
+    /// Price used for an asset the oracle has no price for, if `AllowFallback` is on
+    FallbackPrice(Symbol),
+    /// Whether interest compounds per second instead of accruing linearly
+    CompoundInterest,
+    /// Whether borrow rates come from the external Interest Rate Model
+    UseRateModel,
+    /// Whether liquidations are capped at the repay that restores HF to 1.0
+    TargetedLiquidation,
+    /// Health factor a liquidation may restore at most (scaled by SCALE,
+    /// 0 = uncapped)
+    LiquidationTargetHf,
+    /// LTV headroom kept by `deposit_and_borrow`'s max borrow (scaled by SCALE)
+    ZapSafetyBuffer,
+    /// Whether only whitelisted users may open new positions
+    PermissionedMode,
+    /// Delay (seconds) before a queued parameter change can execute
+    TimelockDelay,
+    /// Id assigned to the next queued parameter change
+    NextParamChangeId,
+    /// Queued parameter change by id
+    QueuedParamChange(u32),
+    /// Symbols of all listed markets, in listing order
+    MarketList,
 
     // ========== POOL STATE (per asset) ==========
-    /// Total underlying supplied to the pool
+    /// Suppliers' claim on the pool: principal plus supplier interest
+    /// (idle cash + total borrows - reserves)
     TotalSupply(Symbol),
     /// Total sToken shares minted
     TotalShares(Symbol),
-    /// Total borrowed from the pool
+    /// Total outstanding borrows, including accrued interest
     TotalBorrow(Symbol),
     /// Exchange rate: underlying per sToken (scaled by 1e9)
     ExchangeRate(Symbol),
@@ -69,6 +170,22 @@ pub enum DataKey {
     ReserveFactor(Symbol),
     /// Total reserves accumulated
     TotalReserves(Symbol),
+    /// Total collateral deposited (in underlying units)
+    TotalCollateral(Symbol),
+    /// Supply-side tokens held by the pool: supplier liquidity plus
+    /// reserves, excluding collateral and tokens sent outside the pool's API
+    Cash(Symbol),
+    /// Debt written off after liquidations exhausted collateral, not yet
+    /// covered by reserves
+    BadDebt(Symbol),
+    /// Cumulative bad debt covered by reserves
+    BadDebtCovered(Symbol),
+    /// Rounding remainder of the supplier interest split (scaled by SCALE),
+    /// carried forward so suppliers receive their exact share over time
+    AccrualDust(Symbol),
+    /// Stable-rate debt principal as of each borrower's last snapshot; it
+    /// is excluded from borrow index growth
+    TotalStableBorrow(Symbol),
 
     // ========== USER STATE ==========
     /// User's sToken share balance per asset
@@ -79,6 +196,232 @@ pub enum DataKey {
     UserDebt(Address, Symbol),
     /// User's borrow index at time of last borrow (for interest calculation)
     UserBorrowIndex(Address, Symbol),
+    /// Fixed annual rate of a stable-rate borrow (scaled by 1e7, absent = variable)
+    UserStableRate(Address, Symbol),
+    /// Timestamp a stable-rate debt principal was last written
+    UserStableTime(Address, Symbol),
+    /// Per-user debt limit override per asset (0 = unlimited)
+    UserBorrowLimit(Address, Symbol),
+    /// Whether a user's deposited collateral of an asset counts (unset = true)
+    UseAsCollateral(Address, Symbol),
+    /// Whether a user's supplied sTokens of an asset count as collateral
+    /// and can be seized by `liquidate_shares` (unset = false)
+    SharesAsCollateral(Address, Symbol),
+    /// Whether a user may use the pool in permissioned mode
+    Whitelisted(Address),
+    /// Whether an operator may manage an owner's positions (owner, operator)
+    Operator(Address, Address),
+    /// Whether a referral code is registered and active
+    ReferralCode(u32),
+    /// Cumulative supply and borrow volume attributed to a referral code per asset
+    ReferralVolume(u32, Symbol),
+    /// Number of liquidations executed against a borrower
+    LiquidationCount(Address),
+    /// Most recent liquidation against a borrower: (timestamp, repaid, seized)
+    LastLiquidation(Address),
+}
+
+/// Errors returned by the lending pool
+///
+/// Codes are part of the contract interface: existing variants are never
+/// renumbered, new ones are appended.
+#[contracterror]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
+#[repr(u32)]
+pub enum PoolError {
+    /// Flash loan principal plus fee was not returned within the call
+    FlashLoanNotRepaid = 1,
+    /// Supply would push the market's total supply above its cap
+    SupplyCapExceeded = 2,
+    /// Borrow would push the market's total borrows above its cap
+    BorrowCapExceeded = 3,
+    /// No queued parameter change with this id
+    ParamChangeNotFound = 4,
+    /// Queued parameter change executed before its eta
+    TimelockNotReady = 5,
+    /// Borrow would leave a debt position below the market's minimum
+    BorrowTooSmall = 6,
+    /// Borrow would push the user's debt above their borrow limit
+    BorrowLimitExceeded = 7,
+    /// User is not whitelisted while the pool is in permissioned mode
+    NotWhitelisted = 8,
+    /// Liquidation would leave the borrower's health factor no better than before
+    LiquidationNoImprovement = 9,
+    /// Borrow would push an isolated-collateral user's debt above the asset's ceiling
+    IsolationCeilingExceeded = 10,
+    /// Isolated collateral cannot be combined with any other collateral
+    IsolatedCollateralMixing = 11,
+    /// An intermediate or final result doesn't fit in an i128
+    MathOverflow = 12,
+    /// A calculation divided by zero
+    DivisionByZero = 13,
+    /// The oracle price is older than the accepted age (strict oracle mode)
+    StalePrice = 14,
+    /// The pool was already initialized
+    AlreadyInitialized = 15,
+    /// An amount, price or target that must be positive was zero or negative
+    AmountNotPositive = 16,
+    /// The user holds fewer sToken shares than requested
+    InsufficientShares = 17,
+    /// The pool doesn't hold enough cash for the withdrawal, borrow or flash loan
+    InsufficientLiquidity = 18,
+    /// The user holds less collateral than requested
+    InsufficientCollateral = 19,
+    /// Borrow would push debt above the collateral's loan-to-value limit
+    LtvExceeded = 20,
+    /// Liquidation target has a health factor of at least 1.0
+    PositionHealthy = 21,
+    /// Action would leave a position with debt below a health factor of 1.0
+    PositionUnhealthy = 22,
+    /// Asset has no market in this pool
+    MarketNotListed = 23,
+    /// Asset already has a market in this pool
+    MarketAlreadyListed = 24,
+    /// Market is frozen and accepts no new exposure
+    MarketFrozen = 25,
+    /// Market must be frozen before it can be removed
+    MarketNotFrozen = 26,
+    /// Market still has supply, debt or collateral
+    MarketNotEmpty = 27,
+    /// Asset is not enabled as collateral
+    CollateralNotEnabled = 28,
+    /// Asset is not enabled for borrowing
+    BorrowNotEnabled = 29,
+    /// User has no debt in the asset to repay or liquidate
+    NoDebt = 30,
+    /// A configuration parameter is out of range
+    InvalidParameter = 31,
+    /// The oracle has no price for the asset
+    PriceUnavailable = 32,
+    /// Caller lacks the required role or operator approval
+    Unauthorized = 33,
+    /// Source and destination account are the same
+    SameAccount = 34,
+    /// Amount is too small to mint any shares
+    AmountTooSmall = 35,
+    /// Batch contains no operations
+    EmptyBatch = 36,
+    /// Withdrawal exceeds the market's reserves
+    InsufficientReserves = 37,
+    /// Repaying with collateral would lower the health factor
+    HealthFactorDecreased = 38,
+    /// Asset has a zero liquidation threshold, so it can't restore health
+    NoHealthContribution = 39,
+    /// Asset is not borrowable, so supplying it as collateral earns nothing
+    NotInterestBearing = 40,
+    /// A variable borrow on a stable-rate position, or vice versa
+    BorrowModeConflict = 41,
+    /// A borrower can't liquidate their own position, nor anyone the protocol's
+    SelfLiquidation = 42,
+    /// Borrower hasn't opted their supplied sTokens of the asset in as collateral
+    SharesNotCollateral = 43,
+}
+
+/// A risk-critical parameter change, mirroring the admin setters
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[contracttype]
+pub enum ParamChange {
+    /// `set_price_oracle(oracle)`
+    PriceOracle(Address),
+    /// `set_interest_rate_model(model)`
+    InterestRateModel(Address),
+    /// `set_collateral_params(asset, ltv, liquidation_threshold)`
+    CollateralParams(Symbol, i128, i128),
+    /// `set_reserve_factor(asset, reserve_factor)`
+    ReserveFactor(Symbol, i128),
+    /// `set_close_factor(asset, close_factor)`
+    CloseFactor(Symbol, i128),
+    /// `set_liquidation_bonus(asset, liquidation_bonus)`
+    LiquidationBonus(Symbol, i128),
+    /// `set_liquidation_protocol_fee(asset, fee)`
+    LiquidationProtocolFee(Symbol, i128),
+    /// `set_supply_cap(asset, cap)`
+    SupplyCap(Symbol, i128),
+    /// `set_borrow_cap(asset, cap)`
+    BorrowCap(Symbol, i128),
+    /// `set_min_borrow(asset, min_borrow)`
+    MinBorrow(Symbol, i128),
+    /// `set_default_borrow_limit(asset, limit)`
+    DefaultBorrowLimit(Symbol, i128),
+    /// `set_user_borrow_limit(user, asset, limit)`
+    UserBorrowLimit(Address, Symbol, i128),
+    /// `set_isolation_mode(asset, isolated, debt_ceiling)`
+    IsolationMode(Symbol, bool, i128),
+    /// `set_strict_oracle(enabled)`
+    StrictOracle(bool),
+    /// `set_pool_max_price_age(max_age)`
+    PoolMaxPriceAge(u64),
+    /// `set_stale_blocks_liquidation(asset, enabled)`
+    StaleBlocksLiquidation(Symbol, bool),
+    /// `set_allow_fallback(enabled)`
+    AllowFallback(bool),
+    /// `set_fallback_price(asset, price)`
+    FallbackPrice(Symbol, i128),
+    /// `set_treasury(treasury)`
+    Treasury(Address),
+    /// `set_flash_loan_fee(fee)`
+    FlashLoanFee(i128),
+    /// `set_targeted_liquidation(enabled)`
+    TargetedLiquidation(bool),
+    /// `set_liquidation_target_hf(target)`
+    LiquidationTargetHf(i128),
+    /// `set_compound_interest(enabled)`
+    CompoundInterest(bool),
+    /// `set_zap_safety_buffer(buffer)`
+    ZapSafetyBuffer(i128),
+    /// Timelock delay in seconds (only changeable through the timelock)
+    TimelockDelay(u64),
+}
+
+/// A parameter change waiting in the timelock
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[contracttype]
+pub struct QueuedParamChange {
+    pub change: ParamChange,
+    /// Earliest timestamp at which the change can execute
+    pub eta: u64,
+}
+
+/// A single step of a `batch` call, executed for the batch's user
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[contracttype]
+pub enum PoolOp {
+    /// `supply(asset, amount)`
+    Supply(Symbol, i128),
+    /// `withdraw(asset, share_amount)`
+    Withdraw(Symbol, i128),
+    /// `deposit_collateral(asset, amount)`
+    DepositCollateral(Symbol, i128),
+    /// `withdraw_collateral(asset, amount)`
+    WithdrawCollateral(Symbol, i128),
+    /// `borrow(asset, amount)`
+    Borrow(Symbol, i128),
+    /// `repay(asset, amount)`
+    Repay(Symbol, i128),
+}
+
+/// Administrative roles
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Role {
+    /// The admin: external contract addresses, treasury, role rotation
+    Owner,
+    /// Tunes risk parameters and freezes markets
+    RiskAdmin,
+}
+
+/// Market accounting projected to the current timestamp (not stored)
+#[derive(Clone)]
+struct AccrualState {
+    total_supply: i128,
+    total_borrow: i128,
+    total_reserves: i128,
+    previous_borrow_index: i128,
+    borrow_index: i128,
+    accrual_dust: i128,
+    interest_accrued: i128,
+    reserve_interest: i128,
+    utilization: i128,
+    borrow_rate: i128,
 }
 
 /// Result struct for user position queries
@@ -88,7 +431,12 @@ pub struct UserPosition {
     pub collateral_value_usd: i128,
     pub debt_value_usd: i128,
     pub available_borrow_usd: i128,
+    /// Only meaningful when `has_debt` is true; `i128::MAX` otherwise
     pub health_factor: i128,
+    /// Collateral weighted by each asset's liquidation threshold
+    pub threshold_collateral_usd: i128,
+    /// Whether the user owes anything
+    pub has_debt: bool,
 }
 
 /// Result struct for market info queries
@@ -103,6 +451,161 @@ pub struct MarketInfo {
     pub borrow_rate: i128,      // Annual borrow APR (scaled by 1e7)
     pub supply_rate: i128,      // Annual supply APY (scaled by 1e7)
     pub ltv_ratio: i128,
+    pub is_frozen: bool,
+    /// Written-off debt suppliers still bear
+    pub bad_debt: i128,
+    /// Bad debt covered by reserves so far
+    pub bad_debt_covered: i128,
+}
+
+/// Result struct for `repay_and_withdraw`
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[contracttype]
+pub struct ExitResult {
+    pub repaid: i128,
+    pub withdrawn: i128,
+}
+
+/// Protocol-wide totals valued in USD (scaled by 1e7)
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[contracttype]
+pub struct ProtocolStats {
+    pub total_supply_usd: i128,
+    pub total_borrow_usd: i128,
+    pub total_reserves_usd: i128,
+    pub total_collateral_usd: i128,
+}
+
+/// Components of a market's accounting invariants, from `check_solvency`
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[contracttype]
+pub struct SolvencyReport {
+    /// Supply-side tokens the pool holds (excludes collateral)
+    pub cash: i128,
+    pub total_supply: i128,
+    pub total_borrow: i128,
+    pub total_reserves: i128,
+    /// What suppliers own: `cash + total_borrow - total_reserves`
+    pub total_underlying: i128,
+    /// `total_reserves <= cash + total_borrow` and `total_underlying >= 0`
+    pub is_solvent: bool,
+}
+
+// ============================================================================
+// EVENTS
+// ============================================================================
+//
+// Operation events are published with topics `(name, EVENT_VERSION)` and one
+// of the structs below as data. Any change to a payload layout bumps
+// `EVENT_VERSION`.
+
+/// Data for `supply` events
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[contracttype]
+pub struct SupplyEvent {
+    /// Account credited with the shares
+    pub user: Address,
+    pub asset: Symbol,
+    pub amount: i128,
+    pub shares: i128,
+    /// The user's share balance after the supply
+    pub new_user_shares: i128,
+    pub new_total_supply: i128,
+}
+
+/// Data for `withdraw` events
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[contracttype]
+pub struct WithdrawEvent {
+    pub user: Address,
+    pub asset: Symbol,
+    pub amount: i128,
+    pub shares: i128,
+    /// The user's share balance after the withdrawal
+    pub new_user_shares: i128,
+    pub new_total_supply: i128,
+}
+
+/// Data for `coll_dep` and `coll_wth` events
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[contracttype]
+pub struct CollateralEvent {
+    pub user: Address,
+    pub asset: Symbol,
+    pub amount: i128,
+    /// The user's collateral balance after the operation
+    pub new_balance: i128,
+}
+
+/// Data for `borrow` events
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[contracttype]
+pub struct BorrowEvent {
+    pub user: Address,
+    pub asset: Symbol,
+    pub amount: i128,
+    pub receiver: Address,
+    /// The user's debt after the borrow, including accrued interest
+    pub new_debt: i128,
+    pub new_total_borrow: i128,
+}
+
+/// Data for `repay` events
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[contracttype]
+pub struct RepayEvent {
+    pub payer: Address,
+    pub borrower: Address,
+    pub asset: Symbol,
+    pub amount: i128,
+    /// The borrower's debt after the repayment, including accrued interest
+    pub remaining_debt: i128,
+    pub new_total_borrow: i128,
+}
+
+/// Data for `liquidate` and `liq_share` events
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[contracttype]
+pub struct LiquidationEvent {
+    pub liquidator: Address,
+    pub borrower: Address,
+    pub repay_asset: Symbol,
+    pub repay_amount: i128,
+    pub collateral_asset: Symbol,
+    /// Collateral seized: underlying for `liquidate`, sToken shares for `liq_share`
+    pub seized: i128,
+    /// Prices the liquidation was sized at (scaled by 1e7)
+    pub repay_price: i128,
+    pub collateral_price: i128,
+    /// Repay-asset debt left uncovered once the borrower's collateral ran
+    /// out, written off to `get_bad_debt` (0 if none). Debt in other
+    /// markets is written off too, with a `bad_debt` event per asset.
+    pub bad_debt: i128,
+    /// The borrower's health factor after the liquidation (scaled by 1e7,
+    /// `i128::MAX` once no debt is left)
+    pub health_factor: i128,
+}
+
+/// Data for `accrue` events
+/// 
+/// Published by `accrue_all` for every market, and otherwise only when an
+/// accrual actually charged interest.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[contracttype]
+pub struct AccrueEvent {
+    pub asset: Symbol,
+    /// Borrow index after the accrual
+    pub borrow_index: i128,
+    /// Annual borrow rate the interest was accrued at
+    pub borrow_rate: i128,
+    /// Annual supply rate after the accrual
+    pub supply_rate: i128,
+    /// Portion of `interest_accrued` added to reserves
+    pub reserves_delta: i128,
+    /// Interest added to total borrows
+    pub interest_accrued: i128,
+    /// Utilization the interest was accrued at (scaled by 1e7)
+    pub utilization: i128,
 }
 
 // ============================================================================
@@ -132,13 +635,45 @@ pub struct LendingPool;
 
 // Oracle contract client for cross-contract calls
 mod oracle_contract {
+    use soroban_sdk::{contractclient, Env, Symbol};
+
+    /// The part of the Price Oracle interface the pool calls
+    #[allow(dead_code)]
+    #[contractclient(name = "Client")]
+    pub trait PriceOracle {
+        fn get_price(env: Env, asset: Symbol) -> i128;
+        fn get_price_fresh(env: Env, asset: Symbol, max_age: u64) -> i128;
+        fn get_last_update(env: Env, asset: Symbol) -> u64;
+        fn is_stale(env: Env, asset: Symbol) -> bool;
+    }
+}
+
+// Interest Rate Model contract client for cross-contract calls
+mod rate_model_contract {
     soroban_sdk::contractimport!(
-        file = "../target/wasm32-unknown-unknown/release/stellend_price_oracle.wasm"
+        file = "../target/wasm32-unknown-unknown/release/stellend_interest_rate_model.wasm"
     );
 }
 
-// Flag to enable/disable oracle calls (for testing without deployed oracle)
-const USE_ORACLE: bool = true; // Oracle is deployed and active
+/// Interface a flash loan receiver contract must implement
+///
+/// The pool transfers `amount` of `token` to the receiver and then invokes
+/// `on_flash_loan`. Before returning, the receiver must transfer
+/// `amount + fee` back to `pool`, otherwise the whole transaction reverts.
+/// `initiator` is the address that authorized the loan; receivers should
+/// reject loans they did not start, since anyone can name them as receiver.
+#[contractclient(name = "FlashLoanReceiverClient")]
+pub trait FlashLoanReceiver {
+    fn on_flash_loan(
+        env: Env,
+        pool: Address,
+        initiator: Address,
+        asset: Symbol,
+        token: Address,
+        amount: i128,
+        fee: i128,
+    );
+}
 
 #[contractimpl]
 impl LendingPool {
@@ -163,13 +698,18 @@ impl LendingPool {
         usdc_token: Address,
     ) {
         if env.storage().instance().has(&DataKey::Admin) {
-            panic!("Already initialized");
+            panic_with_error!(&env, PoolError::AlreadyInitialized);
         }
 
         // Store admin and external contract addresses
+        // The risk admin and treasury default to the admin until set explicitly
         env.storage().instance().set(&DataKey::Admin, &admin);
+        env.storage().instance().set(&DataKey::RiskAdmin, &admin);
+        env.storage().instance().set(&DataKey::Treasury, &admin);
         env.storage().instance().set(&DataKey::PriceOracle, &price_oracle);
         env.storage().instance().set(&DataKey::InterestRateModel, &interest_rate_model);
+        env.storage().instance().set(&DataKey::FlashLoanFee, &DEFAULT_FLASH_LOAN_FEE);
+        env.storage().instance().set(&DataKey::TimelockDelay, &DEFAULT_TIMELOCK_DELAY);
 
         // Store token addresses
         env.storage().instance().set(&DataKey::TokenAddress(XLM), &xlm_token);
@@ -182,12 +722,49 @@ impl LendingPool {
         Self::init_market(&env, USDC, 8_000_000, 8_500_000, true, true); // 80% LTV, 85% liq threshold
     }
 
+    /// Initialize the lending pool with conservative, audited defaults
+    /// 
+    /// Same as `initialize`, but every market starts with a 50% LTV and a
+    /// 60% liquidation threshold, a 10% reserve factor, and strict oracle
+    /// mode (no fallback prices). Loosening any of these requires explicit
+    /// admin calls (`set_collateral_params`, `set_reserve_factor`,
+    /// `set_strict_oracle`) after launch.
+    /// 
+    /// # Arguments
+    /// * `admin` - Admin address for protocol management
+    /// * `price_oracle` - Price oracle contract address
+    /// * `interest_rate_model` - Interest rate model contract address
+    /// * `xlm_token` - Wrapped XLM token contract address
+    /// * `usdc_token` - USDC token contract address
+    pub fn initialize_safe(
+        env: Env,
+        admin: Address,
+        price_oracle: Address,
+        interest_rate_model: Address,
+        xlm_token: Address,
+        usdc_token: Address,
+    ) {
+        Self::initialize(env.clone(), admin, price_oracle, interest_rate_model, xlm_token, usdc_token);
+
+        for asset in [XLM, USDC] {
+            env.storage().instance().set(&DataKey::LtvRatio(asset.clone()), &5_000_000i128); // 50%
+            env.storage().instance().set(&DataKey::LiquidationThreshold(asset.clone()), &6_000_000i128); // 60%
+            env.storage().instance().set(&DataKey::ReserveFactor(asset), &1_000_000i128); // 10%
+        }
+        env.storage().instance().set(&DataKey::StrictOracle, &true);
+    }
+
     /// Internal: Initialize a market for an asset
     fn init_market(env: &Env, asset: Symbol, ltv: i128, liq_threshold: i128, collateral: bool, borrow: bool) {
         env.storage().instance().set(&DataKey::LtvRatio(asset.clone()), &ltv);
         env.storage().instance().set(&DataKey::LiquidationThreshold(asset.clone()), &liq_threshold);
         env.storage().instance().set(&DataKey::CollateralEnabled(asset.clone()), &collateral);
         env.storage().instance().set(&DataKey::BorrowEnabled(asset.clone()), &borrow);
+        env.storage().instance().set(&DataKey::CloseFactor(asset.clone()), &DEFAULT_CLOSE_FACTOR);
+        env.storage().instance().set(&DataKey::LiquidationBonus(asset.clone()), &DEFAULT_LIQUIDATION_BONUS);
+        env.storage()
+            .instance()
+            .set(&DataKey::LiquidationProtocolFee(asset.clone()), &DEFAULT_LIQUIDATION_PROTOCOL_FEE);
         env.storage().instance().set(&DataKey::TotalSupply(asset.clone()), &0i128);
         env.storage().instance().set(&DataKey::TotalShares(asset.clone()), &0i128);
         env.storage().instance().set(&DataKey::TotalBorrow(asset.clone()), &0i128);
@@ -196,6 +773,19 @@ impl LendingPool {
         env.storage().instance().set(&DataKey::LastAccrualTime(asset.clone()), &env.ledger().timestamp());
         env.storage().instance().set(&DataKey::ReserveFactor(asset.clone()), &1_000_000i128); // 10%
         env.storage().instance().set(&DataKey::TotalReserves(asset.clone()), &0i128);
+        env.storage().instance().set(&DataKey::Cash(asset.clone()), &0i128);
+
+        let mut markets = Self::get_market_list(env);
+        markets.push_back(asset);
+        env.storage().instance().set(&DataKey::MarketList, &markets);
+    }
+
+    /// Internal: Get the symbols of all listed markets
+    fn get_market_list(env: &Env) -> Vec<Symbol> {
+        env.storage()
+            .instance()
+            .get(&DataKey::MarketList)
+            .unwrap_or(Vec::new(env))
     }
 
     // ========================================================================
@@ -215,48 +805,141 @@ impl LendingPool {
     /// # Returns
     /// Amount of sToken shares minted
     pub fn supply(env: Env, user: Address, asset: Symbol, amount: i128) -> i128 {
+        Self::supply_to(env, user.clone(), user, asset, amount)
+    }
+
+    /// Supply assets on behalf of another user
+    /// 
+    /// Pulls underlying tokens from `payer` and credits the minted sToken
+    /// shares to `recipient`, who owns the position from then on.
+    /// 
+    /// # Arguments
+    /// * `payer` - Address paying the underlying tokens (must authorize)
+    /// * `recipient` - Address credited with the shares
+    /// * `asset` - Asset symbol (XLM or USDC)
+    /// * `amount` - Amount of underlying to deposit
+    /// 
+    /// # Returns
+    /// Amount of sToken shares minted
+    pub fn supply_to(env: Env, payer: Address, recipient: Address, asset: Symbol, amount: i128) -> i128 {
+        payer.require_auth();
+
+        Self::supply_internal(env, payer, recipient, asset, amount)
+    }
+
+    /// Supply assets as interest-bearing collateral
+    /// 
+    /// Mints sToken shares like `supply` and opts them in as collateral, so
+    /// the collateral keeps earning the supply rate and is valued at the
+    /// current exchange rate. Only for borrowable markets; assets nobody
+    /// borrows earn nothing and are deposited with `deposit_collateral`.
+    /// 
+    /// # Arguments
+    /// * `user` - The depositor's address
+    /// * `asset` - Asset symbol (e.g., USDC)
+    /// * `amount` - Amount of underlying to deposit
+    /// 
+    /// # Returns
+    /// Amount of sToken shares minted
+    pub fn supply_collateral(env: Env, user: Address, asset: Symbol, amount: i128) -> i128 {
         user.require_auth();
-        
+
+        let borrow_enabled: bool = env.storage().instance().get(&DataKey::BorrowEnabled(asset.clone())).unwrap_or(false);
+        if !borrow_enabled {
+            panic_with_error!(&env, PoolError::NotInterestBearing);
+        }
+        let collateral_enabled: bool = env
+            .storage()
+            .instance()
+            .get(&DataKey::CollateralEnabled(asset.clone()))
+            .unwrap_or(false);
+        if !collateral_enabled {
+            panic_with_error!(&env, PoolError::CollateralNotEnabled);
+        }
+        Self::require_isolation_compatible(&env, &user, &asset);
+
+        let shares = Self::supply_internal(env.clone(), user.clone(), user.clone(), asset.clone(), amount);
+        env.storage()
+            .persistent()
+            .set(&DataKey::SharesAsCollateral(user.clone(), asset.clone()), &true);
+
+        env.events().publish((symbol_short!("shr_coll"), user, asset), true);
+
+        shares
+    }
+
+    /// Internal: Supply without the auth check (shared by `supply_to`, `batch` and `operate`)
+    fn supply_internal(env: Env, payer: Address, recipient: Address, asset: Symbol, amount: i128) -> i128 {
         if amount <= 0 {
-            panic!("Amount must be positive");
+            panic_with_error!(&env, PoolError::AmountNotPositive);
+        }
+        Self::require_whitelisted(&env, &recipient);
+
+        // Frozen markets are winding down
+        let frozen: bool = env.storage().instance().get(&DataKey::Frozen(asset.clone())).unwrap_or(false);
+        if frozen {
+            panic_with_error!(&env, PoolError::MarketFrozen);
         }
 
         // Accrue interest before state changes
         Self::accrue_interest(&env, asset.clone());
 
+        // Enforce supply cap (0 = uncapped)
+        let supply_cap: i128 = env.storage().instance().get(&DataKey::SupplyCap(asset.clone())).unwrap_or(0);
+        if supply_cap > 0 {
+            let total_supply: i128 = env.storage().instance().get(&DataKey::TotalSupply(asset.clone())).unwrap_or(0);
+            if total_supply + amount > supply_cap {
+                panic_with_error!(&env, PoolError::SupplyCapExceeded);
+            }
+        }
+
         // Get current exchange rate
         let exchange_rate = Self::get_exchange_rate_internal(&env, asset.clone());
         
         // Calculate shares to mint: shares = amount * 1e9 / exchange_rate
-        let shares_to_mint = (amount * INITIAL_EXCHANGE_RATE) / exchange_rate;
+        // (rounded down, in the pool's favor)
+        let shares_to_mint = mul_div_floor(&env, amount, INITIAL_EXCHANGE_RATE, exchange_rate);
         
         if shares_to_mint <= 0 {
-            panic!("Amount too small");
+            panic_with_error!(&env, PoolError::AmountTooSmall);
         }
 
-        // Transfer underlying from user to pool
+        // Transfer underlying from payer to pool
         let token_address: Address = env.storage().instance().get(&DataKey::TokenAddress(asset.clone())).unwrap();
         let token_client = token::Client::new(&env, &token_address);
-        token_client.transfer(&user, &env.current_contract_address(), &amount);
+        token_client.transfer(&payer, &env.current_contract_address(), &amount);
+        Self::adjust_cash(&env, &asset, amount);
 
-        // Update user's share balance
+        // Update recipient's share balance
         let current_shares: i128 = env
             .storage()
             .persistent()
-            .get(&DataKey::UserShares(user.clone(), asset.clone()))
+            .get(&DataKey::UserShares(recipient.clone(), asset.clone()))
             .unwrap_or(0);
         env.storage()
             .persistent()
-            .set(&DataKey::UserShares(user.clone(), asset.clone()), &(current_shares + shares_to_mint));
+            .set(&DataKey::UserShares(recipient.clone(), asset.clone()), &(current_shares + shares_to_mint));
 
         // Update total supply and shares
         let total_supply: i128 = env.storage().instance().get(&DataKey::TotalSupply(asset.clone())).unwrap_or(0);
         let total_shares: i128 = env.storage().instance().get(&DataKey::TotalShares(asset.clone())).unwrap_or(0);
         env.storage().instance().set(&DataKey::TotalSupply(asset.clone()), &(total_supply + amount));
         env.storage().instance().set(&DataKey::TotalShares(asset.clone()), &(total_shares + shares_to_mint));
+        Self::debug_check_solvency(&env, &asset);
 
         // Emit event
-        env.events().publish((symbol_short!("supply"), user, asset), (amount, shares_to_mint));
+        Self::publish_event(
+            &env,
+            symbol_short!("supply"),
+            SupplyEvent {
+                user: recipient,
+                asset,
+                amount,
+                shares: shares_to_mint,
+                new_user_shares: current_shares + shares_to_mint,
+                new_total_supply: total_supply + amount,
+            },
+        );
 
         shares_to_mint
     }
@@ -268,15 +951,20 @@ impl LendingPool {
     /// # Arguments
     /// * `user` - The user's address
     /// * `asset` - Asset symbol
-    /// * `share_amount` - Amount of sToken shares to burn
+    /// * `share_amount` - Amount of sToken shares to burn (`i128::MAX` = all shares)
     /// 
     /// # Returns
     /// Amount of underlying tokens returned
     pub fn withdraw(env: Env, user: Address, asset: Symbol, share_amount: i128) -> i128 {
         user.require_auth();
-        
+
+        Self::withdraw_internal(env, user, asset, share_amount)
+    }
+
+    /// Internal: Withdraw without the auth check (shared by `withdraw`, `batch` and `operate`)
+    fn withdraw_internal(env: Env, user: Address, asset: Symbol, share_amount: i128) -> i128 {
         if share_amount <= 0 {
-            panic!("Amount must be positive");
+            panic_with_error!(&env, PoolError::AmountNotPositive);
         }
 
         // Accrue interest before state changes
@@ -288,20 +976,73 @@ impl LendingPool {
             .persistent()
             .get(&DataKey::UserShares(user.clone(), asset.clone()))
             .unwrap_or(0);
-        if user_shares < share_amount {
-            panic!("Insufficient share balance");
+        let share_amount = if share_amount == i128::MAX { user_shares } else { share_amount };
+        if share_amount == 0 || user_shares < share_amount {
+            panic_with_error!(&env, PoolError::InsufficientShares);
         }
 
         // Calculate underlying to return: underlying = shares * exchange_rate / 1e9
+        // (rounded down, in the pool's favor)
         let exchange_rate = Self::get_exchange_rate_internal(&env, asset.clone());
-        let underlying_amount = (share_amount * exchange_rate) / INITIAL_EXCHANGE_RATE;
+        let underlying_amount = mul_div_floor(&env, share_amount, exchange_rate, INITIAL_EXCHANGE_RATE);
 
-        // Check pool has sufficient liquidity
-        let total_supply: i128 = env.storage().instance().get(&DataKey::TotalSupply(asset.clone())).unwrap_or(0);
-        let total_borrow: i128 = env.storage().instance().get(&DataKey::TotalBorrow(asset.clone())).unwrap_or(0);
-        let available_liquidity = total_supply - total_borrow;
-        if available_liquidity < underlying_amount {
-            panic!("Insufficient pool liquidity");
+        Self::burn_shares(env, user, asset, user_shares, share_amount, underlying_amount)
+    }
+
+    /// Withdraw an exact amount of underlying from the lending pool
+    /// 
+    /// Burns the shares worth `underlying_amount` at the current exchange
+    /// rate, rounded up so the user never receives more than the shares
+    /// are worth, and transfers exactly `underlying_amount`.
+    /// 
+    /// # Arguments
+    /// * `user` - The user's address
+    /// * `asset` - Asset symbol
+    /// * `underlying_amount` - Amount of underlying to receive (`i128::MAX` = all shares)
+    /// 
+    /// # Returns
+    /// Amount of underlying tokens returned
+    pub fn withdraw_underlying(env: Env, user: Address, asset: Symbol, underlying_amount: i128) -> i128 {
+        user.require_auth();
+
+        if underlying_amount == i128::MAX {
+            return Self::withdraw_internal(env, user, asset, i128::MAX);
+        }
+        if underlying_amount <= 0 {
+            panic_with_error!(&env, PoolError::AmountNotPositive);
+        }
+
+        // Accrue interest before state changes
+        Self::accrue_interest(&env, asset.clone());
+
+        let exchange_rate = Self::get_exchange_rate_internal(&env, asset.clone());
+        let share_amount = mul_div_ceil(&env, underlying_amount, INITIAL_EXCHANGE_RATE, exchange_rate);
+
+        let user_shares: i128 = env
+            .storage()
+            .persistent()
+            .get(&DataKey::UserShares(user.clone(), asset.clone()))
+            .unwrap_or(0);
+        if user_shares < share_amount {
+            panic_with_error!(&env, PoolError::InsufficientShares);
+        }
+
+        Self::burn_shares(env, user, asset, user_shares, share_amount, underlying_amount)
+    }
+
+    /// Internal: Burn a user's shares and pay out `underlying_amount`
+    /// (shared by `withdraw_internal` and `withdraw_underlying`)
+    fn burn_shares(
+        env: Env,
+        user: Address,
+        asset: Symbol,
+        user_shares: i128,
+        share_amount: i128,
+        underlying_amount: i128,
+    ) -> i128 {
+        // Check pool holds enough supply-side cash
+        if Self::get_cash(&env, asset.clone()) < underlying_amount {
+            panic_with_error!(&env, PoolError::InsufficientLiquidity);
         }
 
         // Update user's share balance
@@ -310,21 +1051,94 @@ impl LendingPool {
             .set(&DataKey::UserShares(user.clone(), asset.clone()), &(user_shares - share_amount));
 
         // Update total supply and shares
+        let total_supply: i128 = env.storage().instance().get(&DataKey::TotalSupply(asset.clone())).unwrap_or(0);
         let total_shares: i128 = env.storage().instance().get(&DataKey::TotalShares(asset.clone())).unwrap_or(0);
         env.storage().instance().set(&DataKey::TotalSupply(asset.clone()), &(total_supply - underlying_amount));
         env.storage().instance().set(&DataKey::TotalShares(asset.clone()), &(total_shares - share_amount));
 
+        // Shares backing a borrow must leave the position healthy
+        if Self::shares_used_as_collateral(&env, &user, &asset) {
+            let position = Self::get_user_position(env.clone(), user.clone());
+            if position.has_debt && position.health_factor < SCALE {
+                panic_with_error!(&env, PoolError::PositionUnhealthy);
+            }
+        }
+
         // Transfer underlying from pool to user
         let token_address: Address = env.storage().instance().get(&DataKey::TokenAddress(asset.clone())).unwrap();
         let token_client = token::Client::new(&env, &token_address);
         token_client.transfer(&env.current_contract_address(), &user, &underlying_amount);
+        Self::adjust_cash(&env, &asset, -underlying_amount);
+        Self::debug_check_solvency(&env, &asset);
 
         // Emit event
-        env.events().publish((symbol_short!("withdraw"), user, asset), (underlying_amount, share_amount));
+        Self::publish_event(
+            &env,
+            symbol_short!("withdraw"),
+            WithdrawEvent {
+                user,
+                asset,
+                amount: underlying_amount,
+                shares: share_amount,
+                new_user_shares: user_shares - share_amount,
+                new_total_supply: total_supply - underlying_amount,
+            },
+        );
 
         underlying_amount
     }
 
+    /// Transfer sToken shares to another user
+    /// 
+    /// Moves an interest-bearing supply position without withdrawing, which
+    /// works even while the market's liquidity is borrowed out.
+    /// 
+    /// # Arguments
+    /// * `from` - Current holder of the shares
+    /// * `to` - Recipient of the shares
+    /// * `asset` - Asset symbol
+    /// * `shares` - Amount of sToken shares to transfer
+    pub fn transfer_shares(env: Env, from: Address, to: Address, asset: Symbol, shares: i128) {
+        from.require_auth();
+
+        if shares <= 0 {
+            panic_with_error!(&env, PoolError::AmountNotPositive);
+        }
+        if from == to {
+            panic_with_error!(&env, PoolError::SameAccount);
+        }
+        Self::require_whitelisted(&env, &to);
+
+        let from_shares: i128 = env
+            .storage()
+            .persistent()
+            .get(&DataKey::UserShares(from.clone(), asset.clone()))
+            .unwrap_or(0);
+        if from_shares < shares {
+            panic_with_error!(&env, PoolError::InsufficientShares);
+        }
+        let to_shares: i128 = env
+            .storage()
+            .persistent()
+            .get(&DataKey::UserShares(to.clone(), asset.clone()))
+            .unwrap_or(0);
+
+        env.storage()
+            .persistent()
+            .set(&DataKey::UserShares(from.clone(), asset.clone()), &(from_shares - shares));
+        env.storage()
+            .persistent()
+            .set(&DataKey::UserShares(to.clone(), asset.clone()), &(to_shares + shares));
+
+        // The sender's remaining position must stay healthy
+        let position = Self::get_user_position(env.clone(), from.clone());
+        if position.has_debt && position.health_factor < SCALE {
+            panic_with_error!(&env, PoolError::PositionUnhealthy);
+        }
+
+        env.events().publish((Symbol::new(&env, "sh_transfer"), from, to, asset), shares);
+    }
+
     // ========================================================================
     // COLLATERAL FUNCTIONS
     // ========================================================================
@@ -340,10 +1154,17 @@ impl LendingPool {
     /// * `amount` - Amount to deposit as collateral
     pub fn deposit_collateral(env: Env, user: Address, asset: Symbol, amount: i128) -> i128 {
         user.require_auth();
-        
+
+        Self::deposit_collateral_internal(env, user.clone(), user, asset, amount)
+    }
+
+    /// Internal: Deposit collateral without the auth check (shared by `deposit_collateral`, `batch` and `operate`)
+    /// Tokens are pulled from `payer` and credited to `user`'s collateral.
+    fn deposit_collateral_internal(env: Env, payer: Address, user: Address, asset: Symbol, amount: i128) -> i128 {
         if amount <= 0 {
-            panic!("Amount must be positive");
+            panic_with_error!(&env, PoolError::AmountNotPositive);
         }
+        Self::require_whitelisted(&env, &user);
 
         // Check asset is enabled as collateral
         let collateral_enabled: bool = env
@@ -352,13 +1173,22 @@ impl LendingPool {
             .get(&DataKey::CollateralEnabled(asset.clone()))
             .unwrap_or(false);
         if !collateral_enabled {
-            panic!("Asset not enabled as collateral");
+            panic_with_error!(&env, PoolError::CollateralNotEnabled);
+        }
+
+        // Frozen markets are winding down
+        let frozen: bool = env.storage().instance().get(&DataKey::Frozen(asset.clone())).unwrap_or(false);
+        if frozen {
+            panic_with_error!(&env, PoolError::MarketFrozen);
         }
 
-        // Transfer from user to pool
+        Self::require_isolation_compatible(&env, &user, &asset);
+        Self::accrue_debt_markets(&env, &user);
+
+        // Transfer from payer to pool (collateral is kept out of supply-side cash)
         let token_address: Address = env.storage().instance().get(&DataKey::TokenAddress(asset.clone())).unwrap();
         let token_client = token::Client::new(&env, &token_address);
-        token_client.transfer(&user, &env.current_contract_address(), &amount);
+        token_client.transfer(&payer, &env.current_contract_address(), &amount);
 
         // Update user collateral balance
         let current_collateral: i128 = env
@@ -370,8 +1200,15 @@ impl LendingPool {
             .persistent()
             .set(&DataKey::UserCollateral(user.clone(), asset.clone()), &(current_collateral + amount));
 
+        let total_collateral: i128 = env.storage().instance().get(&DataKey::TotalCollateral(asset.clone())).unwrap_or(0);
+        env.storage().instance().set(&DataKey::TotalCollateral(asset.clone()), &(total_collateral + amount));
+
         // Emit event
-        env.events().publish((symbol_short!("coll_dep"), user, asset), amount);
+        Self::publish_event(
+            &env,
+            symbol_short!("coll_dep"),
+            CollateralEvent { user, asset, amount, new_balance: current_collateral + amount },
+        );
 
         amount
     }
@@ -381,200 +1218,2622 @@ impl LendingPool {
     /// # Arguments
     /// * `user` - The user's address
     /// * `asset` - Asset symbol
-    /// * `amount` - Amount to withdraw
+    /// * `amount` - Amount to withdraw (`i128::MAX` = the most that keeps
+    ///   the position within its LTV)
+    /// 
+    /// # Returns
+    /// Amount of collateral withdrawn
     pub fn withdraw_collateral(env: Env, user: Address, asset: Symbol, amount: i128) -> i128 {
         user.require_auth();
-        
+
+        Self::withdraw_collateral_internal(env, user, asset, amount)
+    }
+
+    /// Internal: Withdraw collateral without the auth check (shared by `withdraw_collateral`, `batch` and `operate`)
+    fn withdraw_collateral_internal(env: Env, user: Address, asset: Symbol, amount: i128) -> i128 {
         if amount <= 0 {
-            panic!("Amount must be positive");
+            panic_with_error!(&env, PoolError::AmountNotPositive);
         }
 
+        // Value the debt at current indexes before checking health
+        Self::accrue_debt_markets(&env, &user);
+
         let current_collateral: i128 = env
             .storage()
             .persistent()
             .get(&DataKey::UserCollateral(user.clone(), asset.clone()))
             .unwrap_or(0);
-        if current_collateral < amount {
-            panic!("Insufficient collateral");
+        let amount = if amount == i128::MAX {
+            Self::calculate_max_withdrawable_collateral(&env, &user, &asset, current_collateral)
+        } else {
+            amount
+        };
+        if amount == 0 || current_collateral < amount {
+            panic_with_error!(&env, PoolError::InsufficientCollateral);
         }
 
-        // Check that withdrawal doesn't make position unhealthy
+        // Check that withdrawal doesn't make position unhealthy, valuing
+        // the reduced balance in memory before anything is written
         let new_collateral = current_collateral - amount;
+        let position = Self::calculate_position(
+            &env,
+            &user,
+            &Vec::new(&env),
+            &Vec::new(&env),
+            Some((asset.clone(), new_collateral)),
+        );
         
-        // Temporarily update collateral to check health
+        // If user has debt, ensure health factor stays above 1.0
+        if position.has_debt && position.health_factor < SCALE {
+            panic_with_error!(&env, PoolError::PositionUnhealthy);
+        }
+
         env.storage()
             .persistent()
             .set(&DataKey::UserCollateral(user.clone(), asset.clone()), &new_collateral);
-        
-        let position = Self::get_user_position(env.clone(), user.clone());
-        
-        // If user has debt, ensure health factor stays above 1.0
-        if position.debt_value_usd > 0 && position.health_factor < SCALE {
-            // Revert the temporary update
-            env.storage()
-                .persistent()
-                .set(&DataKey::UserCollateral(user.clone(), asset.clone()), &current_collateral);
-            panic!("Withdrawal would make position unhealthy");
-        }
 
-        // Transfer from pool to user
+        let total_collateral: i128 = env.storage().instance().get(&DataKey::TotalCollateral(asset.clone())).unwrap_or(0);
+        env.storage().instance().set(&DataKey::TotalCollateral(asset.clone()), &(total_collateral - amount));
+
+        // Transfer from pool to user (collateral is kept out of supply-side cash)
         let token_address: Address = env.storage().instance().get(&DataKey::TokenAddress(asset.clone())).unwrap();
         let token_client = token::Client::new(&env, &token_address);
         token_client.transfer(&env.current_contract_address(), &user, &amount);
 
         // Emit event
-        env.events().publish((symbol_short!("coll_wth"), user, asset), amount);
+        Self::publish_event(
+            &env,
+            symbol_short!("coll_wth"),
+            CollateralEvent { user, asset, amount, new_balance: new_collateral },
+        );
 
         amount
     }
 
-    // ========================================================================
-    // BORROW FUNCTIONS
-    // ========================================================================
-
-    /// Borrow assets from the lending pool
+    /// Preview a user's position after withdrawing collateral
     /// 
-    /// Borrows underlying tokens against deposited collateral.
-    /// Requires: (total_debt_usd + new_borrow_usd) <= collateral_usd * LTV
+    /// Values the position exactly like `withdraw_collateral`'s health
+    /// check, with interest projected to the current timestamp. Read-only:
+    /// the result may be unhealthy, in which case the withdrawal would revert.
     /// 
     /// # Arguments
-    /// * `user` - The borrower's address
-    /// * `asset` - Asset symbol to borrow (typically USDC)
-    /// * `amount` - Amount to borrow
-    pub fn borrow(env: Env, user: Address, asset: Symbol, amount: i128) -> i128 {
-        user.require_auth();
-        
+    /// * `user` - The user's address
+    /// * `asset` - Collateral asset symbol
+    /// * `amount` - Amount of collateral to withdraw
+    pub fn preview_withdraw_collateral(env: Env, user: Address, asset: Symbol, amount: i128) -> UserPosition {
         if amount <= 0 {
-            panic!("Amount must be positive");
+            panic_with_error!(&env, PoolError::AmountNotPositive);
         }
 
-        // Check asset is enabled for borrowing
-        let borrow_enabled: bool = env
+        let current_collateral: i128 = env
             .storage()
-            .instance()
+            .persistent()
+            .get(&DataKey::UserCollateral(user.clone(), asset.clone()))
+            .unwrap_or(0);
+        if current_collateral < amount {
+            panic_with_error!(&env, PoolError::InsufficientCollateral);
+        }
+
+        Self::calculate_position(
+            &env,
+            &user,
+            &Vec::new(&env),
+            &Vec::new(&env),
+            Some((asset, current_collateral - amount)),
+        )
+    }
+
+    /// Opt an asset's deposited collateral in or out of the user's collateral
+    /// 
+    /// Deposited collateral counts by default. Disabling excludes it,
+    /// limiting liquidation exposure, and reverts if it would leave the
+    /// position unhealthy. Supplied sTokens are opted in separately via
+    /// `set_shares_as_collateral`.
+    /// 
+    /// # Arguments
+    /// * `user` - The user's address
+    /// * `asset` - Asset symbol
+    /// * `enabled` - Whether the deposits back the user's borrows
+    pub fn set_use_as_collateral(env: Env, user: Address, asset: Symbol, enabled: bool) {
+        user.require_auth();
+
+        Self::set_collateral_flag(&env, &user, &asset, DataKey::UseAsCollateral(user.clone(), asset.clone()), enabled);
+
+        env.events().publish((symbol_short!("use_coll"), user, asset), enabled);
+    }
+
+    /// Check whether a user's deposited collateral of an asset counts (default true)
+    pub fn is_used_as_collateral(env: Env, user: Address, asset: Symbol) -> bool {
+        env.storage()
+            .persistent()
+            .get(&DataKey::UseAsCollateral(user, asset))
+            .unwrap_or(true)
+    }
+
+    /// Opt supplied sTokens of an asset in or out of the user's collateral
+    /// 
+    /// Supplied sTokens don't count by default. Enabling counts the
+    /// underlying value of the user's shares (weighted by the asset's LTV);
+    /// they keep earning supply interest and can be seized via
+    /// `liquidate_shares`. Disabling reverts if it would leave the position
+    /// unhealthy. Independent of `set_use_as_collateral`.
+    /// 
+    /// # Arguments
+    /// * `user` - The user's address
+    /// * `asset` - Asset symbol
+    /// * `enabled` - Whether the shares back the user's borrows
+    pub fn set_shares_as_collateral(env: Env, user: Address, asset: Symbol, enabled: bool) {
+        user.require_auth();
+
+        Self::set_collateral_flag(&env, &user, &asset, DataKey::SharesAsCollateral(user.clone(), asset.clone()), enabled);
+
+        env.events().publish((symbol_short!("shr_coll"), user, asset), enabled);
+    }
+
+    /// Check whether a user's supplied sTokens of an asset count as collateral (default false)
+    pub fn is_shares_collateral(env: Env, user: Address, asset: Symbol) -> bool {
+        Self::shares_used_as_collateral(&env, &user, &asset)
+    }
+
+    /// Internal: Whether the user has opted supplied sTokens of an asset in as collateral
+    fn shares_used_as_collateral(env: &Env, user: &Address, asset: &Symbol) -> bool {
+        env.storage()
+            .persistent()
+            .get(&DataKey::SharesAsCollateral(user.clone(), asset.clone()))
+            .unwrap_or(false)
+    }
+
+    /// Internal: Store a collateral opt-in flag under `key`
+    /// 
+    /// Enabling requires a collateral-enabled asset compatible with the
+    /// user's isolation mode; disabling must leave the position healthy.
+    fn set_collateral_flag(env: &Env, user: &Address, asset: &Symbol, key: DataKey, enabled: bool) {
+        if enabled {
+            let collateral_enabled: bool = env
+                .storage()
+                .instance()
+                .get(&DataKey::CollateralEnabled(asset.clone()))
+                .unwrap_or(false);
+            if !collateral_enabled {
+                panic_with_error!(env, PoolError::CollateralNotEnabled);
+            }
+            Self::require_isolation_compatible(env, user, asset);
+            env.storage().persistent().set(&key, &true);
+        } else {
+            env.storage().persistent().set(&key, &false);
+
+            let position = Self::get_user_position(env.clone(), user.clone());
+            if position.has_debt && position.health_factor < SCALE {
+                panic_with_error!(env, PoolError::PositionUnhealthy);
+            }
+        }
+    }
+
+    // ========================================================================
+    // BORROW FUNCTIONS
+    // ========================================================================
+
+    /// Borrow assets from the lending pool
+    /// 
+    /// Borrows underlying tokens against deposited collateral.
+    /// Requires: (total_debt_usd + new_borrow_usd) <= collateral_usd * LTV
+    /// 
+    /// # Arguments
+    /// * `user` - The borrower's address
+    /// * `asset` - Asset symbol to borrow (typically USDC)
+    /// * `amount` - Amount to borrow
+    pub fn borrow(env: Env, user: Address, asset: Symbol, amount: i128) -> i128 {
+        Self::borrow_to(env, user.clone(), asset, amount, user)
+    }
+
+    /// Borrow assets and send them to a different address
+    /// 
+    /// The debt and all LTV checks apply to `user`; only the token transfer
+    /// goes to `receiver`. Only the borrower needs to authorize.
+    /// 
+    /// # Arguments
+    /// * `user` - The borrower's address
+    /// * `asset` - Asset symbol to borrow (typically USDC)
+    /// * `amount` - Amount to borrow
+    /// * `receiver` - Address receiving the borrowed tokens
+    pub fn borrow_to(env: Env, user: Address, asset: Symbol, amount: i128, receiver: Address) -> i128 {
+        user.require_auth();
+
+        Self::borrow_internal(env, user, asset, amount, receiver, false)
+    }
+
+    /// Borrow assets at a stable rate
+    /// 
+    /// The current borrow rate is fixed for this debt and accrues
+    /// independently of later utilization swings. Adding to a stable
+    /// position blends the fixed rate with the current one, weighted by
+    /// debt. A position is either variable or stable: a stable borrow
+    /// against variable debt of the same asset (or vice versa) reverts.
+    /// 
+    /// # Arguments
+    /// * `user` - The borrower's address
+    /// * `asset` - Asset symbol to borrow
+    /// * `amount` - Amount to borrow
+    /// 
+    /// # Returns
+    /// Amount borrowed
+    pub fn borrow_stable(env: Env, user: Address, asset: Symbol, amount: i128) -> i128 {
+        user.require_auth();
+
+        Self::borrow_internal(env, user.clone(), asset, amount, user, true)
+    }
+
+    /// Get the fixed annual rate of a user's stable-rate debt (0 = variable)
+    pub fn get_user_stable_rate(env: Env, user: Address, asset: Symbol) -> i128 {
+        env.storage()
+            .persistent()
+            .get(&DataKey::UserStableRate(user, asset))
+            .unwrap_or(0)
+    }
+
+    /// Internal: Borrow without the auth check (shared by `borrow_to`,
+    /// `borrow_stable`, `batch` and `operate`)
+    fn borrow_internal(env: Env, user: Address, asset: Symbol, amount: i128, receiver: Address, stable: bool) -> i128 {
+        if amount <= 0 {
+            panic_with_error!(&env, PoolError::AmountNotPositive);
+        }
+        Self::require_whitelisted(&env, &user);
+
+        // Check asset is enabled for borrowing
+        let borrow_enabled: bool = env
+            .storage()
+            .instance()
             .get(&DataKey::BorrowEnabled(asset.clone()))
             .unwrap_or(false);
-        if !borrow_enabled {
-            panic!("Asset not enabled for borrowing");
+        if !borrow_enabled {
+            panic_with_error!(&env, PoolError::BorrowNotEnabled);
+        }
+
+        // Frozen markets are winding down
+        let frozen: bool = env.storage().instance().get(&DataKey::Frozen(asset.clone())).unwrap_or(false);
+        if frozen {
+            panic_with_error!(&env, PoolError::MarketFrozen);
+        }
+
+        // Accrue interest before state changes
+        Self::accrue_interest(&env, asset.clone());
+
+        // Check pool holds enough supply-side cash
+        if Self::get_cash(&env, asset.clone()) < amount {
+            panic_with_error!(&env, PoolError::InsufficientLiquidity);
+        }
+        let total_borrow: i128 = env.storage().instance().get(&DataKey::TotalBorrow(asset.clone())).unwrap_or(0);
+
+        // Enforce borrow cap (0 = uncapped)
+        let borrow_cap: i128 = env.storage().instance().get(&DataKey::BorrowCap(asset.clone())).unwrap_or(0);
+        if borrow_cap > 0 && total_borrow + amount > borrow_cap {
+            panic_with_error!(&env, PoolError::BorrowCapExceeded);
+        }
+
+        let existing_debt = Self::get_user_debt_with_interest(&env, user.clone(), asset.clone());
+
+        // A position is either variable or stable rate
+        let is_stable = env
+            .storage()
+            .persistent()
+            .has(&DataKey::UserStableRate(user.clone(), asset.clone()));
+        if existing_debt > 0 && is_stable != stable {
+            panic_with_error!(&env, PoolError::BorrowModeConflict);
+        }
+
+        // Enforce minimum position size (0 = no minimum); adding to a
+        // position is fine as long as the result clears the floor
+        let min_borrow: i128 = env.storage().instance().get(&DataKey::MinBorrow(asset.clone())).unwrap_or(0);
+        if min_borrow > 0 && existing_debt + amount < min_borrow {
+            panic_with_error!(&env, PoolError::BorrowTooSmall);
+        }
+
+        // Enforce the user's debt limit (0 = unlimited)
+        let borrow_limit = Self::effective_borrow_limit(&env, &user, &asset);
+        if borrow_limit > 0 && existing_debt + amount > borrow_limit {
+            panic_with_error!(&env, PoolError::BorrowLimitExceeded);
+        }
+
+        // Get current user position
+        let position = Self::get_user_position(env.clone(), user.clone());
+
+        // Get borrow amount in USD
+        let oracle: Address = env.storage().instance().get(&DataKey::PriceOracle).unwrap();
+        let asset_price = Self::get_asset_price(&env, &oracle, &asset);
+        // Rounded up so dust never slips under the LTV limit
+        let borrow_value_usd = mul_div_ceil(&env, amount, asset_price, Self::token_unit(&env, &asset));
+
+        // Check LTV constraint: new_total_debt <= collateral * LTV
+        let new_total_debt_usd = position.debt_value_usd + borrow_value_usd;
+        if new_total_debt_usd > position.available_borrow_usd + position.debt_value_usd {
+            panic_with_error!(&env, PoolError::LtvExceeded);
+        }
+
+        // Isolation mode: debt backed by an isolated asset is capped
+        let collateral_assets = Self::collateral_assets(&env, &user);
+        if collateral_assets.len() == 1 {
+            let collateral_asset = collateral_assets.get(0).unwrap();
+            if Self::is_isolated(&env, &collateral_asset) {
+                let debt_ceiling: i128 = env
+                    .storage()
+                    .instance()
+                    .get(&DataKey::IsolationDebtCeiling(collateral_asset))
+                    .unwrap_or(0);
+                if new_total_debt_usd > debt_ceiling {
+                    panic_with_error!(&env, PoolError::IsolationCeilingExceeded);
+                }
+            }
+        }
+
+        // Update total borrow
+        env.storage().instance().set(&DataKey::TotalBorrow(asset.clone()), &(total_borrow + amount));
+
+        // Update user's debt balance, keeping the interest accrued so far
+        // (stable-rate interest is booked on top of the total written above)
+        Self::write_debt(&env, &user, &asset, existing_debt + amount);
+        if stable {
+            Self::snapshot_stable_rate(&env, &user, &asset, existing_debt, amount);
+        }
+
+        // Transfer underlying from pool to receiver
+        let token_address: Address = env.storage().instance().get(&DataKey::TokenAddress(asset.clone())).unwrap();
+        let token_client = token::Client::new(&env, &token_address);
+        token_client.transfer(&env.current_contract_address(), &receiver, &amount);
+        Self::adjust_cash(&env, &asset, -amount);
+        Self::debug_check_solvency(&env, &asset);
+
+        // Emit event
+        Self::publish_event(
+            &env,
+            symbol_short!("borrow"),
+            BorrowEvent {
+                user,
+                asset,
+                amount,
+                receiver,
+                new_debt: existing_debt + amount,
+                new_total_borrow: total_borrow + amount,
+            },
+        );
+
+        amount
+    }
+
+    /// Repay borrowed assets
+    /// 
+    /// Reduces user's debt balance and pool's total borrows.
+    /// 
+    /// # Arguments
+    /// * `user` - The borrower's address
+    /// * `asset` - Asset symbol
+    /// * `amount` - Amount to repay (use i128::MAX to repay all)
+    /// 
+    /// # Returns
+    /// Actual amount repaid
+    pub fn repay(env: Env, user: Address, asset: Symbol, amount: i128) -> i128 {
+        user.require_auth();
+
+        Self::repay_internal(&env, &user, &user, &asset, amount)
+    }
+
+    /// Repay another borrower's debt
+    /// 
+    /// The payer authorizes and funds the repayment, while the debt reduction
+    /// is applied to the borrower's position. Used by liquidation bots and
+    /// integrations where a third party (e.g. a guarantor) covers a loan.
+    /// 
+    /// # Arguments
+    /// * `payer` - Address funding the repayment
+    /// * `borrower` - Address whose debt is reduced
+    /// * `asset` - Asset symbol
+    /// * `amount` - Amount to repay (use i128::MAX to repay all)
+    /// 
+    /// # Returns
+    /// Actual amount repaid
+    /// 
+    /// # Events
+    /// Emits `("repay_obo", payer, borrower)` with `(asset, repaid)`, next
+    /// to the usual `repay` event
+    pub fn repay_on_behalf(env: Env, payer: Address, borrower: Address, asset: Symbol, amount: i128) -> i128 {
+        payer.require_auth();
+
+        let repaid = Self::repay_internal(&env, &payer, &borrower, &asset, amount);
+
+        env.events()
+            .publish((symbol_short!("repay_obo"), payer, borrower), (asset, repaid));
+
+        repaid
+    }
+
+    /// Internal: Pull `amount` from `payer` and apply it to `borrower`'s debt
+    /// 
+    /// Caps the repayment at the borrower's outstanding debt (with interest).
+    fn repay_internal(env: &Env, payer: &Address, borrower: &Address, asset: &Symbol, amount: i128) -> i128 {
+        if amount <= 0 {
+            panic_with_error!(env, PoolError::AmountNotPositive);
+        }
+
+        // Accrue interest before state changes
+        Self::accrue_interest(env, asset.clone());
+
+        // Get borrower's current debt (including accrued interest)
+        let user_debt = Self::get_user_debt_with_interest(env, borrower.clone(), asset.clone());
+        
+        if user_debt == 0 {
+            panic_with_error!(env, PoolError::NoDebt);
+        }
+
+        // Cap repayment at outstanding debt
+        let repay_amount = if amount > user_debt { user_debt } else { amount };
+
+        // Transfer underlying from payer to pool
+        let token_address: Address = env.storage().instance().get(&DataKey::TokenAddress(asset.clone())).unwrap();
+        let token_client = token::Client::new(env, &token_address);
+        token_client.transfer(payer, &env.current_contract_address(), &repay_amount);
+        Self::adjust_cash(env, asset, repay_amount);
+
+        Self::reduce_debt(env, borrower, asset, repay_amount, user_debt);
+        Self::debug_check_solvency(env, asset);
+
+        // Emit event
+        Self::publish_event(
+            env,
+            symbol_short!("repay"),
+            RepayEvent {
+                payer: payer.clone(),
+                borrower: borrower.clone(),
+                asset: asset.clone(),
+                amount: repay_amount,
+                remaining_debt: user_debt - repay_amount,
+                new_total_borrow: env.storage().instance().get(&DataKey::TotalBorrow(asset.clone())).unwrap_or(0),
+            },
+        );
+
+        repay_amount
+    }
+
+    /// Internal: Apply a repayment to the borrower's debt and the market's total borrows
+    /// 
+    /// `TotalBorrow` includes accrued interest, so it is reduced by the full
+    /// interest-inclusive repayment, like the borrower's balance.
+    fn reduce_debt(env: &Env, borrower: &Address, asset: &Symbol, repay_amount: i128, user_debt: i128) {
+        // Update borrower's debt balance (`user_debt` includes interest)
+        let new_debt = if repay_amount >= user_debt { 0 } else { user_debt - repay_amount };
+        Self::write_debt(env, borrower, asset, new_debt);
+
+        // Update total borrow
+        let total_borrow: i128 = env.storage().instance().get(&DataKey::TotalBorrow(asset.clone())).unwrap_or(0);
+        let new_total_borrow = if total_borrow > repay_amount { total_borrow - repay_amount } else { 0 };
+        env.storage().instance().set(&DataKey::TotalBorrow(asset.clone()), &new_total_borrow);
+    }
+
+    /// Internal: Store a user's debt with interest as principal at the
+    /// current borrow index
+    /// 
+    /// Every debt change goes through here, so interest accrued under the
+    /// old index snapshot is capitalized instead of lost. A zero debt clears
+    /// every key, so no stale index or rate snapshot outlives the position.
+    /// Stable-rate debt is snapshotted at the current time instead.
+    fn write_debt(env: &Env, user: &Address, asset: &Symbol, debt: i128) {
+        let stable = env
+            .storage()
+            .persistent()
+            .has(&DataKey::UserStableRate(user.clone(), asset.clone()));
+        if stable {
+            Self::book_stable_interest(env, user, asset, debt);
+        }
+
+        if debt == 0 {
+            env.storage()
+                .persistent()
+                .remove(&DataKey::UserDebt(user.clone(), asset.clone()));
+            env.storage()
+                .persistent()
+                .remove(&DataKey::UserBorrowIndex(user.clone(), asset.clone()));
+            env.storage()
+                .persistent()
+                .remove(&DataKey::UserStableRate(user.clone(), asset.clone()));
+            env.storage()
+                .persistent()
+                .remove(&DataKey::UserStableTime(user.clone(), asset.clone()));
+            return;
+        }
+
+        if stable {
+            env.storage()
+                .persistent()
+                .set(&DataKey::UserDebt(user.clone(), asset.clone()), &debt);
+            env.storage()
+                .persistent()
+                .set(&DataKey::UserStableTime(user.clone(), asset.clone()), &env.ledger().timestamp());
+            return;
+        }
+
+        let borrow_index: i128 = env
+            .storage()
+            .instance()
+            .get(&DataKey::BorrowIndex(asset.clone()))
+            .unwrap_or(INITIAL_EXCHANGE_RATE);
+
+        env.storage()
+            .persistent()
+            .set(&DataKey::UserDebt(user.clone(), asset.clone()), &debt);
+        env.storage()
+            .persistent()
+            .set(&DataKey::UserBorrowIndex(user.clone(), asset.clone()), &borrow_index);
+    }
+
+    /// Internal: Book a stable-rate position's interest into the market
+    /// totals and move the market's stable principal to `debt`
+    /// 
+    /// Stable interest is not part of borrow index growth, so it reaches
+    /// suppliers and reserves whenever the position is rewritten.
+    fn book_stable_interest(env: &Env, user: &Address, asset: &Symbol, debt: i128) {
+        let principal: i128 = env
+            .storage()
+            .persistent()
+            .get(&DataKey::UserDebt(user.clone(), asset.clone()))
+            .unwrap_or(0);
+        let interest = Self::get_user_debt_with_interest(env, user.clone(), asset.clone()) - principal;
+
+        if interest > 0 {
+            let reserve_factor: i128 = env
+                .storage()
+                .instance()
+                .get(&DataKey::ReserveFactor(asset.clone()))
+                .unwrap_or(1_000_000);
+            let reserve_interest = mul_div_ceil(env, interest, reserve_factor, SCALE);
+
+            let total_borrow: i128 = env.storage().instance().get(&DataKey::TotalBorrow(asset.clone())).unwrap_or(0);
+            let total_supply: i128 = env.storage().instance().get(&DataKey::TotalSupply(asset.clone())).unwrap_or(0);
+            let total_reserves: i128 = env.storage().instance().get(&DataKey::TotalReserves(asset.clone())).unwrap_or(0);
+            env.storage().instance().set(&DataKey::TotalBorrow(asset.clone()), &(total_borrow + interest));
+            env.storage()
+                .instance()
+                .set(&DataKey::TotalSupply(asset.clone()), &(total_supply + interest - reserve_interest));
+            env.storage()
+                .instance()
+                .set(&DataKey::TotalReserves(asset.clone()), &(total_reserves + reserve_interest));
+        }
+
+        let total_stable_borrow: i128 = env
+            .storage()
+            .instance()
+            .get(&DataKey::TotalStableBorrow(asset.clone()))
+            .unwrap_or(0);
+        env.storage()
+            .instance()
+            .set(&DataKey::TotalStableBorrow(asset.clone()), &(total_stable_borrow - principal + debt));
+    }
+
+    /// Internal: Fix the rate of a stable-rate borrow
+    /// 
+    /// Uses the borrow rate after the borrow, so the borrower pays for its
+    /// own utilization impact. A new position moves from the borrow index
+    /// to the stable books; an existing one blends its rate with the
+    /// current one, weighted by debt.
+    fn snapshot_stable_rate(env: &Env, user: &Address, asset: &Symbol, previous_debt: i128, amount: i128) {
+        let current_rate = Self::borrow_rate_at(env, Self::get_utilization_rate(env.clone(), asset.clone()));
+        let rate_key = DataKey::UserStableRate(user.clone(), asset.clone());
+
+        let rate = if previous_debt == 0 {
+            env.storage()
+                .persistent()
+                .remove(&DataKey::UserBorrowIndex(user.clone(), asset.clone()));
+            env.storage()
+                .persistent()
+                .set(&DataKey::UserStableTime(user.clone(), asset.clone()), &env.ledger().timestamp());
+
+            let total_stable_borrow: i128 = env
+                .storage()
+                .instance()
+                .get(&DataKey::TotalStableBorrow(asset.clone()))
+                .unwrap_or(0);
+            env.storage()
+                .instance()
+                .set(&DataKey::TotalStableBorrow(asset.clone()), &(total_stable_borrow + amount));
+
+            current_rate
+        } else {
+            // Debt-weighted average of the old and new rates, written as an
+            // offset from the old rate so a single mul_div covers it
+            let previous_rate: i128 = env.storage().persistent().get(&rate_key).unwrap_or(0);
+            previous_rate + mul_div_floor(env, amount, current_rate - previous_rate, previous_debt + amount)
+        };
+
+        env.storage().persistent().set(&rate_key, &rate);
+    }
+
+    /// Repay debt using deposited collateral
+    /// 
+    /// No tokens move. Collateral in the debt asset is netted directly: it
+    /// leaves the collateral bucket and becomes supply-side cash, exactly as
+    /// if it had been withdrawn and repaid. Other collateral is valued at
+    /// oracle prices (no bonus) and seized into its market's reserves, and
+    /// the repaid debt moves to the pool's own account as protocol debt, so
+    /// the debt market's borrows and its suppliers' claims are unchanged
+    /// until the treasury settles it (`repay_on_behalf` for the pool).
+    /// 
+    /// # Arguments
+    /// * `user` - The borrower's address
+    /// * `debt_asset` - Asset whose debt is repaid
+    /// * `collateral_asset` - Collateral asset used to pay
+    /// * `repay_amount` - Debt to repay (use i128::MAX to repay all)
+    /// 
+    /// # Returns
+    /// Actual amount of debt repaid
+    /// 
+    /// # Errors
+    /// * `PoolError::InsufficientCollateral` - Not enough collateral to cover the repayment
+    /// * `PoolError::HealthFactorDecreased` - The repayment would lower the health factor
+    /// 
+    /// # Events
+    /// Emits `("repay_col", user, debt_asset)` with
+    /// `(collateral_asset, repay_amount, collateral_used)`
+    pub fn repay_with_collateral(
+        env: Env,
+        user: Address,
+        debt_asset: Symbol,
+        collateral_asset: Symbol,
+        repay_amount: i128,
+    ) -> i128 {
+        user.require_auth();
+
+        if repay_amount <= 0 {
+            panic_with_error!(&env, PoolError::AmountNotPositive);
+        }
+
+        // Accrue interest before state changes
+        Self::accrue_interest(&env, debt_asset.clone());
+        Self::accrue_interest(&env, collateral_asset.clone());
+
+        let user_debt = Self::get_user_debt_with_interest(&env, user.clone(), debt_asset.clone());
+        if user_debt == 0 {
+            panic_with_error!(&env, PoolError::NoDebt);
+        }
+        let repay_amount = if repay_amount > user_debt { user_debt } else { repay_amount };
+
+        // Collateral needed at oracle prices, rounded up in the pool's favor
+        let collateral_used = if collateral_asset == debt_asset {
+            repay_amount
+        } else {
+            let oracle: Address = env.storage().instance().get(&DataKey::PriceOracle).unwrap();
+            let debt_price = Self::get_asset_price(&env, &oracle, &debt_asset);
+            let collateral_price = Self::get_asset_price(&env, &oracle, &collateral_asset);
+            let repay_value_usd = mul_div_ceil(&env, repay_amount, debt_price, Self::token_unit(&env, &debt_asset));
+            mul_div_ceil(&env, repay_value_usd, Self::token_unit(&env, &collateral_asset), collateral_price)
+        };
+
+        let current_collateral: i128 = env
+            .storage()
+            .persistent()
+            .get(&DataKey::UserCollateral(user.clone(), collateral_asset.clone()))
+            .unwrap_or(0);
+        if current_collateral < collateral_used {
+            panic_with_error!(&env, PoolError::InsufficientCollateral);
+        }
+
+        let health_before = Self::get_user_position(env.clone(), user.clone()).health_factor;
+
+        env.storage().persistent().set(
+            &DataKey::UserCollateral(user.clone(), collateral_asset.clone()),
+            &(current_collateral - collateral_used),
+        );
+        let total_collateral: i128 = env.storage().instance().get(&DataKey::TotalCollateral(collateral_asset.clone())).unwrap_or(0);
+        env.storage()
+            .instance()
+            .set(&DataKey::TotalCollateral(collateral_asset.clone()), &(total_collateral - collateral_used));
+        Self::adjust_cash(&env, &collateral_asset, collateral_used);
+
+        if collateral_asset == debt_asset {
+            Self::reduce_debt(&env, &user, &debt_asset, repay_amount, user_debt);
+        } else {
+            // The seized collateral belongs to the protocol...
+            let total_reserves: i128 = env
+                .storage()
+                .instance()
+                .get(&DataKey::TotalReserves(collateral_asset.clone()))
+                .unwrap_or(0);
+            env.storage()
+                .instance()
+                .set(&DataKey::TotalReserves(collateral_asset.clone()), &(total_reserves + collateral_used));
+
+            // ...and so does the debt it paid for
+            let pool = env.current_contract_address();
+            let protocol_debt = Self::get_user_debt_with_interest(&env, pool.clone(), debt_asset.clone());
+            Self::write_debt(&env, &user, &debt_asset, user_debt - repay_amount);
+            Self::write_debt(&env, &pool, &debt_asset, protocol_debt + repay_amount);
+        }
+        Self::debug_check_solvency(&env, &debt_asset);
+        Self::debug_check_solvency(&env, &collateral_asset);
+
+        if Self::get_user_position(env.clone(), user.clone()).health_factor < health_before {
+            panic_with_error!(&env, PoolError::HealthFactorDecreased);
+        }
+
+        env.events().publish(
+            (symbol_short!("repay_col"), user, debt_asset),
+            (collateral_asset, repay_amount, collateral_used),
+        );
+
+        repay_amount
+    }
+
+    /// Get the debt the protocol took over from `repay_with_collateral`
+    /// 
+    /// Accrues interest like any variable borrow until the treasury repays
+    /// it with `repay_on_behalf` for the pool's own address.
+    pub fn get_protocol_debt(env: Env, asset: Symbol) -> i128 {
+        let pool = env.current_contract_address();
+        Self::get_user_debt_projected(&env, pool, asset)
+    }
+
+    /// Repay debt and withdraw collateral in one call
+    /// 
+    /// Interest is accrued once, so a full close can't be broken by
+    /// interest accruing between two separate transactions. The health
+    /// check on the withdrawal applies to the post-repay position.
+    /// 
+    /// # Arguments
+    /// * `user` - The user's address
+    /// * `debt_asset` - Asset to repay
+    /// * `repay_amount` - Amount to repay (`i128::MAX` = full debt)
+    /// * `collateral_asset` - Collateral asset to withdraw
+    /// * `withdraw_amount` - Amount to withdraw (`i128::MAX` = max safe amount)
+    /// 
+    /// # Returns
+    /// The amounts actually repaid and withdrawn
+    pub fn repay_and_withdraw(
+        env: Env,
+        user: Address,
+        debt_asset: Symbol,
+        repay_amount: i128,
+        collateral_asset: Symbol,
+        withdraw_amount: i128,
+    ) -> ExitResult {
+        user.require_auth();
+
+        let repaid = Self::repay_internal(&env, &user, &user, &debt_asset, repay_amount);
+
+        let withdrawn = Self::withdraw_collateral_internal(env, user, collateral_asset, withdraw_amount);
+
+        ExitResult { repaid, withdrawn }
+    }
+
+    // ========================================================================
+    // BATCH OPERATIONS
+    // ========================================================================
+
+    /// Execute several position changes atomically
+    /// 
+    /// Runs `ops` in order for `user` under a single authorization, e.g.
+    /// supply -> deposit_collateral -> borrow in one transaction. Each step
+    /// applies the same checks as its standalone function against the state
+    /// left by the previous steps; if any step fails, the whole batch reverts.
+    /// 
+    /// # Arguments
+    /// * `user` - The user whose position is changed
+    /// * `ops` - Operations to execute in order
+    /// 
+    /// # Returns
+    /// Each operation's result, in order (what the standalone function returns)
+    pub fn batch(env: Env, user: Address, ops: Vec<PoolOp>) -> Vec<i128> {
+        user.require_auth();
+
+        Self::execute_ops(&env, &user, &user, ops)
+    }
+
+    /// Deposit collateral and borrow against it in one call
+    /// 
+    /// The borrow is checked against the post-deposit position, and if it
+    /// fails the deposit is rolled back with it.
+    /// 
+    /// # Arguments
+    /// * `user` - The user's address
+    /// * `collateral_asset` - Asset to deposit as collateral
+    /// * `collateral_amount` - Amount of collateral to deposit
+    /// * `borrow_asset` - Asset to borrow
+    /// * `borrow_amount` - Amount to borrow (`i128::MAX` = up to the LTV
+    ///   limit minus the zap safety buffer)
+    /// 
+    /// # Returns
+    /// `(deposited, borrowed)`
+    pub fn deposit_and_borrow(
+        env: Env,
+        user: Address,
+        collateral_asset: Symbol,
+        collateral_amount: i128,
+        borrow_asset: Symbol,
+        borrow_amount: i128,
+    ) -> (i128, i128) {
+        user.require_auth();
+
+        let deposited = Self::deposit_collateral_internal(
+            env.clone(),
+            user.clone(),
+            user.clone(),
+            collateral_asset.clone(),
+            collateral_amount,
+        );
+
+        let borrow_amount = if borrow_amount == i128::MAX {
+            Self::calculate_zap_max_borrow(&env, &user, &borrow_asset)
+        } else {
+            borrow_amount
+        };
+        let borrowed = Self::borrow_internal(env.clone(), user.clone(), borrow_asset.clone(), borrow_amount, user.clone(), false);
+
+        env.events().publish(
+            (symbol_short!("zap"), user),
+            (collateral_asset, deposited, borrow_asset, borrowed),
+        );
+
+        (deposited, borrowed)
+    }
+
+    /// Deposit collateral and borrow against it atomically
+    /// 
+    /// Same as `deposit_and_borrow`: one authorization, and a single health
+    /// check on the post-deposit position, so the borrow succeeds where a
+    /// lone borrow before the deposit would be rejected.
+    /// 
+    /// # Arguments
+    /// * `user` - The user's address
+    /// * `collateral_asset` - Asset to deposit as collateral
+    /// * `collateral_amount` - Amount of collateral to deposit
+    /// * `borrow_asset` - Asset to borrow
+    /// * `borrow_amount` - Amount to borrow (`i128::MAX` = up to the LTV
+    ///   limit minus the zap safety buffer)
+    /// 
+    /// # Returns
+    /// `(deposited, borrowed)`
+    pub fn supply_and_borrow(
+        env: Env,
+        user: Address,
+        collateral_asset: Symbol,
+        collateral_amount: i128,
+        borrow_asset: Symbol,
+        borrow_amount: i128,
+    ) -> (i128, i128) {
+        Self::deposit_and_borrow(env, user, collateral_asset, collateral_amount, borrow_asset, borrow_amount)
+    }
+
+    /// Internal: Largest borrow of `asset` that keeps the user the zap
+    /// safety buffer below their LTV limit
+    fn calculate_zap_max_borrow(env: &Env, user: &Address, asset: &Symbol) -> i128 {
+        // Value existing debt at the index the borrow will see
+        Self::accrue_interest(env, asset.clone());
+
+        let position = Self::get_user_position(env.clone(), user.clone());
+        let buffer = Self::get_zap_safety_buffer(env.clone());
+        let borrowable_usd = position.available_borrow_usd - mul_div_floor(env, position.collateral_value_usd, buffer, SCALE);
+        if borrowable_usd <= 0 {
+            return 0;
+        }
+
+        let oracle: Address = env.storage().instance().get(&DataKey::PriceOracle).unwrap();
+        let price = Self::get_asset_price(env, &oracle, asset);
+        mul_div_floor(env, borrowable_usd, Self::token_unit(env, asset), price)
+    }
+
+    /// Execute operations on an owner's position as an approved operator
+    /// 
+    /// Works like `batch` for `owner`, authorized by `operator` instead.
+    /// Tokens going into the pool (supply, collateral, repay) are paid by
+    /// the operator; tokens coming out (withdrawals, borrows) always go to
+    /// the owner, never the operator.
+    /// 
+    /// # Arguments
+    /// * `operator` - Address approved via `set_operator`
+    /// * `owner` - The user whose position is changed
+    /// * `ops` - Operations to execute in order
+    /// 
+    /// # Returns
+    /// Each operation's result, in order
+    pub fn operate(env: Env, operator: Address, owner: Address, ops: Vec<PoolOp>) -> Vec<i128> {
+        operator.require_auth();
+
+        if !Self::is_operator(env.clone(), owner.clone(), operator.clone()) {
+            panic_with_error!(&env, PoolError::Unauthorized);
+        }
+
+        Self::execute_ops(&env, &owner, &operator, ops)
+    }
+
+    /// Internal: Run batch operations on `owner`'s position, pulling any
+    /// incoming tokens from `payer`
+    fn execute_ops(env: &Env, owner: &Address, payer: &Address, ops: Vec<PoolOp>) -> Vec<i128> {
+        if ops.is_empty() {
+            panic_with_error!(env, PoolError::EmptyBatch);
+        }
+
+        let mut results = Vec::new(env);
+        for op in ops.iter() {
+            let result = match op {
+                PoolOp::Supply(asset, amount) => {
+                    Self::supply_internal(env.clone(), payer.clone(), owner.clone(), asset, amount)
+                }
+                PoolOp::Withdraw(asset, share_amount) => {
+                    Self::withdraw_internal(env.clone(), owner.clone(), asset, share_amount)
+                }
+                PoolOp::DepositCollateral(asset, amount) => {
+                    Self::deposit_collateral_internal(env.clone(), payer.clone(), owner.clone(), asset, amount)
+                }
+                PoolOp::WithdrawCollateral(asset, amount) => {
+                    Self::withdraw_collateral_internal(env.clone(), owner.clone(), asset, amount)
+                }
+                PoolOp::Borrow(asset, amount) => {
+                    Self::borrow_internal(env.clone(), owner.clone(), asset, amount, owner.clone(), false)
+                }
+                PoolOp::Repay(asset, amount) => Self::repay_internal(env, payer, owner, &asset, amount),
+            };
+            results.push_back(result);
+        }
+
+        results
+    }
+
+    /// Approve or revoke an operator for the owner's positions
+    /// 
+    /// # Arguments
+    /// * `owner` - The user granting access (must authorize)
+    /// * `operator` - Address allowed to call `operate` for the owner
+    /// * `approved` - `true` to grant, `false` to revoke
+    pub fn set_operator(env: Env, owner: Address, operator: Address, approved: bool) {
+        owner.require_auth();
+
+        let key = DataKey::Operator(owner.clone(), operator.clone());
+        if approved {
+            env.storage().persistent().set(&key, &true);
+        } else {
+            env.storage().persistent().remove(&key);
+        }
+
+        env.events().publish((symbol_short!("operator"), owner, operator), approved);
+    }
+
+    /// Check whether `operator` may manage `owner`'s positions
+    pub fn is_operator(env: Env, owner: Address, operator: Address) -> bool {
+        env.storage()
+            .persistent()
+            .get(&DataKey::Operator(owner, operator))
+            .unwrap_or(false)
+    }
+
+    // ========================================================================
+    // REFERRALS
+    // ========================================================================
+
+    /// Supply assets, attributing the volume to a referral code
+    /// 
+    /// Behaves exactly like `supply`. Unknown or deactivated codes are
+    /// treated as 0 (no referral).
+    /// 
+    /// # Arguments
+    /// * `user` - The supplier's address
+    /// * `asset` - Asset symbol
+    /// * `amount` - Amount to supply
+    /// * `referral` - Integrator's referral code
+    /// 
+    /// # Returns
+    /// Amount of sToken shares minted
+    pub fn supply_with_referral(env: Env, user: Address, asset: Symbol, amount: i128, referral: u32) -> i128 {
+        let shares = Self::supply(env.clone(), user.clone(), asset.clone(), amount);
+        Self::record_referral(&env, referral, user, asset, amount, symbol_short!("supply"));
+        shares
+    }
+
+    /// Borrow assets, attributing the volume to a referral code
+    /// 
+    /// Behaves exactly like `borrow`. Unknown or deactivated codes are
+    /// treated as 0 (no referral).
+    /// 
+    /// # Arguments
+    /// * `user` - The borrower's address
+    /// * `asset` - Asset symbol to borrow
+    /// * `amount` - Amount to borrow
+    /// * `referral` - Integrator's referral code
+    /// 
+    /// # Returns
+    /// Amount borrowed
+    pub fn borrow_with_referral(env: Env, user: Address, asset: Symbol, amount: i128, referral: u32) -> i128 {
+        let borrowed = Self::borrow(env.clone(), user.clone(), asset.clone(), amount);
+        Self::record_referral(&env, referral, user, asset, amount, symbol_short!("borrow"));
+        borrowed
+    }
+
+    /// Internal: Add referred volume and emit `("referral", code, user)`
+    /// with `(action, asset, amount)`
+    fn record_referral(env: &Env, referral: u32, user: Address, asset: Symbol, amount: i128, action: Symbol) {
+        let code = if Self::is_referral_active(env.clone(), referral) { referral } else { 0 };
+
+        if code != 0 {
+            let key = DataKey::ReferralVolume(code, asset.clone());
+            let volume: i128 = env.storage().persistent().get(&key).unwrap_or(0);
+            env.storage().persistent().set(&key, &(volume + amount));
+        }
+
+        env.events().publish((symbol_short!("referral"), code, user), (action, asset, amount));
+    }
+
+    /// Register a referral code (owner only)
+    pub fn register_referral(env: Env, code: u32) {
+        Self::require_role(&env, Role::Owner);
+
+        if code == 0 {
+            panic_with_error!(&env, PoolError::InvalidParameter);
+        }
+        env.storage().persistent().set(&DataKey::ReferralCode(code), &true);
+
+        env.events().publish((symbol_short!("ref_add"),), code);
+    }
+
+    /// Deactivate a referral code (owner only)
+    /// 
+    /// Its recorded volume is kept; new activity is no longer attributed.
+    pub fn deactivate_referral(env: Env, code: u32) {
+        Self::require_role(&env, Role::Owner);
+
+        env.storage().persistent().remove(&DataKey::ReferralCode(code));
+
+        env.events().publish((symbol_short!("ref_rm"),), code);
+    }
+
+    /// Check whether a referral code is registered and active
+    pub fn is_referral_active(env: Env, code: u32) -> bool {
+        env.storage()
+            .persistent()
+            .get(&DataKey::ReferralCode(code))
+            .unwrap_or(false)
+    }
+
+    /// Get cumulative supply and borrow volume attributed to a referral code
+    pub fn get_referral_volume(env: Env, code: u32, asset: Symbol) -> i128 {
+        env.storage()
+            .persistent()
+            .get(&DataKey::ReferralVolume(code, asset))
+            .unwrap_or(0)
+    }
+
+    // ========================================================================
+    // POSITION MIGRATION
+    // ========================================================================
+
+    /// Move a user's entire position to a new address
+    /// 
+    /// Moves sToken shares, collateral, debt and collateral toggles in every
+    /// market, merging with any balances `to` already holds. Debt is merged
+    /// by capitalizing both sides' accrued interest into principal at the
+    /// current borrow index, so no interest is lost or double-counted.
+    /// Stable-rate debt keeps its rate (blended by debt when both sides are
+    /// stable); merging stable into variable debt, or vice versa, reverts.
+    /// Where both sides hold a balance, a collateral toggle is on if either
+    /// side had it on. The merged position must respect isolation mode and
+    /// stay healthy.
+    /// 
+    /// # Arguments
+    /// * `from` - Current owner of the position (must authorize)
+    /// * `to` - New address (must authorize)
+    /// 
+    /// # Errors
+    /// * `PoolError::BorrowModeConflict` - `from` and `to` owe the same asset
+    ///   at different rate modes
+    /// * `PoolError::IsolatedCollateralMixing` - The merged collateral mixes
+    ///   an isolated asset with others
+    /// * `PoolError::PositionUnhealthy` - The merged position is liquidatable
+    pub fn migrate_position(env: Env, from: Address, to: Address) {
+        from.require_auth();
+        to.require_auth();
+
+        if from == to {
+            panic_with_error!(&env, PoolError::SameAccount);
+        }
+        Self::require_whitelisted(&env, &to);
+
+        for asset in Self::get_market_list(&env).iter() {
+            Self::accrue_interest(&env, asset.clone());
+
+            // Toggles are resolved before the balances they apply to move
+            Self::merge_collateral_flag(
+                &env,
+                DataKey::UserCollateral(from.clone(), asset.clone()),
+                DataKey::UserCollateral(to.clone(), asset.clone()),
+                DataKey::UseAsCollateral(from.clone(), asset.clone()),
+                DataKey::UseAsCollateral(to.clone(), asset.clone()),
+                true,
+            );
+            Self::merge_collateral_flag(
+                &env,
+                DataKey::UserShares(from.clone(), asset.clone()),
+                DataKey::UserShares(to.clone(), asset.clone()),
+                DataKey::SharesAsCollateral(from.clone(), asset.clone()),
+                DataKey::SharesAsCollateral(to.clone(), asset.clone()),
+                false,
+            );
+
+            Self::merge_balance(
+                &env,
+                DataKey::UserShares(from.clone(), asset.clone()),
+                DataKey::UserShares(to.clone(), asset.clone()),
+            );
+            Self::merge_balance(
+                &env,
+                DataKey::UserCollateral(from.clone(), asset.clone()),
+                DataKey::UserCollateral(to.clone(), asset.clone()),
+            );
+
+            let from_debt = Self::get_user_debt_with_interest(&env, from.clone(), asset.clone());
+            if from_debt > 0 {
+                Self::merge_debt(&env, &from, &to, &asset, from_debt);
+            }
+        }
+
+        // Merging into an existing position must not mix isolated
+        // collateral or leave it liquidatable
+        for asset in Self::collateral_assets(&env, &to).iter() {
+            Self::require_isolation_compatible(&env, &to, &asset);
+        }
+        let position = Self::get_user_position(env.clone(), to.clone());
+        if position.has_debt && position.health_factor < SCALE {
+            panic_with_error!(&env, PoolError::PositionUnhealthy);
+        }
+
+        env.events().publish((symbol_short!("migrate"), from, to), ());
+    }
+
+    /// Internal: Move `from`'s debt in `asset` onto `to`, keeping its rate mode
+    fn merge_debt(env: &Env, from: &Address, to: &Address, asset: &Symbol, from_debt: i128) {
+        let from_rate_key = DataKey::UserStableRate(from.clone(), asset.clone());
+        let to_rate_key = DataKey::UserStableRate(to.clone(), asset.clone());
+        let from_rate: Option<i128> = env.storage().persistent().get(&from_rate_key);
+        let to_rate: Option<i128> = env.storage().persistent().get(&to_rate_key);
+        let to_debt = Self::get_user_debt_with_interest(env, to.clone(), asset.clone());
+
+        if to_debt > 0 && from_rate.is_some() != to_rate.is_some() {
+            panic_with_error!(env, PoolError::BorrowModeConflict);
+        }
+
+        // A fresh stable position on `to` takes over `from`'s rate; the
+        // stable snapshot time restarts once interest is capitalized below
+        if to_debt == 0 {
+            if let Some(rate) = from_rate {
+                env.storage().persistent().set(&to_rate_key, &rate);
+            }
+        }
+
+        Self::write_debt(env, to, asset, from_debt + to_debt);
+        Self::write_debt(env, from, asset, 0);
+
+        // Two stable positions blend their rates, weighted by debt
+        if let (Some(from_rate), Some(to_rate)) = (from_rate, to_rate) {
+            if to_debt > 0 {
+                let rate = to_rate + mul_div_floor(env, from_debt, from_rate - to_rate, to_debt + from_debt);
+                env.storage().persistent().set(&to_rate_key, &rate);
+            }
+        }
+    }
+
+    /// Internal: Resolve a collateral toggle for a merge and clear `from`'s
+    /// 
+    /// A side's toggle only matters if it holds a balance it applies to;
+    /// when both do, the merged toggle is on if either was. Toggles equal
+    /// to `default` are stored as absent.
+    fn merge_collateral_flag(
+        env: &Env,
+        from_balance_key: DataKey,
+        to_balance_key: DataKey,
+        from_flag_key: DataKey,
+        to_flag_key: DataKey,
+        default: bool,
+    ) {
+        let from_balance: i128 = env.storage().persistent().get(&from_balance_key).unwrap_or(0);
+        let to_balance: i128 = env.storage().persistent().get(&to_balance_key).unwrap_or(0);
+        let from_flag: bool = env.storage().persistent().get(&from_flag_key).unwrap_or(default);
+        let to_flag: bool = env.storage().persistent().get(&to_flag_key).unwrap_or(default);
+
+        let merged = if from_balance > 0 && to_balance > 0 {
+            from_flag || to_flag
+        } else if from_balance > 0 {
+            from_flag
+        } else {
+            to_flag
+        };
+
+        if merged == default {
+            env.storage().persistent().remove(&to_flag_key);
+        } else {
+            env.storage().persistent().set(&to_flag_key, &merged);
+        }
+        env.storage().persistent().remove(&from_flag_key);
+    }
+
+    /// Internal: Add the balance under `from_key` to `to_key` and clear `from_key`
+    fn merge_balance(env: &Env, from_key: DataKey, to_key: DataKey) {
+        let amount: i128 = env.storage().persistent().get(&from_key).unwrap_or(0);
+        if amount == 0 {
+            return;
+        }
+
+        let existing: i128 = env.storage().persistent().get(&to_key).unwrap_or(0);
+        env.storage().persistent().set(&to_key, &(existing + amount));
+        env.storage().persistent().remove(&from_key);
+    }
+
+    // ========================================================================
+    // FLASH LOANS
+    // ========================================================================
+
+    /// Borrow pool liquidity within a single transaction
+    /// 
+    /// Transfers `amount` to `receiver`, invokes its `on_flash_loan` callback,
+    /// and requires `amount + fee` to be back in the pool when it returns.
+    /// The fee is added to protocol reserves.
+    /// 
+    /// # Arguments
+    /// * `initiator` - Address starting the loan (must authorize), forwarded to the receiver
+    /// * `receiver` - Contract implementing `FlashLoanReceiver`
+    /// * `asset` - Asset symbol to borrow
+    /// * `amount` - Amount to borrow
+    /// 
+    /// # Returns
+    /// Fee charged for the loan
+    /// 
+    /// # Errors
+    /// * `PoolError::FlashLoanNotRepaid` - Principal plus fee not returned
+    pub fn flash_loan(env: Env, initiator: Address, receiver: Address, asset: Symbol, amount: i128) -> i128 {
+        initiator.require_auth();
+
+        if amount <= 0 {
+            panic_with_error!(&env, PoolError::AmountNotPositive);
+        }
+
+        // Only idle supply-side cash can be lent out
+        if Self::get_cash(&env, asset.clone()) < amount {
+            panic_with_error!(&env, PoolError::InsufficientLiquidity);
+        }
+
+        let fee_rate: i128 = env
+            .storage()
+            .instance()
+            .get(&DataKey::FlashLoanFee)
+            .unwrap_or(DEFAULT_FLASH_LOAN_FEE);
+        let fee = mul_div_floor(&env, amount, fee_rate, SCALE);
+
+        let token_address: Address = env.storage().instance().get(&DataKey::TokenAddress(asset.clone())).unwrap();
+        let token_client = token::Client::new(&env, &token_address);
+        let pool = env.current_contract_address();
+        let balance_before = token_client.balance(&pool);
+
+        // Lend, then hand control to the receiver
+        token_client.transfer(&pool, &receiver, &amount);
+        FlashLoanReceiverClient::new(&env, &receiver).on_flash_loan(
+            &pool,
+            &initiator,
+            &asset,
+            &token_address,
+            &amount,
+            &fee,
+        );
+
+        // Principal plus fee must be back before we return
+        if token_client.balance(&pool) < balance_before + fee {
+            panic_with_error!(&env, PoolError::FlashLoanNotRepaid);
+        }
+
+        let current_reserves: i128 = env
+            .storage()
+            .instance()
+            .get(&DataKey::TotalReserves(asset.clone()))
+            .unwrap_or(0);
+        env.storage().instance().set(&DataKey::TotalReserves(asset.clone()), &(current_reserves + fee));
+        Self::adjust_cash(&env, &asset, fee);
+        Self::debug_check_solvency(&env, &asset);
+
+        // Emit event
+        env.events().publish((symbol_short!("flash"), receiver, asset), (amount, fee));
+
+        fee
+    }
+
+    // ========================================================================
+    // ADMIN FUNCTIONS
+    // ========================================================================
+
+    /// Get the admin (owner) address
+    pub fn get_admin(env: Env) -> Address {
+        env.storage().instance().get(&DataKey::Admin).unwrap()
+    }
+
+    /// Propose a new admin (owner only)
+    /// 
+    /// Ownership only moves once the proposed address calls `accept_admin`,
+    /// so a mistyped address can't lock the protocol. A new proposal
+    /// replaces any pending one.
+    pub fn propose_admin(env: Env, new_admin: Address) {
+        Self::require_role(&env, Role::Owner);
+
+        env.storage().instance().set(&DataKey::PendingAdmin, &new_admin);
+
+        env.events().publish((symbol_short!("adm_prop"),), new_admin);
+    }
+
+    /// Accept a pending admin proposal (proposed admin only)
+    /// 
+    /// The risk admin is not affected by the transfer.
+    pub fn accept_admin(env: Env) {
+        let pending: Address = env
+            .storage()
+            .instance()
+            .get(&DataKey::PendingAdmin)
+            .unwrap_or_else(|| panic_with_error!(&env, PoolError::Unauthorized));
+        pending.require_auth();
+
+        env.storage().instance().set(&DataKey::Admin, &pending);
+        env.storage().instance().remove(&DataKey::PendingAdmin);
+
+        env.events().publish((symbol_short!("adm_acc"),), pending);
+    }
+
+    /// Get the admin proposed via `propose_admin`, if any
+    pub fn get_pending_admin(env: Env) -> Option<Address> {
+        env.storage().instance().get(&DataKey::PendingAdmin)
+    }
+
+    /// Set the risk admin (owner only)
+    pub fn set_risk_admin(env: Env, risk_admin: Address) {
+        Self::require_role(&env, Role::Owner);
+
+        env.storage().instance().set(&DataKey::RiskAdmin, &risk_admin);
+
+        env.events().publish((symbol_short!("risk_adm"),), risk_admin);
+    }
+
+    /// Get the risk admin address
+    pub fn get_risk_admin(env: Env) -> Address {
+        env.storage()
+            .instance()
+            .get(&DataKey::RiskAdmin)
+            .unwrap_or_else(|| env.storage().instance().get(&DataKey::Admin).unwrap())
+    }
+
+    /// Internal: Require authorization from the holder of `role`
+    fn require_role(env: &Env, role: Role) {
+        let holder: Address = match role {
+            Role::Owner => env.storage().instance().get(&DataKey::Admin).unwrap(),
+            Role::RiskAdmin => Self::get_risk_admin(env.clone()),
+        };
+        holder.require_auth();
+    }
+
+    /// Queue pointing the pool at a new price oracle (owner only)
+    /// 
+    /// Goes through the timelock like `queue_param_change`; the switch takes
+    /// effect via `execute_param_change` once the delay has elapsed. The new
+    /// oracle is then probed with `get_price` for every listed asset and
+    /// rejected if any of them has no price, so the pool is never pointed
+    /// at a dead, uninitialized or incomplete contract.
+    /// 
+    /// # Arguments
+    /// * `new_oracle` - New price oracle contract address
+    /// 
+    /// # Returns
+    /// Id of the queued change
+    pub fn set_price_oracle(env: Env, new_oracle: Address) -> u32 {
+        Self::queue_param_change(env, ParamChange::PriceOracle(new_oracle))
+    }
+
+    /// Get the price oracle contract address
+    pub fn get_price_oracle(env: Env) -> Address {
+        env.storage().instance().get(&DataKey::PriceOracle).unwrap()
+    }
+
+    /// Queue pointing the pool at a new Interest Rate Model (owner only)
+    /// 
+    /// Goes through the timelock like `queue_param_change`. On execution the
+    /// new model is probed with `get_parameters` before acceptance, and
+    /// interest is accrued on every market first so that time elapsed under
+    /// the old model is never recomputed at the new model's rates. From then
+    /// on, borrow rates are read from the model.
+    /// 
+    /// # Arguments
+    /// * `new_model` - New interest rate model contract address
+    /// 
+    /// # Returns
+    /// Id of the queued change
+    pub fn set_interest_rate_model(env: Env, new_model: Address) -> u32 {
+        Self::queue_param_change(env, ParamChange::InterestRateModel(new_model))
+    }
+
+    /// Queue new LTV and liquidation threshold for an asset (risk admin)
+    /// 
+    /// Goes through the timelock like `queue_param_change`, so borrowers
+    /// see a tighter LTV or threshold coming before it can liquidate them.
+    /// 
+    /// # Arguments
+    /// * `asset` - Asset symbol
+    /// * `ltv` - Loan-to-value ratio (scaled by SCALE)
+    /// * `liquidation_threshold` - Liquidation threshold (scaled by SCALE), must be >= LTV
+    /// 
+    /// # Returns
+    /// Id of the queued change
+    pub fn set_collateral_params(env: Env, asset: Symbol, ltv: i128, liquidation_threshold: i128) -> u32 {
+        Self::queue_param_change(env, ParamChange::CollateralParams(asset, ltv, liquidation_threshold))
+    }
+
+    /// Queue a new reserve factor for an asset (risk admin)
+    /// 
+    /// Interest is accrued at the old factor when the change is executed.
+    /// 
+    /// Goes through the timelock like `queue_param_change`; the change
+    /// takes effect via `execute_param_change` once the delay has elapsed.
+    /// 
+    /// # Arguments
+    /// * `asset` - Asset symbol
+    /// * `reserve_factor` - Portion of interest going to reserves (scaled by SCALE)
+    /// 
+    /// # Returns
+    /// Id of the queued change
+    pub fn set_reserve_factor(env: Env, asset: Symbol, reserve_factor: i128) -> u32 {
+        Self::queue_param_change(env, ParamChange::ReserveFactor(asset, reserve_factor))
+    }
+
+    /// Queue a new close factor for a collateral asset (risk admin)
+    /// 
+    /// Goes through the timelock like `queue_param_change`; the change
+    /// takes effect via `execute_param_change` once the delay has elapsed.
+    /// 
+    /// # Arguments
+    /// * `asset` - Collateral asset symbol
+    /// * `close_factor` - Max portion of debt repayable per liquidation, in (0, 100%]
+    /// 
+    /// # Returns
+    /// Id of the queued change
+    pub fn set_close_factor(env: Env, asset: Symbol, close_factor: i128) -> u32 {
+        Self::queue_param_change(env, ParamChange::CloseFactor(asset, close_factor))
+    }
+
+    /// Queue a new liquidation bonus for a collateral asset (risk admin)
+    /// 
+    /// Goes through the timelock like `queue_param_change`; the change
+    /// takes effect via `execute_param_change` once the delay has elapsed.
+    /// 
+    /// # Arguments
+    /// * `asset` - Collateral asset symbol
+    /// * `liquidation_bonus` - Extra collateral given to liquidators, in [0, 50%]
+    /// 
+    /// # Returns
+    /// Id of the queued change
+    pub fn set_liquidation_bonus(env: Env, asset: Symbol, liquidation_bonus: i128) -> u32 {
+        Self::queue_param_change(env, ParamChange::LiquidationBonus(asset, liquidation_bonus))
+    }
+
+    /// Queue a new liquidation protocol fee for a collateral asset (risk admin)
+    /// 
+    /// Goes through the timelock like `queue_param_change`; the change
+    /// takes effect via `execute_param_change` once the delay has elapsed.
+    /// 
+    /// # Arguments
+    /// * `asset` - Collateral asset symbol
+    /// * `fee` - Share of the liquidation bonus credited to reserves instead
+    ///   of the liquidator, in [0, 100%]
+    /// 
+    /// # Returns
+    /// Id of the queued change
+    pub fn set_liquidation_protocol_fee(env: Env, asset: Symbol, fee: i128) -> u32 {
+        Self::queue_param_change(env, ParamChange::LiquidationProtocolFee(asset, fee))
+    }
+
+    /// Set the supply cap for an asset (risk admin)
+    /// 
+    /// # Arguments
+    /// * `asset` - Asset symbol
+    /// * `cap` - Maximum total supply in underlying units (0 = uncapped)
+    pub fn set_supply_cap(env: Env, asset: Symbol, cap: i128) {
+        Self::require_role(&env, Role::RiskAdmin);
+
+        Self::apply_param_change(&env, ParamChange::SupplyCap(asset, cap));
+    }
+
+    /// Set the borrow cap for an asset (risk admin)
+    /// 
+    /// # Arguments
+    /// * `asset` - Asset symbol
+    /// * `cap` - Maximum total borrows in underlying units (0 = uncapped)
+    pub fn set_borrow_cap(env: Env, asset: Symbol, cap: i128) {
+        Self::require_role(&env, Role::RiskAdmin);
+
+        Self::apply_param_change(&env, ParamChange::BorrowCap(asset, cap));
+    }
+
+    /// Set the minimum debt position for an asset (risk admin)
+    /// 
+    /// Prevents dust positions that are uneconomical to liquidate.
+    /// 
+    /// # Arguments
+    /// * `asset` - Asset symbol
+    /// * `min_borrow` - Minimum debt in underlying units (0 = no minimum)
+    pub fn set_min_borrow(env: Env, asset: Symbol, min_borrow: i128) {
+        Self::require_role(&env, Role::RiskAdmin);
+
+        Self::apply_param_change(&env, ParamChange::MinBorrow(asset, min_borrow));
+    }
+
+    /// Get the minimum debt position for an asset (0 = no minimum)
+    pub fn get_min_borrow(env: Env, asset: Symbol) -> i128 {
+        env.storage().instance().get(&DataKey::MinBorrow(asset)).unwrap_or(0)
+    }
+
+    /// Set the default per-user debt limit for an asset (risk admin)
+    /// 
+    /// # Arguments
+    /// * `asset` - Asset symbol
+    /// * `limit` - Maximum debt (with interest) per user (0 = unlimited)
+    pub fn set_default_borrow_limit(env: Env, asset: Symbol, limit: i128) {
+        Self::require_role(&env, Role::RiskAdmin);
+
+        Self::apply_param_change(&env, ParamChange::DefaultBorrowLimit(asset, limit));
+    }
+
+    /// Set a user-specific debt limit for an asset, overriding the default (risk admin)
+    /// 
+    /// # Arguments
+    /// * `user` - Borrower address
+    /// * `asset` - Asset symbol
+    /// * `limit` - Maximum debt (with interest) for this user (0 = unlimited)
+    pub fn set_user_borrow_limit(env: Env, user: Address, asset: Symbol, limit: i128) {
+        Self::require_role(&env, Role::RiskAdmin);
+
+        Self::apply_param_change(&env, ParamChange::UserBorrowLimit(user, asset, limit));
+    }
+
+    /// Get a user's effective debt limit and remaining headroom for an asset
+    /// 
+    /// # Returns
+    /// `(limit, headroom)`; an unlimited user gets `(0, i128::MAX)`
+    pub fn get_user_borrow_limit(env: Env, user: Address, asset: Symbol) -> (i128, i128) {
+        let limit = Self::effective_borrow_limit(&env, &user, &asset);
+        if limit == 0 {
+            return (0, i128::MAX);
+        }
+
+        let debt = Self::get_user_debt_with_interest(&env, user, asset);
+        let headroom = if limit > debt { limit - debt } else { 0 };
+        (limit, headroom)
+    }
+
+    /// Internal: User override if set, otherwise the asset default (0 = unlimited)
+    fn effective_borrow_limit(env: &Env, user: &Address, asset: &Symbol) -> i128 {
+        env.storage()
+            .persistent()
+            .get(&DataKey::UserBorrowLimit(user.clone(), asset.clone()))
+            .unwrap_or_else(|| {
+                env.storage()
+                    .instance()
+                    .get(&DataKey::DefaultBorrowLimit(asset.clone()))
+                    .unwrap_or(0)
+            })
+    }
+
+    /// Get the supply cap for an asset (0 = uncapped)
+    pub fn get_supply_cap(env: Env, asset: Symbol) -> i128 {
+        env.storage().instance().get(&DataKey::SupplyCap(asset)).unwrap_or(0)
+    }
+
+    /// Get the borrow cap for an asset (0 = uncapped)
+    pub fn get_borrow_cap(env: Env, asset: Symbol) -> i128 {
+        env.storage().instance().get(&DataKey::BorrowCap(asset)).unwrap_or(0)
+    }
+
+    /// Configure isolation mode for a collateral asset (risk admin)
+    /// 
+    /// Isolated collateral cannot be held alongside any other collateral,
+    /// and users backed by it may only borrow up to `debt_ceiling` in
+    /// total debt.
+    /// 
+    /// # Arguments
+    /// * `asset` - Collateral asset
+    /// * `isolated` - Whether the asset is isolated
+    /// * `debt_ceiling` - Max USD debt per user (scaled by SCALE)
+    pub fn set_isolation_mode(env: Env, asset: Symbol, isolated: bool, debt_ceiling: i128) {
+        Self::require_role(&env, Role::RiskAdmin);
+
+        Self::apply_param_change(&env, ParamChange::IsolationMode(asset, isolated, debt_ceiling));
+    }
+
+    /// Get an asset's isolation mode: `(isolated, debt_ceiling)`
+    pub fn get_isolation_mode(env: Env, asset: Symbol) -> (bool, i128) {
+        (
+            Self::is_isolated(&env, &asset),
+            env.storage().instance().get(&DataKey::IsolationDebtCeiling(asset)).unwrap_or(0),
+        )
+    }
+
+    /// Internal: Whether a collateral asset is isolated
+    fn is_isolated(env: &Env, asset: &Symbol) -> bool {
+        env.storage().instance().get(&DataKey::Isolated(asset.clone())).unwrap_or(false)
+    }
+
+    /// Internal: Assets currently counting toward a user's collateral
+    fn collateral_assets(env: &Env, user: &Address) -> Vec<Symbol> {
+        let mut assets = Vec::new(env);
+        for asset in Self::get_market_list(env).iter() {
+            let collateral: i128 = env
+                .storage()
+                .persistent()
+                .get(&DataKey::UserCollateral(user.clone(), asset.clone()))
+                .unwrap_or(0);
+            let shares: i128 = env
+                .storage()
+                .persistent()
+                .get(&DataKey::UserShares(user.clone(), asset.clone()))
+                .unwrap_or(0);
+
+            let deposits_count = collateral > 0 && Self::is_used_as_collateral(env.clone(), user.clone(), asset.clone());
+            let shares_count = shares > 0 && Self::shares_used_as_collateral(env, user, &asset);
+            if deposits_count || shares_count {
+                assets.push_back(asset);
+            }
+        }
+        assets
+    }
+
+    /// Internal: Reject adding `asset` as collateral next to different
+    /// collateral when either side is isolated
+    fn require_isolation_compatible(env: &Env, user: &Address, asset: &Symbol) {
+        for other in Self::collateral_assets(env, user).iter() {
+            if other != *asset && (Self::is_isolated(env, asset) || Self::is_isolated(env, &other)) {
+                panic_with_error!(env, PoolError::IsolatedCollateralMixing);
+            }
+        }
+    }
+
+    /// Queue enabling or disabling strict oracle mode (risk admin)
+    /// 
+    /// When enabled, stale oracle prices are rejected and a missing price
+    /// panics even if fallback prices are allowed.
+    /// 
+    /// Goes through the timelock like `queue_param_change`; the change
+    /// takes effect via `execute_param_change` once the delay has elapsed.
+    /// 
+    /// # Returns
+    /// Id of the queued change
+    pub fn set_strict_oracle(env: Env, enabled: bool) -> u32 {
+        Self::queue_param_change(env, ParamChange::StrictOracle(enabled))
+    }
+
+    /// Check whether strict oracle mode is enabled
+    pub fn is_strict_oracle(env: Env) -> bool {
+        env.storage().instance().get(&DataKey::StrictOracle).unwrap_or(false)
+    }
+
+    /// Queue allowing or forbidding fallback prices (risk admin)
+    /// 
+    /// Meant for local testing: when enabled (and not in strict oracle
+    /// mode), an asset the oracle has no price for is valued at its
+    /// `set_fallback_price` price instead of reverting.
+    /// 
+    /// Goes through the timelock like `queue_param_change`; the change
+    /// takes effect via `execute_param_change` once the delay has elapsed.
+    /// 
+    /// # Returns
+    /// Id of the queued change
+    pub fn set_allow_fallback(env: Env, enabled: bool) -> u32 {
+        Self::queue_param_change(env, ParamChange::AllowFallback(enabled))
+    }
+
+    /// Check whether fallback prices are allowed
+    pub fn is_fallback_allowed(env: Env) -> bool {
+        env.storage().instance().get(&DataKey::AllowFallback).unwrap_or(false)
+    }
+
+    /// Queue a new fallback price for an asset (risk admin)
+    /// 
+    /// Goes through the timelock like `queue_param_change`; the change
+    /// takes effect via `execute_param_change` once the delay has elapsed.
+    /// 
+    /// # Arguments
+    /// * `asset` - Asset symbol
+    /// * `price` - Price in USD (scaled by 1e7)
+    /// 
+    /// # Returns
+    /// Id of the queued change
+    pub fn set_fallback_price(env: Env, asset: Symbol, price: i128) -> u32 {
+        Self::queue_param_change(env, ParamChange::FallbackPrice(asset, price))
+    }
+
+    /// Get the fallback price for an asset (0 if unset)
+    pub fn get_fallback_price(env: Env, asset: Symbol) -> i128 {
+        env.storage().instance().get(&DataKey::FallbackPrice(asset)).unwrap_or(0)
+    }
+
+    /// Set the pool's own maximum accepted price age (risk admin)
+    /// 
+    /// In strict oracle mode, prices older than `max_age` seconds are
+    /// rejected regardless of the oracle's staleness threshold.
+    /// 
+    /// # Arguments
+    /// * `max_age` - Maximum price age in seconds (0 = disabled)
+    pub fn set_pool_max_price_age(env: Env, max_age: u64) {
+        Self::require_role(&env, Role::RiskAdmin);
+
+        Self::apply_param_change(&env, ParamChange::PoolMaxPriceAge(max_age));
+    }
+
+    /// Get the pool's maximum accepted price age in seconds (0 = disabled)
+    pub fn get_pool_max_price_age(env: Env) -> u64 {
+        env.storage().instance().get(&DataKey::PoolMaxPriceAge).unwrap_or(0)
+    }
+
+    /// Choose whether a stale price of `asset` also blocks liquidations (risk admin)
+    /// 
+    /// In strict oracle mode a stale price always blocks borrows, collateral
+    /// withdrawals and other actions valued at that price. By default
+    /// liquidations still go through at the last known price, so positions
+    /// can be closed out while the feed is down.
+    /// 
+    /// # Arguments
+    /// * `asset` - Asset symbol
+    /// * `enabled` - Whether liquidations valued at a stale price revert too
+    pub fn set_stale_blocks_liquidation(env: Env, asset: Symbol, enabled: bool) {
+        Self::require_role(&env, Role::RiskAdmin);
+
+        Self::apply_param_change(&env, ParamChange::StaleBlocksLiquidation(asset, enabled));
+    }
+
+    /// Check whether a stale price of `asset` blocks liquidations
+    pub fn stale_blocks_liquidation(env: Env, asset: Symbol) -> bool {
+        env.storage()
+            .instance()
+            .get(&DataKey::StaleBlocksLiquidation(asset))
+            .unwrap_or(false)
+    }
+
+    /// Queue a new treasury address that owns protocol reserves (owner only)
+    /// 
+    /// Goes through the timelock like `queue_param_change`; the change
+    /// takes effect via `execute_param_change` once the delay has elapsed.
+    /// 
+    /// # Returns
+    /// Id of the queued change
+    pub fn set_treasury(env: Env, treasury: Address) -> u32 {
+        Self::queue_param_change(env, ParamChange::Treasury(treasury))
+    }
+
+    /// Get the treasury address
+    pub fn get_treasury(env: Env) -> Address {
+        env.storage().instance().get(&DataKey::Treasury).unwrap()
+    }
+
+    /// Withdraw protocol reserves
+    /// 
+    /// All protocol income (the reserve share of interest and flash loan
+    /// fees) accrues to `TotalReserves`, which is claimable by the treasury.
+    /// The treasury may send reserves to any recipient; the admin may only
+    /// withdraw on the treasury's behalf, so the funds always go to it.
+    /// 
+    /// # Arguments
+    /// * `caller` - Treasury or admin address
+    /// * `asset` - Asset symbol
+    /// * `amount` - Amount of reserves to withdraw
+    /// * `recipient` - Destination (defaults to the treasury)
+    /// 
+    /// # Returns
+    /// Amount withdrawn
+    pub fn withdraw_reserves(env: Env, caller: Address, asset: Symbol, amount: i128, recipient: Option<Address>) -> i128 {
+        caller.require_auth();
+
+        let admin: Address = env.storage().instance().get(&DataKey::Admin).unwrap();
+        let treasury: Address = env.storage().instance().get(&DataKey::Treasury).unwrap();
+        let recipient = recipient.unwrap_or(treasury.clone());
+        if caller != treasury && (caller != admin || recipient != treasury) {
+            panic_with_error!(&env, PoolError::Unauthorized);
+        }
+
+        if amount <= 0 {
+            panic_with_error!(&env, PoolError::AmountNotPositive);
+        }
+
+        Self::accrue_interest(&env, asset.clone());
+
+        let total_reserves: i128 = env.storage().instance().get(&DataKey::TotalReserves(asset.clone())).unwrap_or(0);
+        if amount > total_reserves {
+            panic_with_error!(&env, PoolError::InsufficientReserves);
+        }
+        // Reserves may be lent out; only idle cash can leave the pool
+        if amount > Self::get_cash(&env, asset.clone()) {
+            panic_with_error!(&env, PoolError::InsufficientLiquidity);
+        }
+        env.storage().instance().set(&DataKey::TotalReserves(asset.clone()), &(total_reserves - amount));
+
+        let token_address: Address = env.storage().instance().get(&DataKey::TokenAddress(asset.clone())).unwrap();
+        let token_client = token::Client::new(&env, &token_address);
+        token_client.transfer(&env.current_contract_address(), &recipient, &amount);
+        Self::adjust_cash(&env, &asset, -amount);
+        Self::debug_check_solvency(&env, &asset);
+
+        env.events().publish((symbol_short!("res_wth"), recipient, asset), amount);
+
+        amount
+    }
+
+    /// Convert protocol reserves into supply shares (admin only)
+    /// 
+    /// Reserves already sit in the market (as cash or lent out), so no
+    /// tokens move: `amount` leaves `TotalReserves` and joins the suppliers'
+    /// claim, and `to` is minted the shares it buys at the current exchange
+    /// rate. `TotalSupply` tracks that claim (it excludes reserves), so it
+    /// grows by `amount` and the exchange rate is unchanged.
+    /// 
+    /// # Arguments
+    /// * `asset` - Asset symbol
+    /// * `amount` - Amount of reserves to convert
+    /// * `to` - Account credited with the shares (e.g. the treasury)
+    /// 
+    /// # Returns
+    /// Number of shares minted
+    pub fn reserves_to_shares(env: Env, asset: Symbol, amount: i128, to: Address) -> i128 {
+        Self::require_role(&env, Role::Owner);
+
+        if amount <= 0 {
+            panic_with_error!(&env, PoolError::AmountNotPositive);
+        }
+
+        Self::accrue_interest(&env, asset.clone());
+
+        let total_reserves: i128 = env.storage().instance().get(&DataKey::TotalReserves(asset.clone())).unwrap_or(0);
+        if amount > total_reserves {
+            panic_with_error!(&env, PoolError::InsufficientReserves);
+        }
+
+        // Priced before the move, rounded down like a regular supply
+        let exchange_rate = Self::get_exchange_rate_internal(&env, asset.clone());
+        let shares_to_mint = mul_div_floor(&env, amount, INITIAL_EXCHANGE_RATE, exchange_rate);
+        if shares_to_mint <= 0 {
+            panic_with_error!(&env, PoolError::AmountTooSmall);
+        }
+
+        let total_supply: i128 = env.storage().instance().get(&DataKey::TotalSupply(asset.clone())).unwrap_or(0);
+        let total_shares: i128 = env.storage().instance().get(&DataKey::TotalShares(asset.clone())).unwrap_or(0);
+        let user_shares: i128 = env
+            .storage()
+            .persistent()
+            .get(&DataKey::UserShares(to.clone(), asset.clone()))
+            .unwrap_or(0);
+        env.storage().instance().set(&DataKey::TotalReserves(asset.clone()), &(total_reserves - amount));
+        env.storage().instance().set(&DataKey::TotalSupply(asset.clone()), &(total_supply + amount));
+        env.storage().instance().set(&DataKey::TotalShares(asset.clone()), &(total_shares + shares_to_mint));
+        env.storage()
+            .persistent()
+            .set(&DataKey::UserShares(to.clone(), asset.clone()), &(user_shares + shares_to_mint));
+        Self::debug_check_solvency(&env, &asset);
+
+        env.events().publish((symbol_short!("res_shr"), to, asset), (amount, shares_to_mint));
+
+        shares_to_mint
+    }
+
+    /// Set the flash loan fee (risk admin)
+    /// 
+    /// # Arguments
+    /// * `fee` - Fee rate scaled by SCALE (0.09% = 9_000), at most 100%
+    pub fn set_flash_loan_fee(env: Env, fee: i128) {
+        Self::require_role(&env, Role::RiskAdmin);
+
+        Self::apply_param_change(&env, ParamChange::FlashLoanFee(fee));
+    }
+
+    /// Enable or disable targeted deleveraging (risk admin)
+    /// 
+    /// When enabled, `liquidate` repays at most the amount that restores
+    /// the borrower's health factor to 1.0, instead of the full close factor.
+    pub fn set_targeted_liquidation(env: Env, enabled: bool) {
+        Self::require_role(&env, Role::RiskAdmin);
+
+        Self::apply_param_change(&env, ParamChange::TargetedLiquidation(enabled));
+    }
+
+    /// Check whether targeted deleveraging is enabled
+    pub fn is_targeted_liquidation(env: Env) -> bool {
+        env.storage().instance().get(&DataKey::TargetedLiquidation).unwrap_or(false)
+    }
+
+    /// Queue the highest health factor a liquidation may restore (risk admin)
+    /// 
+    /// Liquidations are capped at the repay that brings the borrower to
+    /// `target`, so a barely-underwater position only loses the bonus on
+    /// the collateral needed to make it safe again.
+    /// 
+    /// Goes through the timelock like `queue_param_change`; the change
+    /// takes effect via `execute_param_change` once the delay has elapsed.
+    /// 
+    /// # Arguments
+    /// * `target` - Health factor scaled by SCALE, between 1.0 and 2.0
+    ///   (1.1 = 11_000_000), or 0 to cap by the close factor alone
+    /// 
+    /// # Returns
+    /// Id of the queued change
+    pub fn set_liquidation_target_hf(env: Env, target: i128) -> u32 {
+        Self::queue_param_change(env, ParamChange::LiquidationTargetHf(target))
+    }
+
+    /// Get the liquidation target health factor (scaled by SCALE, 0 = uncapped)
+    pub fn get_liquidation_target_hf(env: Env) -> i128 {
+        env.storage().instance().get(&DataKey::LiquidationTargetHf).unwrap_or(0)
+    }
+
+    /// Enable or disable compound interest accrual (risk admin)
+    /// 
+    /// Enabled by default: each accrual grows the borrow index by
+    /// `(1 + rate_per_second)^time_elapsed` instead of the linear
+    /// `1 + rate * time_elapsed / year`, so infrequently poked markets
+    /// don't understate APY. Disabling falls back to the linear accrual.
+    pub fn set_compound_interest(env: Env, enabled: bool) {
+        Self::require_role(&env, Role::RiskAdmin);
+
+        Self::apply_param_change(&env, ParamChange::CompoundInterest(enabled));
+    }
+
+    /// Check whether compound interest accrual is enabled
+    pub fn is_compound_interest(env: Env) -> bool {
+        env.storage().instance().get(&DataKey::CompoundInterest).unwrap_or(true)
+    }
+
+    /// Get the flash loan fee (scaled by SCALE)
+    pub fn get_flash_loan_fee(env: Env) -> i128 {
+        env.storage()
+            .instance()
+            .get(&DataKey::FlashLoanFee)
+            .unwrap_or(DEFAULT_FLASH_LOAN_FEE)
+    }
+
+    /// Set the LTV headroom kept by `deposit_and_borrow`'s max borrow (risk admin)
+    /// 
+    /// # Arguments
+    /// * `buffer` - LTV points to stay below the limit, scaled by SCALE
+    ///   (5% = 500_000: at 75% LTV the zap borrows up to 70%)
+    pub fn set_zap_safety_buffer(env: Env, buffer: i128) {
+        Self::require_role(&env, Role::RiskAdmin);
+
+        Self::apply_param_change(&env, ParamChange::ZapSafetyBuffer(buffer));
+    }
+
+    /// Get the zap safety buffer (scaled by SCALE, default 0)
+    pub fn get_zap_safety_buffer(env: Env) -> i128 {
+        env.storage().instance().get(&DataKey::ZapSafetyBuffer).unwrap_or(0)
+    }
+
+    // ========================================================================
+    // MARKET LIFECYCLE
+    // ========================================================================
+
+    /// List a new market (owner only)
+    /// 
+    /// The asset joins the market list, so positions, health checks and
+    /// interest accrual pick it up immediately. Its oracle price must be
+    /// set before it can be valued. The token's `decimals()` is stored, so
+    /// amounts are valued correctly whatever its precision.
+    /// 
+    /// # Arguments
+    /// * `asset` - Asset symbol
+    /// * `token` - Token contract address
+    /// * `ltv` - Loan-to-value ratio (scaled by SCALE)
+    /// * `liquidation_threshold` - Liquidation threshold (scaled by SCALE)
+    /// * `collateral` - Whether the asset can be deposited as collateral
+    /// * `borrow` - Whether the asset can be borrowed
+    pub fn add_market(
+        env: Env,
+        asset: Symbol,
+        token: Address,
+        ltv: i128,
+        liquidation_threshold: i128,
+        collateral: bool,
+        borrow: bool,
+    ) {
+        Self::require_role(&env, Role::Owner);
+
+        if env.storage().instance().has(&DataKey::TokenAddress(asset.clone())) {
+            panic_with_error!(&env, PoolError::MarketAlreadyListed);
+        }
+        if ltv < 0 || ltv > liquidation_threshold || liquidation_threshold > SCALE {
+            panic_with_error!(&env, PoolError::InvalidParameter);
+        }
+
+        let decimals = token::Client::new(&env, &token).decimals();
+        if decimals > MAX_TOKEN_DECIMALS {
+            panic_with_error!(&env, PoolError::InvalidParameter);
+        }
+
+        env.storage().instance().set(&DataKey::TokenAddress(asset.clone()), &token);
+        env.storage().instance().set(&DataKey::Decimals(asset.clone()), &decimals);
+        Self::init_market(&env, asset.clone(), ltv, liquidation_threshold, collateral, borrow);
+
+        env.events().publish((symbol_short!("add_mkt"), asset), (token, ltv, liquidation_threshold));
+    }
+
+    /// Freeze a market for wind-down (risk admin)
+    /// 
+    /// Blocks new supplies, collateral deposits and borrows. Withdrawals,
+    /// repayments and liquidations keep working until the market is empty.
+    pub fn freeze_market(env: Env, asset: Symbol) {
+        Self::require_role(&env, Role::RiskAdmin);
+
+        if !env.storage().instance().has(&DataKey::TokenAddress(asset.clone())) {
+            panic_with_error!(&env, PoolError::MarketNotListed);
+        }
+
+        env.storage().instance().set(&DataKey::Frozen(asset.clone()), &true);
+
+        env.events().publish((symbol_short!("freeze"), asset), ());
+    }
+
+    /// Unfreeze a market (risk admin)
+    pub fn unfreeze_market(env: Env, asset: Symbol) {
+        Self::require_role(&env, Role::RiskAdmin);
+
+        env.storage().instance().remove(&DataKey::Frozen(asset.clone()));
+
+        env.events().publish((symbol_short!("unfreeze"), asset), ());
+    }
+
+    /// Check whether a market is frozen
+    pub fn is_frozen(env: Env, asset: Symbol) -> bool {
+        env.storage().instance().get(&DataKey::Frozen(asset)).unwrap_or(false)
+    }
+
+    /// Check whether a market is empty and can be removed
+    /// 
+    /// True only when total supply, shares, borrows, reserves, collateral,
+    /// cash and uncovered bad debt for the asset are all zero.
+    pub fn can_be_removed(env: Env, asset: Symbol) -> bool {
+        let total_supply: i128 = env.storage().instance().get(&DataKey::TotalSupply(asset.clone())).unwrap_or(0);
+        let total_shares: i128 = env.storage().instance().get(&DataKey::TotalShares(asset.clone())).unwrap_or(0);
+        let total_borrow: i128 = env.storage().instance().get(&DataKey::TotalBorrow(asset.clone())).unwrap_or(0);
+        let total_reserves: i128 = env.storage().instance().get(&DataKey::TotalReserves(asset.clone())).unwrap_or(0);
+        let total_collateral: i128 = env.storage().instance().get(&DataKey::TotalCollateral(asset.clone())).unwrap_or(0);
+        let cash: i128 = env.storage().instance().get(&DataKey::Cash(asset.clone())).unwrap_or(0);
+        let bad_debt: i128 = env.storage().instance().get(&DataKey::BadDebt(asset)).unwrap_or(0);
+
+        total_supply == 0
+            && total_shares == 0
+            && total_borrow == 0
+            && total_reserves == 0
+            && total_collateral == 0
+            && cash == 0
+            && bad_debt == 0
+    }
+
+    /// Remove a frozen, empty market and delete its storage (owner only)
+    pub fn remove_market(env: Env, asset: Symbol) {
+        Self::require_role(&env, Role::Owner);
+
+        let frozen: bool = env.storage().instance().get(&DataKey::Frozen(asset.clone())).unwrap_or(false);
+        if !frozen {
+            panic_with_error!(&env, PoolError::MarketNotFrozen);
+        }
+        if !Self::can_be_removed(env.clone(), asset.clone()) {
+            panic_with_error!(&env, PoolError::MarketNotEmpty);
+        }
+
+        for key in [
+            DataKey::TokenAddress(asset.clone()),
+            DataKey::LtvRatio(asset.clone()),
+            DataKey::LiquidationThreshold(asset.clone()),
+            DataKey::CollateralEnabled(asset.clone()),
+            DataKey::BorrowEnabled(asset.clone()),
+            DataKey::Frozen(asset.clone()),
+            DataKey::CloseFactor(asset.clone()),
+            DataKey::LiquidationBonus(asset.clone()),
+            DataKey::LiquidationProtocolFee(asset.clone()),
+            DataKey::SupplyCap(asset.clone()),
+            DataKey::BorrowCap(asset.clone()),
+            DataKey::MinBorrow(asset.clone()),
+            DataKey::DefaultBorrowLimit(asset.clone()),
+            DataKey::Isolated(asset.clone()),
+            DataKey::IsolationDebtCeiling(asset.clone()),
+            DataKey::StaleBlocksLiquidation(asset.clone()),
+            DataKey::FallbackPrice(asset.clone()),
+            DataKey::TotalSupply(asset.clone()),
+            DataKey::TotalShares(asset.clone()),
+            DataKey::TotalBorrow(asset.clone()),
+            DataKey::ExchangeRate(asset.clone()),
+            DataKey::BorrowIndex(asset.clone()),
+            DataKey::LastAccrualTime(asset.clone()),
+            DataKey::ReserveFactor(asset.clone()),
+            DataKey::TotalReserves(asset.clone()),
+            DataKey::TotalCollateral(asset.clone()),
+            DataKey::Cash(asset.clone()),
+            DataKey::Decimals(asset.clone()),
+            DataKey::TotalStableBorrow(asset.clone()),
+            DataKey::AccrualDust(asset.clone()),
+            DataKey::BadDebt(asset.clone()),
+            DataKey::BadDebtCovered(asset.clone()),
+        ] {
+            env.storage().instance().remove(&key);
+        }
+
+        let mut markets = Self::get_market_list(&env);
+        if let Some(index) = markets.first_index_of(&asset) {
+            markets.remove(index);
+        }
+        env.storage().instance().set(&DataKey::MarketList, &markets);
+
+        env.events().publish((symbol_short!("rm_mkt"), asset), ());
+    }
+
+    // ========================================================================
+    // PERMISSIONED MODE
+    // ========================================================================
+
+    /// Enable or disable permissioned mode (owner only)
+    /// 
+    /// While enabled, `supply`, `deposit_collateral` and `borrow` are limited
+    /// to whitelisted users. Exits (withdrawals, repayments, liquidations)
+    /// stay open to everyone so no funds get stuck.
+    pub fn set_permissioned_mode(env: Env, enabled: bool) {
+        Self::require_role(&env, Role::Owner);
+
+        env.storage().instance().set(&DataKey::PermissionedMode, &enabled);
+
+        env.events().publish((symbol_short!("perm_mode"),), enabled);
+    }
+
+    /// Check whether permissioned mode is enabled
+    pub fn is_permissioned(env: Env) -> bool {
+        env.storage().instance().get(&DataKey::PermissionedMode).unwrap_or(false)
+    }
+
+    /// Whitelist a user (owner only)
+    pub fn add_to_whitelist(env: Env, user: Address) {
+        Self::require_role(&env, Role::Owner);
+
+        Self::set_whitelisted(&env, user, true);
+    }
+
+    /// Remove a user from the whitelist (owner only)
+    pub fn remove_from_whitelist(env: Env, user: Address) {
+        Self::require_role(&env, Role::Owner);
+
+        Self::set_whitelisted(&env, user, false);
+    }
+
+    /// Whitelist several users at once (owner only)
+    pub fn add_to_whitelist_batch(env: Env, users: Vec<Address>) {
+        Self::require_role(&env, Role::Owner);
+
+        for user in users.iter() {
+            Self::set_whitelisted(&env, user, true);
+        }
+    }
+
+    /// Remove several users from the whitelist at once (owner only)
+    pub fn remove_from_whitelist_batch(env: Env, users: Vec<Address>) {
+        Self::require_role(&env, Role::Owner);
+
+        for user in users.iter() {
+            Self::set_whitelisted(&env, user, false);
+        }
+    }
+
+    /// Check whether a user is whitelisted
+    pub fn is_whitelisted(env: Env, user: Address) -> bool {
+        env.storage().persistent().get(&DataKey::Whitelisted(user)).unwrap_or(false)
+    }
+
+    /// Internal: Update a user's whitelist flag and emit the matching event
+    fn set_whitelisted(env: &Env, user: Address, whitelisted: bool) {
+        if whitelisted {
+            env.storage().persistent().set(&DataKey::Whitelisted(user.clone()), &true);
+            env.events().publish((symbol_short!("wl_add"), user), ());
+        } else {
+            env.storage().persistent().remove(&DataKey::Whitelisted(user.clone()));
+            env.events().publish((symbol_short!("wl_remove"), user), ());
+        }
+    }
+
+    /// Internal: Reject non-whitelisted users while permissioned mode is on
+    fn require_whitelisted(env: &Env, user: &Address) {
+        let permissioned: bool = env.storage().instance().get(&DataKey::PermissionedMode).unwrap_or(false);
+        if !permissioned {
+            return;
+        }
+
+        let whitelisted: bool = env
+            .storage()
+            .persistent()
+            .get(&DataKey::Whitelisted(user.clone()))
+            .unwrap_or(false);
+        if !whitelisted {
+            panic_with_error!(env, PoolError::NotWhitelisted);
         }
+    }
 
-        // Accrue interest before state changes
-        Self::accrue_interest(&env, asset.clone());
+    // ========================================================================
+    // TIMELOCK
+    // ========================================================================
 
-        // Check pool has sufficient liquidity
-        let total_supply: i128 = env.storage().instance().get(&DataKey::TotalSupply(asset.clone())).unwrap_or(0);
-        let total_borrow: i128 = env.storage().instance().get(&DataKey::TotalBorrow(asset.clone())).unwrap_or(0);
-        let available_liquidity = total_supply - total_borrow;
-        if available_liquidity < amount {
-            panic!("Insufficient pool liquidity");
-        }
+    /// Queue a parameter change behind the timelock (role depends on the change)
+    /// 
+    /// The change is validated now and can be executed once the configured
+    /// delay has elapsed, giving users time to react before risk parameters
+    /// or external contracts change.
+    /// 
+    /// # Arguments
+    /// * `change` - The parameter change to queue
+    /// 
+    /// # Returns
+    /// Id of the queued change
+    pub fn queue_param_change(env: Env, change: ParamChange) -> u32 {
+        Self::require_role(&env, Self::param_change_role(&change));
 
-        // Get current user position
-        let position = Self::get_user_position(env.clone(), user.clone());
+        Self::validate_param_change(&env, &change);
 
-        // Get borrow amount in USD
-        let oracle: Address = env.storage().instance().get(&DataKey::PriceOracle).unwrap();
-        let asset_price = Self::get_asset_price(&env, &oracle, &asset);
-        let borrow_value_usd = (amount * asset_price) / SCALE;
+        let delay: u64 = env
+            .storage()
+            .instance()
+            .get(&DataKey::TimelockDelay)
+            .unwrap_or(DEFAULT_TIMELOCK_DELAY);
+        let eta = env.ledger().timestamp() + delay;
+
+        let id: u32 = env.storage().instance().get(&DataKey::NextParamChangeId).unwrap_or(0);
+        env.storage().instance().set(&DataKey::NextParamChangeId, &(id + 1));
+        env.storage().persistent().set(
+            &DataKey::QueuedParamChange(id),
+            &QueuedParamChange { change: change.clone(), eta },
+        );
 
-        // Check LTV constraint: new_total_debt <= collateral * LTV
-        let new_total_debt_usd = position.debt_value_usd + borrow_value_usd;
-        if new_total_debt_usd > position.available_borrow_usd + position.debt_value_usd {
-            panic!("Borrow exceeds LTV limit");
-        }
+        env.events().publish((symbol_short!("pc_queue"), id), (change, eta));
+
+        id
+    }
 
-        // Update user's debt balance
-        let current_debt: i128 = env
+    /// Execute a queued parameter change once its eta has passed (role depends on the change)
+    /// 
+    /// # Errors
+    /// * `PoolError::ParamChangeNotFound` - No queued change with this id
+    /// * `PoolError::TimelockNotReady` - The delay has not elapsed yet
+    pub fn execute_param_change(env: Env, id: u32) {
+        let queued: QueuedParamChange = env
             .storage()
             .persistent()
-            .get(&DataKey::UserDebt(user.clone(), asset.clone()))
-            .unwrap_or(0);
-        env.storage()
-            .persistent()
-            .set(&DataKey::UserDebt(user.clone(), asset.clone()), &(current_debt + amount));
+            .get(&DataKey::QueuedParamChange(id))
+            .unwrap_or_else(|| panic_with_error!(&env, PoolError::ParamChangeNotFound));
+        Self::require_role(&env, Self::param_change_role(&queued.change));
 
-        // Store user's borrow index for interest calculation
-        let borrow_index: i128 = env.storage().instance().get(&DataKey::BorrowIndex(asset.clone())).unwrap();
-        env.storage()
+        if env.ledger().timestamp() < queued.eta {
+            panic_with_error!(&env, PoolError::TimelockNotReady);
+        }
+
+        env.storage().persistent().remove(&DataKey::QueuedParamChange(id));
+        Self::apply_param_change(&env, queued.change.clone());
+
+        env.events().publish((symbol_short!("pc_exec"), id), queued.change);
+    }
+
+    /// Cancel a queued parameter change (role depends on the change)
+    /// 
+    /// # Errors
+    /// * `PoolError::ParamChangeNotFound` - No queued change with this id
+    pub fn cancel_param_change(env: Env, id: u32) {
+        let queued: QueuedParamChange = env
+            .storage()
             .persistent()
-            .set(&DataKey::UserBorrowIndex(user.clone(), asset.clone()), &borrow_index);
+            .get(&DataKey::QueuedParamChange(id))
+            .unwrap_or_else(|| panic_with_error!(&env, PoolError::ParamChangeNotFound));
+        Self::require_role(&env, Self::param_change_role(&queued.change));
+        env.storage().persistent().remove(&DataKey::QueuedParamChange(id));
 
-        // Update total borrow
-        env.storage().instance().set(&DataKey::TotalBorrow(asset.clone()), &(total_borrow + amount));
+        env.events().publish((symbol_short!("pc_cancel"), id), queued.change);
+    }
 
-        // Transfer underlying from pool to user
-        let token_address: Address = env.storage().instance().get(&DataKey::TokenAddress(asset.clone())).unwrap();
-        let token_client = token::Client::new(&env, &token_address);
-        token_client.transfer(&env.current_contract_address(), &user, &amount);
+    /// Get a queued parameter change, if still pending
+    pub fn get_param_change(env: Env, id: u32) -> Option<QueuedParamChange> {
+        env.storage().persistent().get(&DataKey::QueuedParamChange(id))
+    }
 
-        // Emit event
-        env.events().publish((symbol_short!("borrow"), user, asset), amount);
+    /// Get the timelock delay in seconds
+    pub fn get_timelock_delay(env: Env) -> u64 {
+        env.storage()
+            .instance()
+            .get(&DataKey::TimelockDelay)
+            .unwrap_or(DEFAULT_TIMELOCK_DELAY)
+    }
 
-        amount
+    /// Internal: Role allowed to queue, execute or cancel a parameter change
+    fn param_change_role(change: &ParamChange) -> Role {
+        match change {
+            ParamChange::PriceOracle(_)
+            | ParamChange::InterestRateModel(_)
+            | ParamChange::Treasury(_)
+            | ParamChange::TimelockDelay(_) => Role::Owner,
+            _ => Role::RiskAdmin,
+        }
     }
 
-    /// Repay borrowed assets
-    /// 
-    /// Reduces user's debt balance and pool's total borrows.
+    /// Internal: Check a parameter change's bounds
     /// 
-    /// # Arguments
-    /// * `user` - The borrower's address
-    /// * `asset` - Asset symbol
-    /// * `amount` - Amount to repay (use i128::MAX to repay all)
-    /// 
-    /// # Returns
-    /// Actual amount repaid
-    pub fn repay(env: Env, user: Address, asset: Symbol, amount: i128) -> i128 {
-        user.require_auth();
-        
-        if amount <= 0 {
-            panic!("Amount must be positive");
+    /// External contracts (oracle, rate model) are probed when the change
+    /// is applied, since their state may change while it is queued.
+    fn validate_param_change(env: &Env, change: &ParamChange) {
+        match change {
+            ParamChange::CollateralParams(_, ltv, liquidation_threshold) => {
+                if *ltv < 0 || ltv > liquidation_threshold || *liquidation_threshold > SCALE {
+                    panic_with_error!(env, PoolError::InvalidParameter);
+                }
+            }
+            ParamChange::ReserveFactor(_, reserve_factor) => {
+                if *reserve_factor < 0 || *reserve_factor > SCALE {
+                    panic_with_error!(env, PoolError::InvalidParameter);
+                }
+            }
+            ParamChange::CloseFactor(_, close_factor) => {
+                if *close_factor <= 0 || *close_factor > SCALE {
+                    panic_with_error!(env, PoolError::InvalidParameter);
+                }
+            }
+            ParamChange::LiquidationBonus(_, liquidation_bonus) => {
+                if *liquidation_bonus < 0 || *liquidation_bonus > MAX_LIQUIDATION_BONUS {
+                    panic_with_error!(env, PoolError::InvalidParameter);
+                }
+            }
+            ParamChange::LiquidationProtocolFee(_, fee) => {
+                if *fee < 0 || *fee > SCALE {
+                    panic_with_error!(env, PoolError::InvalidParameter);
+                }
+            }
+            ParamChange::SupplyCap(_, cap) | ParamChange::BorrowCap(_, cap) => {
+                if *cap < 0 {
+                    panic_with_error!(env, PoolError::InvalidParameter);
+                }
+            }
+            ParamChange::MinBorrow(_, min_borrow) => {
+                if *min_borrow < 0 {
+                    panic_with_error!(env, PoolError::InvalidParameter);
+                }
+            }
+            ParamChange::DefaultBorrowLimit(_, limit) | ParamChange::UserBorrowLimit(_, _, limit) => {
+                if *limit < 0 {
+                    panic_with_error!(env, PoolError::InvalidParameter);
+                }
+            }
+            ParamChange::IsolationMode(_, _, debt_ceiling) => {
+                if *debt_ceiling < 0 {
+                    panic_with_error!(env, PoolError::InvalidParameter);
+                }
+            }
+            ParamChange::FlashLoanFee(fee) => {
+                if *fee < 0 || *fee > SCALE {
+                    panic_with_error!(env, PoolError::InvalidParameter);
+                }
+            }
+            ParamChange::ZapSafetyBuffer(buffer) => {
+                if *buffer < 0 || *buffer > SCALE {
+                    panic_with_error!(env, PoolError::InvalidParameter);
+                }
+            }
+            ParamChange::FallbackPrice(_, price) => {
+                if *price <= 0 {
+                    panic_with_error!(env, PoolError::InvalidParameter);
+                }
+            }
+            ParamChange::LiquidationTargetHf(target) => {
+                if *target != 0 && (*target < SCALE || *target > 2 * SCALE) {
+                    panic_with_error!(env, PoolError::InvalidParameter);
+                }
+            }
+            ParamChange::TimelockDelay(delay) => {
+                if *delay > MAX_TIMELOCK_DELAY {
+                    panic_with_error!(env, PoolError::InvalidParameter);
+                }
+            }
+            ParamChange::PriceOracle(_)
+            | ParamChange::InterestRateModel(_)
+            | ParamChange::StrictOracle(_)
+            | ParamChange::PoolMaxPriceAge(_)
+            | ParamChange::StaleBlocksLiquidation(_, _)
+            | ParamChange::AllowFallback(_)
+            | ParamChange::Treasury(_)
+            | ParamChange::TargetedLiquidation(_)
+            | ParamChange::CompoundInterest(_) => {}
         }
+    }
 
-        // Accrue interest before state changes
-        Self::accrue_interest(&env, asset.clone());
+    /// Internal: Validate and apply a parameter change, emitting its event
+    /// 
+    /// Shared by the direct admin setters and `execute_param_change`.
+    /// Callers are responsible for authorization.
+    fn apply_param_change(env: &Env, change: ParamChange) {
+        Self::validate_param_change(env, &change);
+
+        match change {
+            ParamChange::PriceOracle(new_oracle) => {
+                // Never point the pool at a dead or uninitialized oracle, or
+                // one that can't price every listed market
+                let oracle_client = oracle_contract::Client::new(env, &new_oracle);
+                for asset in Self::get_market_list(env).iter() {
+                    if oracle_client.get_price(&asset) == 0 {
+                        panic_with_error!(env, PoolError::PriceUnavailable);
+                    }
+                }
+
+                env.storage().instance().set(&DataKey::PriceOracle, &new_oracle);
+
+                env.events().publish((Symbol::new(env, "set_oracle"),), new_oracle);
+            }
+            ParamChange::InterestRateModel(new_model) => {
+                let (_, _, rate_max, optimal_utilization) =
+                    rate_model_contract::Client::new(env, &new_model).get_parameters();
+                if rate_max <= 0 || optimal_utilization <= 0 || optimal_utilization >= SCALE {
+                    panic_with_error!(env, PoolError::InvalidParameter);
+                }
+
+                // Settle interest at the old rates
+                for asset in Self::get_market_list(env).iter() {
+                    Self::accrue_interest(env, asset);
+                }
+
+                env.storage().instance().set(&DataKey::InterestRateModel, &new_model);
+                env.storage().instance().set(&DataKey::UseRateModel, &true);
+
+                env.events().publish((symbol_short!("set_irm"),), new_model);
+            }
+            ParamChange::CollateralParams(asset, ltv, liquidation_threshold) => {
+                env.storage().instance().set(&DataKey::LtvRatio(asset.clone()), &ltv);
+                env.storage().instance().set(&DataKey::LiquidationThreshold(asset.clone()), &liquidation_threshold);
 
-        // Get user's current debt (including accrued interest)
-        let user_debt = Self::get_user_debt_with_interest(&env, user.clone(), asset.clone());
-        
-        if user_debt == 0 {
-            panic!("No outstanding debt");
-        }
+                env.events().publish((symbol_short!("coll_cfg"), asset), (ltv, liquidation_threshold));
+            }
+            ParamChange::ReserveFactor(asset, reserve_factor) => {
+                // Accrue at the old factor before the change takes effect
+                Self::accrue_interest(env, asset.clone());
+                env.storage().instance().set(&DataKey::ReserveFactor(asset.clone()), &reserve_factor);
 
-        // Cap repayment at outstanding debt
-        let repay_amount = if amount > user_debt { user_debt } else { amount };
+                env.events().publish((symbol_short!("rf_cfg"), asset), reserve_factor);
+            }
+            ParamChange::CloseFactor(asset, close_factor) => {
+                env.storage().instance().set(&DataKey::CloseFactor(asset.clone()), &close_factor);
 
-        // Transfer underlying from user to pool
-        let token_address: Address = env.storage().instance().get(&DataKey::TokenAddress(asset.clone())).unwrap();
-        let token_client = token::Client::new(&env, &token_address);
-        token_client.transfer(&user, &env.current_contract_address(), &repay_amount);
+                env.events().publish((symbol_short!("cf_cfg"), asset), close_factor);
+            }
+            ParamChange::LiquidationBonus(asset, liquidation_bonus) => {
+                env.storage().instance().set(&DataKey::LiquidationBonus(asset.clone()), &liquidation_bonus);
 
-        // Update user's debt balance
-        let current_debt: i128 = env
-            .storage()
-            .persistent()
-            .get(&DataKey::UserDebt(user.clone(), asset.clone()))
-            .unwrap_or(0);
-        let new_debt = if repay_amount >= user_debt { 0 } else { current_debt - repay_amount };
-        env.storage()
-            .persistent()
-            .set(&DataKey::UserDebt(user.clone(), asset.clone()), &new_debt);
+                env.events().publish((symbol_short!("lb_cfg"), asset), liquidation_bonus);
+            }
+            ParamChange::LiquidationProtocolFee(asset, fee) => {
+                env.storage().instance().set(&DataKey::LiquidationProtocolFee(asset.clone()), &fee);
 
-        // Update total borrow
-        let total_borrow: i128 = env.storage().instance().get(&DataKey::TotalBorrow(asset.clone())).unwrap_or(0);
-        let new_total_borrow = if total_borrow > repay_amount { total_borrow - repay_amount } else { 0 };
-        env.storage().instance().set(&DataKey::TotalBorrow(asset.clone()), &new_total_borrow);
+                env.events().publish((symbol_short!("lpf_cfg"), asset), fee);
+            }
+            ParamChange::SupplyCap(asset, cap) => {
+                env.storage().instance().set(&DataKey::SupplyCap(asset.clone()), &cap);
 
-        // Emit event
-        env.events().publish((symbol_short!("repay"), user, asset), repay_amount);
+                env.events().publish((symbol_short!("sup_cap"), asset), cap);
+            }
+            ParamChange::BorrowCap(asset, cap) => {
+                env.storage().instance().set(&DataKey::BorrowCap(asset.clone()), &cap);
 
-        repay_amount
+                env.events().publish((symbol_short!("bor_cap"), asset), cap);
+            }
+            ParamChange::MinBorrow(asset, min_borrow) => {
+                env.storage().instance().set(&DataKey::MinBorrow(asset.clone()), &min_borrow);
+
+                env.events().publish((symbol_short!("min_bor"), asset), min_borrow);
+            }
+            ParamChange::DefaultBorrowLimit(asset, limit) => {
+                env.storage().instance().set(&DataKey::DefaultBorrowLimit(asset.clone()), &limit);
+
+                env.events().publish((symbol_short!("bor_limit"), asset), limit);
+            }
+            ParamChange::UserBorrowLimit(user, asset, limit) => {
+                env.storage()
+                    .persistent()
+                    .set(&DataKey::UserBorrowLimit(user.clone(), asset.clone()), &limit);
+
+                env.events().publish((symbol_short!("usr_limit"), user, asset), limit);
+            }
+            ParamChange::IsolationMode(asset, isolated, debt_ceiling) => {
+                env.storage().instance().set(&DataKey::Isolated(asset.clone()), &isolated);
+                env.storage().instance().set(&DataKey::IsolationDebtCeiling(asset.clone()), &debt_ceiling);
+
+                env.events().publish((symbol_short!("isolation"), asset), (isolated, debt_ceiling));
+            }
+            ParamChange::StrictOracle(enabled) => {
+                env.storage().instance().set(&DataKey::StrictOracle, &enabled);
+
+                env.events().publish((symbol_short!("strict"),), enabled);
+            }
+            ParamChange::PoolMaxPriceAge(max_age) => {
+                env.storage().instance().set(&DataKey::PoolMaxPriceAge, &max_age);
+
+                env.events().publish((symbol_short!("max_age"),), max_age);
+            }
+            ParamChange::StaleBlocksLiquidation(asset, enabled) => {
+                env.storage().instance().set(&DataKey::StaleBlocksLiquidation(asset.clone()), &enabled);
+
+                env.events().publish((symbol_short!("stale_liq"), asset), enabled);
+            }
+            ParamChange::AllowFallback(enabled) => {
+                env.storage().instance().set(&DataKey::AllowFallback, &enabled);
+
+                env.events().publish((symbol_short!("fallback"),), enabled);
+            }
+            ParamChange::FallbackPrice(asset, price) => {
+                env.storage().instance().set(&DataKey::FallbackPrice(asset.clone()), &price);
+
+                env.events().publish((symbol_short!("fb_price"), asset), price);
+            }
+            ParamChange::Treasury(treasury) => {
+                env.storage().instance().set(&DataKey::Treasury, &treasury);
+
+                env.events().publish((symbol_short!("treasury"),), treasury);
+            }
+            ParamChange::FlashLoanFee(fee) => {
+                env.storage().instance().set(&DataKey::FlashLoanFee, &fee);
+
+                env.events().publish((symbol_short!("fl_fee"),), fee);
+            }
+            ParamChange::ZapSafetyBuffer(buffer) => {
+                env.storage().instance().set(&DataKey::ZapSafetyBuffer, &buffer);
+
+                env.events().publish((symbol_short!("zap_buf"),), buffer);
+            }
+            ParamChange::TargetedLiquidation(enabled) => {
+                env.storage().instance().set(&DataKey::TargetedLiquidation, &enabled);
+
+                env.events().publish((symbol_short!("tgt_liq"),), enabled);
+            }
+            ParamChange::LiquidationTargetHf(target) => {
+                env.storage().instance().set(&DataKey::LiquidationTargetHf, &target);
+
+                env.events().publish((symbol_short!("liq_tgt"),), target);
+            }
+            ParamChange::CompoundInterest(enabled) => {
+                // Settle interest under the old accrual mode
+                for asset in Self::get_market_list(env).iter() {
+                    Self::accrue_interest(env, asset);
+                }
+
+                env.storage().instance().set(&DataKey::CompoundInterest, &enabled);
+
+                env.events().publish((symbol_short!("compound"),), enabled);
+            }
+            ParamChange::TimelockDelay(delay) => {
+                env.storage().instance().set(&DataKey::TimelockDelay, &delay);
+
+                env.events().publish((symbol_short!("tl_delay"),), delay);
+            }
+        }
     }
 
     // ========================================================================
     // INTEREST ACCRUAL
     // ========================================================================
 
+    /// Accrue interest on every listed market
+    /// 
+    /// Lets keepers and indexers bring all markets up to date in one call
+    /// without touching any balances. Emits an `accrue` event for every
+    /// market, carrying its borrow index even when nothing accrued.
+    pub fn accrue_all(env: Env) {
+        for asset in Self::get_market_list(&env).iter() {
+            Self::accrue_market(&env, asset, true);
+        }
+    }
+
+    /// Internal: Publish an operation event with topics `(name, EVENT_VERSION)`
+    fn publish_event<T: IntoVal<Env, Val>>(env: &Env, name: Symbol, data: T) {
+        env.events().publish((name, EVENT_VERSION), data);
+    }
+
+    /// Internal: Accrue interest on every market where the user has debt
+    fn accrue_debt_markets(env: &Env, user: &Address) {
+        for asset in Self::get_market_list(env).iter() {
+            if env.storage().persistent().has(&DataKey::UserDebt(user.clone(), asset.clone())) {
+                Self::accrue_interest(env, asset);
+            }
+        }
+    }
+
     /// Accrue interest for an asset market
     /// 
     /// This function is called before any state-changing operation to ensure
@@ -592,72 +3851,146 @@ impl LendingPool {
     /// - Base rate: 0%, Slope1: 4%, Slope2: 75%, Optimal: 80%
     /// - For MVP, we use an internal fallback that mimics the external model
     fn accrue_interest(env: &Env, asset: Symbol) {
-        // Get timestamps
+        Self::accrue_market(env, asset, false);
+    }
+
+    /// Internal: Accrue a market, publishing its `accrue` event when
+    /// interest was charged or `always_emit` is set
+    fn accrue_market(env: &Env, asset: Symbol, always_emit: bool) {
         let last_accrual: u64 = env
             .storage()
             .instance()
             .get(&DataKey::LastAccrualTime(asset.clone()))
             .unwrap_or(0);
         let current_time = env.ledger().timestamp();
-        
-        // Skip if no time has passed
-        if current_time <= last_accrual {
-            return;
+
+        let state = Self::project_accrual(env, &asset);
+
+        // Skip the writes if no time has passed
+        if current_time > last_accrual {
+            if state.interest_accrued > 0 || state.borrow_index != state.previous_borrow_index {
+                env.storage().instance().set(&DataKey::BorrowIndex(asset.clone()), &state.borrow_index);
+                env.storage().instance().set(&DataKey::TotalSupply(asset.clone()), &state.total_supply);
+                env.storage().instance().set(&DataKey::TotalBorrow(asset.clone()), &state.total_borrow);
+                env.storage().instance().set(&DataKey::TotalReserves(asset.clone()), &state.total_reserves);
+                env.storage().instance().set(&DataKey::AccrualDust(asset.clone()), &state.accrual_dust);
+            }
+
+            // Update last accrual timestamp
+            env.storage().instance().set(&DataKey::LastAccrualTime(asset.clone()), &current_time);
+            Self::debug_check_solvency(env, &asset);
         }
 
-        let time_elapsed = current_time - last_accrual;
-        
+        if state.interest_accrued > 0 || always_emit {
+            // Nothing accrued: report the market's current rates instead
+            let (borrow_rate, utilization) = if state.interest_accrued > 0 {
+                (state.borrow_rate, state.utilization)
+            } else {
+                (
+                    Self::get_borrow_rate(env.clone(), asset.clone()),
+                    Self::get_utilization_rate(env.clone(), asset.clone()),
+                )
+            };
+            Self::publish_event(
+                env,
+                symbol_short!("accrue"),
+                AccrueEvent {
+                    asset: asset.clone(),
+                    borrow_index: state.borrow_index,
+                    borrow_rate,
+                    supply_rate: Self::get_supply_rate(env.clone(), asset),
+                    reserves_delta: state.reserve_interest,
+                    interest_accrued: state.interest_accrued,
+                    utilization,
+                },
+            );
+        }
+    }
+
+    /// Project a market's interest accrual to the current ledger timestamp
+    /// 
+    /// Pure computation shared by `accrue_interest`, which persists the
+    /// result, and the preview/view functions, which must not write storage.
+    fn project_accrual(env: &Env, asset: &Symbol) -> AccrualState {
         // Get current pool state
         let total_supply: i128 = env.storage().instance().get(&DataKey::TotalSupply(asset.clone())).unwrap_or(0);
         let total_borrow: i128 = env.storage().instance().get(&DataKey::TotalBorrow(asset.clone())).unwrap_or(0);
-        
-        // Skip if nothing to accrue on
-        if total_borrow == 0 || total_supply == 0 {
-            env.storage().instance().set(&DataKey::LastAccrualTime(asset.clone()), &current_time);
-            return;
+        let total_reserves: i128 = env.storage().instance().get(&DataKey::TotalReserves(asset.clone())).unwrap_or(0);
+        let borrow_index: i128 = env
+            .storage()
+            .instance()
+            .get(&DataKey::BorrowIndex(asset.clone()))
+            .unwrap_or(INITIAL_EXCHANGE_RATE);
+        let accrual_dust: i128 = env
+            .storage()
+            .instance()
+            .get(&DataKey::AccrualDust(asset.clone()))
+            .unwrap_or(0);
+
+        let mut state = AccrualState {
+            total_supply,
+            total_borrow,
+            total_reserves,
+            previous_borrow_index: borrow_index,
+            borrow_index,
+            accrual_dust,
+            interest_accrued: 0,
+            reserve_interest: 0,
+            utilization: 0,
+            borrow_rate: 0,
+        };
+
+        // Get timestamps
+        let last_accrual: u64 = env
+            .storage()
+            .instance()
+            .get(&DataKey::LastAccrualTime(asset.clone()))
+            .unwrap_or(0);
+        let current_time = env.ledger().timestamp();
+
+        // Skip if no time has passed or nothing to accrue on
+        if current_time <= last_accrual || total_borrow == 0 || total_supply == 0 {
+            return state;
         }
 
+        let time_elapsed = current_time - last_accrual;
+
         // ====================================================================
         // STEP 1: Calculate utilization rate
         // ====================================================================
         // Utilization = Total Borrowed / Total Supplied
         // Scaled by SCALE (1e7), so 80% = 8_000_000
-        let utilization = (total_borrow * SCALE) / total_supply;
+        let utilization = mul_div_floor(env, total_borrow, SCALE, total_supply);
 
         // ====================================================================
         // STEP 2: Get borrow rate from Interest Rate Model
         // ====================================================================
-        // For MVP, we use an internal implementation that matches the kinked model:
-        // - Base rate: 0%
-        // - Below 80% utilization: rate = (utilization / 80%) * 4%
-        // - Above 80%: rate = 4% + ((utilization - 80%) / 20%) * 75%
-        let annual_borrow_rate = Self::calculate_borrow_rate(utilization);
-        
-        // Convert annual rate to rate for elapsed time
-        // interest_factor = annual_rate * time_elapsed / seconds_per_year
-        let seconds_per_year: i128 = 31_557_600; // 365.25 days
-        let interest_factor = (annual_borrow_rate * time_elapsed as i128) / seconds_per_year;
+        // Uses the external model once one has been validated via
+        // `set_interest_rate_model`, otherwise the internal implementation
+        // that matches the default multi-kink curve.
+        let annual_borrow_rate = Self::borrow_rate_at(env, utilization);
 
         // ====================================================================
         // STEP 3: Update borrow index
         // ====================================================================
         // The borrow index tracks accumulated interest over time
         // User debt = principal * current_index / user_index_at_borrow
-        let current_borrow_index: i128 = env
+        state.borrow_index = Self::grow_borrow_index(env, borrow_index, utilization, annual_borrow_rate, time_elapsed);
+
+        // Borrows grow by exactly the index growth, rounded up like each
+        // borrower's debt, so a lone borrower's debt and total borrows never
+        // drift apart. Stable-rate debt accrues at its own fixed rate instead.
+        let total_stable_borrow: i128 = env
             .storage()
             .instance()
-            .get(&DataKey::BorrowIndex(asset.clone()))
-            .unwrap_or(INITIAL_EXCHANGE_RATE);
-        
-        // new_index = current_index * (1 + interest_factor)
-        let new_borrow_index = current_borrow_index + (current_borrow_index * interest_factor) / SCALE;
-        env.storage().instance().set(&DataKey::BorrowIndex(asset.clone()), &new_borrow_index);
+            .get(&DataKey::TotalStableBorrow(asset.clone()))
+            .unwrap_or(0);
+        let variable_borrow = (total_borrow - total_stable_borrow).max(0);
+        let interest_accrued = mul_div_ceil(env, variable_borrow, state.borrow_index - borrow_index, borrow_index);
 
         // ====================================================================
-        // STEP 4: Calculate and distribute interest
+        // STEP 4: Distribute interest
         // ====================================================================
-        // Total interest accrued on all borrows
-        let interest_accrued = (total_borrow * interest_factor) / SCALE;
 
         // Split between suppliers and protocol reserves
         let reserve_factor: i128 = env
@@ -666,22 +3999,148 @@ impl LendingPool {
             .get(&DataKey::ReserveFactor(asset.clone()))
             .unwrap_or(1_000_000); // Default 10%
         
-        let reserve_interest = (interest_accrued * reserve_factor) / SCALE;
-        let supplier_interest = interest_accrued - reserve_interest;
+        // Supplier share is computed with the rounding remainder carried over
+        // from previous accruals, so truncation never compounds in favor of
+        // either side: cumulative supplier interest stays exact to one unit.
+        // The remainder of the split only depends on the low digits of the
+        // interest, so it is taken from those and the product never overflows.
+        let supplier_share = mul_div_floor(env, interest_accrued, SCALE - reserve_factor, SCALE);
+        let remainder = (interest_accrued % SCALE) * (SCALE - reserve_factor) % SCALE + accrual_dust;
+        let supplier_interest = supplier_share + remainder / SCALE;
+        let reserve_interest = interest_accrued - supplier_interest;
+
+        // Borrows grow by the full interest; the supplier's portion grows
+        // total supply (and sToken value), the rest goes to protocol reserves.
+        // Idle cash is unchanged: no tokens move during accrual.
+        state.total_supply = total_supply + supplier_interest;
+        state.total_borrow = total_borrow + interest_accrued;
+        state.total_reserves = total_reserves + reserve_interest;
+        state.accrual_dust = remainder % SCALE;
+        state.interest_accrued = interest_accrued;
+        state.reserve_interest = reserve_interest;
+        state.utilization = utilization;
+        state.borrow_rate = annual_borrow_rate;
+
+        state
+    }
 
-        // Increase total supply by supplier's portion (this grows sToken value)
-        env.storage().instance().set(&DataKey::TotalSupply(asset.clone()), &(total_supply + supplier_interest));
-        
-        // Increase protocol reserves
-        let current_reserves: i128 = env
-            .storage()
-            .instance()
-            .get(&DataKey::TotalReserves(asset.clone()))
-            .unwrap_or(0);
-        env.storage().instance().set(&DataKey::TotalReserves(asset.clone()), &(current_reserves + reserve_interest));
+    /// Grow a borrow index over `time_elapsed` at `annual_borrow_rate`,
+    /// compounding per second unless compound interest is disabled
+    fn grow_borrow_index(env: &Env, borrow_index: i128, utilization: i128, annual_borrow_rate: i128, time_elapsed: u64) -> i128 {
+        let compound: bool = env.storage().instance().get(&DataKey::CompoundInterest).unwrap_or(true);
+        if compound {
+            // new_index = current_index * (1 + rate_per_second)^time_elapsed
+            // growth is scaled by 1e9
+            let growth = Self::compound_factor_at(env, utilization, annual_borrow_rate, time_elapsed);
+            mul_div_floor(env, borrow_index, growth, INITIAL_EXCHANGE_RATE)
+        } else {
+            // Convert annual rate to rate for elapsed time
+            // interest_factor = annual_rate * time_elapsed / seconds_per_year
+            let interest_factor = mul_div_floor(env, annual_borrow_rate, time_elapsed as i128, Self::seconds_per_year(env));
+
+            // new_index = current_index * (1 + interest_factor)
+            borrow_index + mul_div_floor(env, borrow_index, interest_factor, SCALE)
+        }
+    }
+
+    /// Get the annual borrow rate for a utilization from the active rate source
+    fn borrow_rate_at(env: &Env, utilization: i128) -> i128 {
+        let use_rate_model: bool = env.storage().instance().get(&DataKey::UseRateModel).unwrap_or(false);
+        if use_rate_model {
+            let model: Address = env.storage().instance().get(&DataKey::InterestRateModel).unwrap();
+            rate_model_contract::Client::new(env, &model).get_borrow_rate(&utilization)
+        } else {
+            Self::calculate_borrow_rate(utilization)
+        }
+    }
+
+    /// Get the length of the accrual year in seconds from the active rate
+    /// source, so per-second conversions match the model's
+    fn seconds_per_year(env: &Env) -> i128 {
+        let use_rate_model: bool = env.storage().instance().get(&DataKey::UseRateModel).unwrap_or(false);
+        if use_rate_model {
+            let model: Address = env.storage().instance().get(&DataKey::InterestRateModel).unwrap();
+            rate_model_contract::Client::new(env, &model).get_seconds_per_year()
+        } else {
+            SECONDS_PER_YEAR
+        }
+    }
+
+    /// Get the compound growth factor `(1 + rate_per_second)^time_elapsed`
+    /// (scaled by 1e9) from the active rate source
+    fn compound_factor_at(env: &Env, utilization: i128, annual_borrow_rate: i128, time_elapsed: u64) -> i128 {
+        let use_rate_model: bool = env.storage().instance().get(&DataKey::UseRateModel).unwrap_or(false);
+        if use_rate_model {
+            let model: Address = env.storage().instance().get(&DataKey::InterestRateModel).unwrap();
+            rate_model_contract::Client::new(env, &model).get_compound_factor(&utilization, &time_elapsed)
+        } else {
+            Self::calculate_compound_factor(env, annual_borrow_rate, time_elapsed)
+        }
+    }
+
+    /// Approximate `(1 + rate_per_second)^time_elapsed` (scaled by 1e9)
+    /// 
+    /// Uses the first terms of the binomial expansion (matching the
+    /// InterestRateModel contract), with `x = rate_per_second * t`:
+    /// `1 + x + x^2 (t-1) / 2t + x^3 (t-1)(t-2) / 6t^2`
+    /// 
+    /// Dropping the higher terms undershoots by less than `x^4 / 24`: under
+    /// 1e-7 of the index while `x <= 3.9%`, and about 1.07e-7 at `x = 4%`
+    /// (e.g. 4% APR for a year), so how often a market is touched barely
+    /// changes what borrowers owe. Intervals with a larger `x` (a market
+    /// left untouched for years, or a high rate) are split into equal steps
+    /// of at most `MAX_COMPOUND_STEP` and multiplied back together, so every
+    /// step stays within that bound and no product can overflow.
+    fn calculate_compound_factor(env: &Env, annual_borrow_rate: i128, time_elapsed: u64) -> i128 {
+        let t = time_elapsed as i128;
+        if t == 0 {
+            return INITIAL_EXCHANGE_RATE;
+        }
+
+        // Work at 1e18 precision: per-second rates are tiny
+        let x = mul_div_floor(env, annual_borrow_rate, t * (COMPOUND_PRECISION / SCALE), SECONDS_PER_YEAR);
+
+        // `steps` equal intervals of `step_t` seconds, plus the leftover seconds
+        let steps = ((x - 1) / MAX_COMPOUND_STEP + 1).clamp(1, t);
+        let step_t = t / steps;
+        let rest_t = t - step_t * steps;
+
+        let step = Self::compound_step(env, mul_div_floor(env, x, step_t, t), step_t);
+        let mut factor = Self::compound_pow(env, step, steps);
+        if rest_t > 0 {
+            let rest = Self::compound_step(env, mul_div_floor(env, x, rest_t, t), rest_t);
+            factor = mul_div_floor(env, factor, rest, COMPOUND_PRECISION);
+        }
+
+        factor / (COMPOUND_PRECISION / INITIAL_EXCHANGE_RATE)
+    }
+
+    /// Internal: One expansion step of `calculate_compound_factor` for
+    /// `x = rate_per_second * t` over `t` seconds (both at 1e18 precision)
+    fn compound_step(env: &Env, x: i128, t: i128) -> i128 {
+        let x2 = mul_div_floor(env, x, x, COMPOUND_PRECISION);
+        let second = mul_div_floor(env, x2, t - 1, 2 * t);
+        let x3 = mul_div_floor(env, x2, x, COMPOUND_PRECISION);
+        let third = mul_div_floor(env, mul_div_floor(env, x3, t - 1, t), t - 2, 6 * t);
+
+        COMPOUND_PRECISION + x + second + third
+    }
 
-        // Update last accrual timestamp
-        env.storage().instance().set(&DataKey::LastAccrualTime(asset.clone()), &current_time);
+    /// Internal: `factor^n` at 1e18 precision, by repeated squaring
+    fn compound_pow(env: &Env, factor: i128, n: i128) -> i128 {
+        let mut result = COMPOUND_PRECISION;
+        let mut base = factor;
+        let mut n = n;
+        while n > 0 {
+            if n & 1 == 1 {
+                result = mul_div_floor(env, result, base, COMPOUND_PRECISION);
+            }
+            n >>= 1;
+            if n > 0 {
+                base = mul_div_floor(env, base, base, COMPOUND_PRECISION);
+            }
+        }
+        result
     }
 
     /// Calculate the borrow rate based on utilization
@@ -787,23 +4246,113 @@ impl LendingPool {
     /// Get exchange rate for sTokens
     fn get_exchange_rate_internal(env: &Env, asset: Symbol) -> i128 {
         let total_shares: i128 = env.storage().instance().get(&DataKey::TotalShares(asset.clone())).unwrap_or(0);
+        let total_supply: i128 = env.storage().instance().get(&DataKey::TotalSupply(asset.clone())).unwrap_or(0);
+        let total_borrow: i128 = env.storage().instance().get(&DataKey::TotalBorrow(asset.clone())).unwrap_or(0);
+        let total_reserves: i128 = env.storage().instance().get(&DataKey::TotalReserves(asset.clone())).unwrap_or(0);
         
+        let cash = Self::calculate_cash(total_supply, total_borrow, total_reserves);
+        Self::calculate_exchange_rate(env, cash, total_borrow, total_reserves, total_shares)
+    }
+
+    /// Calculate the sToken exchange rate from market totals
+    /// 
+    /// Suppliers own everything the market holds or is owed, minus the
+    /// protocol's reserves: `(cash + total_borrow - reserves) / shares`.
+    fn calculate_exchange_rate(env: &Env, cash: i128, total_borrow: i128, total_reserves: i128, total_shares: i128) -> i128 {
         if total_shares == 0 {
             return INITIAL_EXCHANGE_RATE;
         }
 
+        let total_underlying = cash + total_borrow - total_reserves;
+        
+        mul_div_floor(env, total_underlying, INITIAL_EXCHANGE_RATE, total_shares)
+    }
+
+    /// Calculate the market's idle cash (supplier tokens plus reserves held
+    /// by the pool, excluding collateral) from market totals
+    /// 
+    /// `TotalSupply` is the suppliers' claim (`cash + borrows - reserves`),
+    /// so `cash = total_supply - total_borrow + total_reserves`.
+    fn calculate_cash(total_supply: i128, total_borrow: i128, total_reserves: i128) -> i128 {
+        total_supply - total_borrow + total_reserves
+    }
+
+    /// Get the supply-side tokens the pool actually holds for a market
+    /// 
+    /// Tracked on every token movement rather than derived from totals, so
+    /// virtual accrued interest and collateral deposits never count as
+    /// lendable liquidity.
+    fn get_cash(env: &Env, asset: Symbol) -> i128 {
+        env.storage().instance().get(&DataKey::Cash(asset)).unwrap_or(0)
+    }
+
+    /// Record supply-side tokens entering (positive) or leaving (negative) the pool
+    fn adjust_cash(env: &Env, asset: &Symbol, delta: i128) {
+        let cash = Self::get_cash(env, asset.clone());
+        env.storage().instance().set(&DataKey::Cash(asset.clone()), &(cash + delta));
+    }
+
+    /// Compute a market's solvency invariants from stored totals
+    fn solvency_report(env: &Env, asset: &Symbol) -> SolvencyReport {
+        let cash = Self::get_cash(env, asset.clone());
         let total_supply: i128 = env.storage().instance().get(&DataKey::TotalSupply(asset.clone())).unwrap_or(0);
         let total_borrow: i128 = env.storage().instance().get(&DataKey::TotalBorrow(asset.clone())).unwrap_or(0);
         let total_reserves: i128 = env.storage().instance().get(&DataKey::TotalReserves(asset.clone())).unwrap_or(0);
-        
-        // Total cash = supply - borrows + borrow interest (approximated by borrow amount)
-        let total_underlying = total_supply + total_borrow - total_reserves;
-        
-        (total_underlying * INITIAL_EXCHANGE_RATE) / total_shares
+        let total_underlying = cash + total_borrow - total_reserves;
+
+        SolvencyReport {
+            cash,
+            total_supply,
+            total_borrow,
+            total_reserves,
+            total_underlying,
+            is_solvent: total_reserves <= cash + total_borrow && total_underlying >= 0,
+        }
+    }
+
+    /// Assert a market's solvency invariants after a state change
+    /// 
+    /// Compiled out of release builds, so it costs nothing on-chain; in
+    /// debug and test builds an accounting bug panics at the operation that
+    /// caused it.
+    fn debug_check_solvency(env: &Env, asset: &Symbol) {
+        debug_assert!(
+            Self::solvency_report(env, asset).is_solvent,
+            "market accounting invariant violated"
+        );
+    }
+
+    /// Get the exchange rate a state-changing call would use right now,
+    /// projecting interest accrual without writing storage
+    fn get_exchange_rate_projected(env: &Env, asset: &Symbol) -> i128 {
+        let total_shares: i128 = env.storage().instance().get(&DataKey::TotalShares(asset.clone())).unwrap_or(0);
+        let state = Self::project_accrual(env, asset);
+        let cash = Self::calculate_cash(state.total_supply, state.total_borrow, state.total_reserves);
+        Self::calculate_exchange_rate(env, cash, state.total_borrow, state.total_reserves, total_shares)
     }
 
     /// Get user's debt including accrued interest
     fn get_user_debt_with_interest(env: &Env, user: Address, asset: Symbol) -> i128 {
+        let current_borrow_index: i128 = env
+            .storage()
+            .instance()
+            .get(&DataKey::BorrowIndex(asset.clone()))
+            .unwrap_or(INITIAL_EXCHANGE_RATE);
+
+        Self::debt_at_index(env, user, asset, current_borrow_index)
+    }
+
+    /// Get user's debt including interest projected to the current timestamp
+    /// 
+    /// Matches what a state-changing call would see after `accrue_interest`,
+    /// without writing storage.
+    fn get_user_debt_projected(env: &Env, user: Address, asset: Symbol) -> i128 {
+        let current_borrow_index = Self::project_accrual(env, &asset).borrow_index;
+        Self::debt_at_index(env, user, asset, current_borrow_index)
+    }
+
+    /// Internal: Value a user's debt principal at the given market borrow index
+    fn debt_at_index(env: &Env, user: Address, asset: Symbol, current_borrow_index: i128) -> i128 {
         let principal: i128 = env
             .storage()
             .persistent()
@@ -814,26 +4363,39 @@ impl LendingPool {
             return 0;
         }
 
-        let user_borrow_index: i128 = env
+        // Stable-rate debt grows at its fixed rate since the last snapshot
+        // (simple interest, rounded up like variable debt)
+        let stable_rate: Option<i128> = env
             .storage()
             .persistent()
-            .get(&DataKey::UserBorrowIndex(user, asset.clone()))
-            .unwrap_or(INITIAL_EXCHANGE_RATE);
-        
-        let current_borrow_index: i128 = env
+            .get(&DataKey::UserStableRate(user.clone(), asset.clone()));
+        if let Some(rate) = stable_rate {
+            let since: u64 = env
+                .storage()
+                .persistent()
+                .get(&DataKey::UserStableTime(user, asset))
+                .unwrap_or(env.ledger().timestamp());
+            let elapsed = (env.ledger().timestamp() - since) as i128;
+            let accrued_rate = mul_div_ceil(env, rate, elapsed, Self::seconds_per_year(env));
+            return principal + mul_div_ceil(env, principal, accrued_rate, SCALE);
+        }
+
+        let user_borrow_index: i128 = env
             .storage()
-            .instance()
-            .get(&DataKey::BorrowIndex(asset))
+            .persistent()
+            .get(&DataKey::UserBorrowIndex(user, asset))
             .unwrap_or(INITIAL_EXCHANGE_RATE);
 
-        // debt = principal * current_index / user_index
-        (principal * current_borrow_index) / user_borrow_index
+        // debt = principal * current_index / user_index (rounded up: debt
+        // owed never rounds in the borrower's favor)
+        mul_div_ceil(env, principal, current_borrow_index, user_borrow_index)
     }
 
     /// Get asset price from oracle
     ///
     /// Calls the Price Oracle contract to get current USD price for an asset.
-    /// Falls back to hardcoded prices if oracle is not available.
+    /// A missing price reverts unless fallback prices are allowed. In strict
+    /// oracle mode a stale price panics with `PoolError::StalePrice`.
     ///
     /// # Arguments
     /// * `env` - Soroban environment
@@ -843,34 +4405,98 @@ impl LendingPool {
     /// # Returns
     /// Price in USD (scaled by 1e7)
     fn get_asset_price(env: &Env, oracle: &Address, asset: &Symbol) -> i128 {
-        if USE_ORACLE {
-            // Cross-contract call to Oracle
-            let oracle_client = oracle_contract::Client::new(env, oracle);
-            let price = oracle_client.get_price(asset);
-            
-            // Fallback if price not set
-            if price == 0 {
-                Self::get_fallback_price(asset)
-            } else {
-                price
+        // Cross-contract call to Oracle
+        let oracle_client = oracle_contract::Client::new(env, oracle);
+
+        let strict: bool = env.storage().instance().get(&DataKey::StrictOracle).unwrap_or(false);
+        if strict {
+            if Self::is_price_stale(env, &oracle_client, asset) {
+                panic_with_error!(env, PoolError::StalePrice);
             }
-        } else {
-            // Use fallback prices (for testing without deployed oracle)
-            Self::get_fallback_price(asset)
+
+            // A pool-level age bound is enforced by the oracle too
+            let max_age: u64 = env.storage().instance().get(&DataKey::PoolMaxPriceAge).unwrap_or(0);
+            if max_age > 0 {
+                return oracle_client.get_price_fresh(asset, &max_age);
+            }
+        }
+
+        let price = oracle_client.get_price(asset);
+        if price > 0 {
+            return price;
+        }
+
+        // Fallback only where explicitly allowed, never in strict mode
+        let allow_fallback: bool = env.storage().instance().get(&DataKey::AllowFallback).unwrap_or(false);
+        let fallback: i128 = env.storage().instance().get(&DataKey::FallbackPrice(asset.clone())).unwrap_or(0);
+        if strict || !allow_fallback || fallback == 0 {
+            panic_with_error!(env, PoolError::PriceUnavailable);
         }
+        fallback
     }
 
-    /// Get fallback price for testing
-    ///
-    /// Used when oracle is not deployed or price not available.
-    fn get_fallback_price(asset: &Symbol) -> i128 {
-        if *asset == XLM {
-            3_000_000 // $0.30
-        } else if *asset == USDC {
-            SCALE // $1.00
-        } else {
-            panic!("Unknown asset")
+    /// Internal: Base units per whole token of `asset` (10^decimals)
+    /// 
+    /// Prices are quoted per whole token, so amounts are valued with
+    /// `amount * price / unit` rather than assuming 7 decimals.
+    fn token_unit(env: &Env, asset: &Symbol) -> i128 {
+        10i128.pow(Self::get_decimals(env.clone(), asset.clone()))
+    }
+
+    /// Internal: Whether the oracle price of `asset` is older than the
+    /// pool's max age, or the oracle's own threshold if none is set
+    fn is_price_stale(env: &Env, oracle_client: &oracle_contract::Client, asset: &Symbol) -> bool {
+        let max_age: u64 = env.storage().instance().get(&DataKey::PoolMaxPriceAge).unwrap_or(0);
+        if max_age == 0 {
+            return oracle_client.is_stale(asset);
+        }
+
+        let age = env.ledger().timestamp().saturating_sub(oracle_client.get_last_update(asset));
+        age > max_age
+    }
+
+    /// Internal: Price overrides that let a liquidation value assets at
+    /// their last known price while the feed is stale
+    /// 
+    /// Covers every market whose stale price doesn't block liquidations.
+    /// Outside strict oracle mode prices are never rejected as stale, so no
+    /// overrides are needed.
+    fn liquidation_prices(env: &Env) -> Vec<(Symbol, i128)> {
+        let mut prices = Vec::new(env);
+        let strict: bool = env.storage().instance().get(&DataKey::StrictOracle).unwrap_or(false);
+        if !strict {
+            return prices;
+        }
+
+        let oracle: Address = env.storage().instance().get(&DataKey::PriceOracle).unwrap();
+        let oracle_client = oracle_contract::Client::new(env, &oracle);
+        for asset in Self::get_market_list(env).iter() {
+            if Self::stale_blocks_liquidation(env.clone(), asset.clone()) {
+                continue;
+            }
+            let price = oracle_client.get_price(&asset);
+            if price > 0 {
+                prices.push_back((asset, price));
+            }
+        }
+        prices
+    }
+
+    /// Internal: Price used to size a liquidation, falling back to the last
+    /// known price when staleness doesn't block liquidations of `asset`
+    fn get_liquidation_price(env: &Env, oracle: &Address, asset: &Symbol) -> i128 {
+        Self::get_position_price(env, oracle, asset, &Self::liquidation_prices(env))
+    }
+
+    /// Get asset price, preferring a hypothetical override if one is given
+    fn get_position_price(env: &Env, oracle: &Address, asset: &Symbol, price_overrides: &Vec<(Symbol, i128)>) -> i128 {
+        for (override_asset, price) in price_overrides.iter() {
+            if override_asset == *asset {
+                return price;
+            }
         }
+
+        Self::get_asset_price(env, oracle, asset)
     }
 
     // ========================================================================
@@ -878,51 +4504,162 @@ impl LendingPool {
     // ========================================================================
 
     /// Get user's complete position across all assets
+    /// 
+    /// Debt and share-collateral values include interest projected to the
+    /// current timestamp, without writing storage.
     pub fn get_user_position(env: Env, user: Address) -> UserPosition {
-        let oracle: Address = env.storage().instance().get(&DataKey::PriceOracle).unwrap();
+        Self::calculate_position(&env, &user, &Vec::new(&env), &Vec::new(&env), None)
+    }
 
-        // Calculate total collateral value in USD
-        let mut collateral_value_usd: i128 = 0;
-        let mut weighted_collateral_usd: i128 = 0; // collateral * LTV
+    /// Simulate a user's position under hypothetical prices
+    ///
+    /// Values the position exactly like `get_user_position`, but any asset
+    /// listed in `prices` is valued at the given price instead of the oracle
+    /// price. Read-only: no state is modified.
+    ///
+    /// # Arguments
+    /// * `user` - The user's address
+    /// * `prices` - Hypothetical `(asset, price)` pairs (price scaled by 1e7)
+    pub fn simulate_position_at_prices(env: Env, user: Address, prices: Vec<(Symbol, i128)>) -> UserPosition {
+        for (_, price) in prices.iter() {
+            if price <= 0 {
+                panic_with_error!(&env, PoolError::AmountNotPositive);
+            }
+        }
 
-        // XLM collateral
-        let xlm_collateral: i128 = env
-            .storage()
-            .persistent()
-            .get(&DataKey::UserCollateral(user.clone(), XLM))
-            .unwrap_or(0);
-        if xlm_collateral > 0 {
-            let xlm_price = Self::get_asset_price(&env, &oracle, &XLM);
-            let xlm_value = (xlm_collateral * xlm_price) / SCALE;
-            collateral_value_usd += xlm_value;
-            
-            let xlm_ltv: i128 = env.storage().instance().get(&DataKey::LtvRatio(XLM)).unwrap_or(7_500_000);
-            weighted_collateral_usd += (xlm_value * xlm_ltv) / SCALE;
+        Self::calculate_position(&env, &user, &prices, &Vec::new(&env), None)
+    }
+
+    /// Simulate a user's health factor with one asset at a hypothetical price
+    ///
+    /// Every other asset keeps its current oracle price, so sweeping
+    /// `hypothetical_price` builds a borrower's liquidation-price table.
+    /// Read-only: no state is modified.
+    ///
+    /// # Arguments
+    /// * `user` - The user's address
+    /// * `asset` - Asset to reprice
+    /// * `hypothetical_price` - Price to use for `asset` (scaled by 1e7)
+    ///
+    /// # Returns
+    /// Health factor at that price (scaled by 1e7), `i128::MAX` with no debt
+    pub fn health_factor_at_price(env: Env, user: Address, asset: Symbol, hypothetical_price: i128) -> i128 {
+        if hypothetical_price <= 0 {
+            panic_with_error!(&env, PoolError::AmountNotPositive);
         }
 
-        // USDC collateral (if any)
-        let usdc_collateral: i128 = env
-            .storage()
-            .persistent()
-            .get(&DataKey::UserCollateral(user.clone(), USDC))
-            .unwrap_or(0);
-        if usdc_collateral > 0 {
-            let usdc_price = Self::get_asset_price(&env, &oracle, &USDC);
-            let usdc_value = (usdc_collateral * usdc_price) / SCALE;
-            collateral_value_usd += usdc_value;
-            
-            let usdc_ltv: i128 = env.storage().instance().get(&DataKey::LtvRatio(USDC)).unwrap_or(8_000_000);
-            weighted_collateral_usd += (usdc_value * usdc_ltv) / SCALE;
+        let prices = Vec::from_array(&env, [(asset, hypothetical_price)]);
+        Self::calculate_position(&env, &user, &prices, &Vec::new(&env), None).health_factor
+    }
+
+    /// Simulate the health factor after borrowing
+    ///
+    /// Values the position exactly like `get_user_position`, with `amount`
+    /// of `asset` added to the user's debt at current prices. Read-only: no
+    /// state is modified and no LTV or liquidity checks are applied.
+    ///
+    /// # Arguments
+    /// * `user` - The user's address
+    /// * `asset` - Asset to borrow
+    /// * `amount` - Hypothetical borrow amount
+    ///
+    /// # Returns
+    /// Health factor the position would have (scaled by 1e7)
+    pub fn simulate_borrow(env: Env, user: Address, asset: Symbol, amount: i128) -> i128 {
+        if amount <= 0 {
+            panic_with_error!(&env, PoolError::AmountNotPositive);
+        }
+        if !env.storage().instance().has(&DataKey::TokenAddress(asset.clone())) {
+            panic_with_error!(&env, PoolError::MarketNotListed);
         }
 
-        // Calculate total debt value in USD
+        let borrows = Vec::from_array(&env, [(asset, amount)]);
+        Self::calculate_position(&env, &user, &Vec::new(&env), &borrows, None).health_factor
+    }
+
+    /// Internal: Calculate a user's position
+    /// 
+    /// # Arguments
+    /// * `price_overrides` - Hypothetical `(asset, price)` pairs used instead of oracle prices
+    /// * `extra_debt` - Hypothetical `(asset, amount)` borrows added to the user's debt
+    /// * `collateral_override` - Hypothetical `(asset, amount)` deposited collateral
+    ///   balance used instead of the stored one
+    /// 
+    /// Interest is projected to the current timestamp without writing storage.
+    fn calculate_position(
+        env: &Env,
+        user: &Address,
+        price_overrides: &Vec<(Symbol, i128)>,
+        extra_debt: &Vec<(Symbol, i128)>,
+        collateral_override: Option<(Symbol, i128)>,
+    ) -> UserPosition {
+        let oracle: Address = env.storage().instance().get(&DataKey::PriceOracle).unwrap();
+
+        let mut collateral_value_usd: i128 = 0;
+        let mut weighted_collateral_usd: i128 = 0; // collateral * LTV
+        let mut threshold_collateral_usd: i128 = 0; // collateral * liquidation threshold
         let mut debt_value_usd: i128 = 0;
 
-        // USDC debt
-        let usdc_debt = Self::get_user_debt_with_interest(&env, user.clone(), USDC);
-        if usdc_debt > 0 {
-            let usdc_price = Self::get_asset_price(&env, &oracle, &USDC);
-            debt_value_usd += (usdc_debt * usdc_price) / SCALE;
+        for asset in Self::get_market_list(env).iter() {
+            // Deposited collateral, unless the user opted the asset out
+            let deposited: i128 = if Self::is_used_as_collateral(env.clone(), user.clone(), asset.clone()) {
+                match &collateral_override {
+                    Some((override_asset, balance)) if *override_asset == asset => *balance,
+                    _ => env
+                        .storage()
+                        .persistent()
+                        .get(&DataKey::UserCollateral(user.clone(), asset.clone()))
+                        .unwrap_or(0),
+                }
+            } else {
+                0
+            };
+
+            // Supplied sTokens the user has opted in as collateral
+            let shares: i128 = if Self::shares_used_as_collateral(env, user, &asset) {
+                env.storage()
+                    .persistent()
+                    .get(&DataKey::UserShares(user.clone(), asset.clone()))
+                    .unwrap_or(0)
+            } else {
+                0
+            };
+
+            let mut debt = Self::get_user_debt_projected(env, user.clone(), asset.clone());
+            for (extra_asset, amount) in extra_debt.iter() {
+                if extra_asset == asset {
+                    debt += amount;
+                }
+            }
+
+            if deposited == 0 && shares == 0 && debt == 0 {
+                continue;
+            }
+
+            let price = Self::get_position_price(env, &oracle, &asset, price_overrides);
+            let unit = Self::token_unit(env, &asset);
+            let ltv: i128 = env.storage().instance().get(&DataKey::LtvRatio(asset.clone())).unwrap_or(0);
+            let threshold: i128 = env.storage().instance().get(&DataKey::LiquidationThreshold(asset.clone())).unwrap_or(0);
+
+            if deposited > 0 {
+                let value = mul_div_floor(env, deposited, price, unit);
+                collateral_value_usd += value;
+                weighted_collateral_usd += mul_div_floor(env, value, ltv, SCALE);
+                threshold_collateral_usd += mul_div_floor(env, value, threshold, SCALE);
+            }
+
+            if shares > 0 {
+                let exchange_rate = Self::get_exchange_rate_projected(env, &asset);
+                let underlying = mul_div_floor(env, shares, exchange_rate, INITIAL_EXCHANGE_RATE);
+                let value = mul_div_floor(env, underlying, price, unit);
+                collateral_value_usd += value;
+                weighted_collateral_usd += mul_div_floor(env, value, ltv, SCALE);
+                threshold_collateral_usd += mul_div_floor(env, value, threshold, SCALE);
+            }
+
+            if debt > 0 {
+                debt_value_usd += mul_div_ceil(env, debt, price, unit);
+            }
         }
 
         // Calculate available borrow (max borrow - current debt)
@@ -933,13 +4670,12 @@ impl LendingPool {
         };
 
         // Calculate health factor
-        // HF = (collateral * liquidation_threshold) / debt
-        let health_factor = if debt_value_usd == 0 {
-            999 * SCALE // Infinite
+        // HF = sum(collateral_i * liquidation_threshold_i) / debt
+        let has_debt = debt_value_usd > 0;
+        let health_factor = if has_debt {
+            mul_div_floor(env, threshold_collateral_usd, SCALE, debt_value_usd)
         } else {
-            // Use average liquidation threshold (simplified)
-            let liq_threshold: i128 = env.storage().instance().get(&DataKey::LiquidationThreshold(XLM)).unwrap_or(8_000_000);
-            (collateral_value_usd * liq_threshold) / debt_value_usd
+            i128::MAX
         };
 
         UserPosition {
@@ -947,7 +4683,197 @@ impl LendingPool {
             debt_value_usd,
             available_borrow_usd,
             health_factor,
+            threshold_collateral_usd,
+            has_debt,
+        }
+    }
+
+    /// Internal: Largest amount of `asset` collateral the user can withdraw
+    /// while their debt stays within the LTV-weighted value of what remains
+    fn calculate_max_withdrawable_collateral(env: &Env, user: &Address, asset: &Symbol, current_collateral: i128) -> i128 {
+        let position = Self::get_user_position(env.clone(), user.clone());
+        if position.debt_value_usd == 0 {
+            return current_collateral;
+        }
+
+        let ltv: i128 = env.storage().instance().get(&DataKey::LtvRatio(asset.clone())).unwrap_or(0);
+        if ltv == 0 {
+            return current_collateral;
+        }
+
+        // Spare borrowing power = sum(collateral * LTV) - debt. Each unit of
+        // `asset` withdrawn removes price * LTV of it.
+        let oracle: Address = env.storage().instance().get(&DataKey::PriceOracle).unwrap();
+        let price = Self::get_asset_price(env, &oracle, asset);
+        let max_amount = mul_div_floor(
+            env,
+            mul_div_floor(env, position.available_borrow_usd, SCALE, ltv),
+            Self::token_unit(env, asset),
+            price,
+        );
+
+        if max_amount > current_collateral {
+            current_collateral
+        } else {
+            max_amount
+        }
+    }
+
+    /// Get the most collateral a user can withdraw while keeping HF >= 1.0
+    /// 
+    /// Unlike the `withdraw_collateral` max sentinel, which stops at the LTV
+    /// limit, this goes all the way to the liquidation threshold.
+    /// 
+    /// # Arguments
+    /// * `user` - The user's address
+    /// * `asset` - Collateral asset to withdraw
+    /// 
+    /// # Returns
+    /// Amount of `asset` (the full balance if the user has no debt)
+    pub fn max_withdrawable_collateral(env: Env, user: Address, asset: Symbol) -> i128 {
+        let balance: i128 = env
+            .storage()
+            .persistent()
+            .get(&DataKey::UserCollateral(user.clone(), asset.clone()))
+            .unwrap_or(0);
+        if balance == 0 || !Self::is_used_as_collateral(env.clone(), user.clone(), asset.clone()) {
+            return balance;
+        }
+
+        let position = Self::get_user_position(env.clone(), user);
+        if position.debt_value_usd == 0 {
+            return balance;
+        }
+
+        // Collateral outside the health factor can always leave
+        let liq_threshold: i128 = env.storage().instance().get(&DataKey::LiquidationThreshold(asset.clone())).unwrap_or(0);
+        if liq_threshold == 0 {
+            return balance;
+        }
+
+        // HF >= 1 while threshold-weighted collateral covers debt. Each unit
+        // of `asset` withdrawn removes price * its own threshold.
+        let excess_weighted_usd = position.threshold_collateral_usd - position.debt_value_usd;
+        if excess_weighted_usd <= 0 {
+            return 0;
+        }
+        let excess_usd = mul_div_floor(&env, excess_weighted_usd, SCALE, liq_threshold);
+
+        let oracle: Address = env.storage().instance().get(&DataKey::PriceOracle).unwrap();
+        let price = Self::get_asset_price(&env, &oracle, &asset);
+        let max_amount = mul_div_floor(&env, excess_usd, Self::token_unit(&env, &asset), price);
+
+        if max_amount > balance {
+            balance
+        } else {
+            max_amount
+        }
+    }
+
+    /// Get the collateral deposit needed to reach a target health factor
+    /// 
+    /// Returns how much of `asset` the user must deposit as collateral, at
+    /// the current price, to raise their health factor to `target_hf`.
+    /// Rounded up so depositing the returned amount always reaches the target.
+    /// 
+    /// # Arguments
+    /// * `user` - The user's address
+    /// * `asset` - Collateral asset to deposit
+    /// * `target_hf` - Target health factor (scaled by SCALE, 1.5 = 15_000_000)
+    /// 
+    /// # Returns
+    /// Amount of `asset` to deposit (0 if there is no debt or HF is already at target)
+    pub fn get_collateral_to_health(env: Env, user: Address, asset: Symbol, target_hf: i128) -> i128 {
+        if target_hf <= 0 {
+            panic_with_error!(&env, PoolError::AmountNotPositive);
+        }
+
+        let position = Self::get_user_position(env.clone(), user);
+        if !position.has_debt || position.health_factor >= target_hf {
+            return 0;
+        }
+
+        let liq_threshold: i128 = env.storage().instance().get(&DataKey::LiquidationThreshold(asset.clone())).unwrap_or(0);
+        if liq_threshold == 0 {
+            panic_with_error!(&env, PoolError::NoHealthContribution);
         }
+
+        // HF = threshold_collateral_usd / debt_usd
+        // => required threshold-weighted collateral = target_hf * debt_usd,
+        //    topped up at `asset`'s own threshold
+        let required_weighted_usd = mul_div_ceil(&env, target_hf, position.debt_value_usd, SCALE);
+        let missing_weighted_usd = required_weighted_usd - position.threshold_collateral_usd;
+        if missing_weighted_usd <= 0 {
+            return 0;
+        }
+        let missing_usd = mul_div_ceil(&env, missing_weighted_usd, SCALE, liq_threshold);
+
+        // Convert USD shortfall into collateral units, rounding up
+        let oracle: Address = env.storage().instance().get(&DataKey::PriceOracle).unwrap();
+        let price = Self::get_asset_price(&env, &oracle, &asset);
+        mul_div_ceil(&env, missing_usd, Self::token_unit(&env, &asset), price)
+    }
+
+    // ========================================================================
+    // PREVIEW FUNCTIONS (read-only, no auth)
+    // ========================================================================
+
+    /// Preview the sToken shares `supply` would mint right now
+    /// 
+    /// Runs the same arithmetic as `supply`, including interest accrual up to
+    /// the current timestamp, without mutating storage.
+    pub fn preview_supply(env: Env, asset: Symbol, amount: i128) -> i128 {
+        if amount <= 0 {
+            return 0;
+        }
+
+        let exchange_rate = Self::get_exchange_rate_projected(&env, &asset);
+        mul_div_floor(&env, amount, INITIAL_EXCHANGE_RATE, exchange_rate)
+    }
+
+    /// Preview the underlying `withdraw` would return right now for `share_amount`
+    /// 
+    /// Runs the same arithmetic as `withdraw`, including interest accrual up
+    /// to the current timestamp, without mutating storage.
+    pub fn preview_withdraw(env: Env, asset: Symbol, share_amount: i128) -> i128 {
+        if share_amount <= 0 {
+            return 0;
+        }
+
+        let exchange_rate = Self::get_exchange_rate_projected(&env, &asset);
+        mul_div_floor(&env, share_amount, exchange_rate, INITIAL_EXCHANGE_RATE)
+    }
+
+    /// Preview the maximum additional borrow for a user, in USD (scaled by 1e7)
+    /// 
+    /// Values debt with interest projected to the current timestamp, as
+    /// `borrow` would after accruing.
+    pub fn preview_borrow_capacity(env: Env, user: Address) -> i128 {
+        Self::calculate_position(&env, &user, &Vec::new(&env), &Vec::new(&env), None).available_borrow_usd
+    }
+
+    /// Preview the total owed (principal + interest) on a new borrow of
+    /// `amount` held for `holding_seconds`
+    /// 
+    /// Assumes the borrow rate stays at the rate the market would charge
+    /// right after this borrow (utilization including `amount`), and applies
+    /// it the way accrual does when the market is not touched in between:
+    /// one step on the borrow index, compounded unless compounding is off.
+    pub fn preview_borrow_cost(env: Env, asset: Symbol, amount: i128, holding_seconds: u64) -> i128 {
+        if amount <= 0 {
+            return 0;
+        }
+
+        let state = Self::project_accrual(&env, &asset);
+        let utilization = if state.total_supply > 0 {
+            mul_div_floor(&env, state.total_borrow + amount, SCALE, state.total_supply)
+        } else {
+            0
+        };
+        let annual_borrow_rate = Self::borrow_rate_at(&env, utilization);
+        let future_index =
+            Self::grow_borrow_index(&env, state.borrow_index, utilization, annual_borrow_rate, holding_seconds);
+        mul_div_ceil(&env, amount, future_index, state.borrow_index)
     }
 
     /// Get market information for an asset
@@ -960,16 +4886,19 @@ impl LendingPool {
         let total_shares: i128 = env.storage().instance().get(&DataKey::TotalShares(asset.clone())).unwrap_or(0);
         let exchange_rate = Self::get_exchange_rate_internal(&env, asset.clone());
         let ltv_ratio: i128 = env.storage().instance().get(&DataKey::LtvRatio(asset.clone())).unwrap_or(0);
+        let is_frozen: bool = env.storage().instance().get(&DataKey::Frozen(asset.clone())).unwrap_or(false);
+        let bad_debt: i128 = env.storage().instance().get(&DataKey::BadDebt(asset.clone())).unwrap_or(0);
+        let bad_debt_covered: i128 = env.storage().instance().get(&DataKey::BadDebtCovered(asset.clone())).unwrap_or(0);
 
         // Calculate utilization rate
         let utilization_rate = if total_supply > 0 {
-            (total_borrow * SCALE) / total_supply
+            mul_div_floor(&env, total_borrow, SCALE, total_supply)
         } else {
             0
         };
 
-        // Calculate interest rates using the kinked model
-        let borrow_rate = Self::calculate_borrow_rate(utilization_rate);
+        // Calculate interest rates using the active rate source
+        let borrow_rate = Self::borrow_rate_at(&env, utilization_rate);
         
         // Supply rate = borrow_rate * utilization * (1 - reserve_factor)
         let reserve_factor: i128 = env
@@ -978,7 +4907,7 @@ impl LendingPool {
             .get(&DataKey::ReserveFactor(asset))
             .unwrap_or(1_000_000);
         let supply_rate = if utilization_rate > 0 {
-            (borrow_rate * utilization_rate * (SCALE - reserve_factor)) / (SCALE * SCALE)
+            mul_div_floor(&env, borrow_rate, utilization_rate * (SCALE - reserve_factor), SCALE * SCALE)
         } else {
             0
         };
@@ -992,7 +4921,69 @@ impl LendingPool {
             borrow_rate,
             supply_rate,
             ltv_ratio,
+            is_frozen,
+            bad_debt,
+            bad_debt_covered,
+        }
+    }
+
+    /// Get the symbols of all listed markets, in listing order
+    pub fn get_markets(env: Env) -> Vec<Symbol> {
+        Self::get_market_list(&env)
+    }
+
+    /// Get market information for every listed market
+    ///
+    /// Entries are in the same order as `get_markets`.
+    pub fn get_all_market_info(env: Env) -> Vec<MarketInfo> {
+        let mut infos = Vec::new(&env);
+        for asset in Self::get_market_list(&env).iter() {
+            infos.push_back(Self::get_market_info(env.clone(), asset));
+        }
+        infos
+    }
+
+    /// Get protocol-wide totals in USD
+    ///
+    /// Sums supply, borrows, reserves and collateral across every listed
+    /// market, each valued at the current oracle price.
+    pub fn get_protocol_stats(env: Env) -> ProtocolStats {
+        let oracle: Address = env.storage().instance().get(&DataKey::PriceOracle).unwrap();
+        let mut stats = ProtocolStats {
+            total_supply_usd: 0,
+            total_borrow_usd: 0,
+            total_reserves_usd: 0,
+            total_collateral_usd: 0,
+        };
+
+        for asset in Self::get_market_list(&env).iter() {
+            let price = Self::get_asset_price(&env, &oracle, &asset);
+            let total_supply: i128 = env.storage().instance().get(&DataKey::TotalSupply(asset.clone())).unwrap_or(0);
+            let total_borrow: i128 = env.storage().instance().get(&DataKey::TotalBorrow(asset.clone())).unwrap_or(0);
+            let total_reserves: i128 = env.storage().instance().get(&DataKey::TotalReserves(asset.clone())).unwrap_or(0);
+            let total_collateral: i128 = env.storage().instance().get(&DataKey::TotalCollateral(asset.clone())).unwrap_or(0);
+
+            let unit = Self::token_unit(&env, &asset);
+            stats.total_supply_usd += mul_div_floor(&env, total_supply, price, unit);
+            stats.total_borrow_usd += mul_div_floor(&env, total_borrow, price, unit);
+            stats.total_reserves_usd += mul_div_floor(&env, total_reserves, price, unit);
+            stats.total_collateral_usd += mul_div_floor(&env, total_collateral, price, unit);
         }
+
+        stats
+    }
+
+    /// Get the liquidity available to withdraw, borrow or flash-borrow
+    /// 
+    /// Supply-side cash actually held by the pool; collateral deposits are
+    /// excluded.
+    pub fn get_available_liquidity(env: Env, asset: Symbol) -> i128 {
+        Self::get_cash(&env, asset)
+    }
+
+    /// Get total collateral deposited for an asset
+    pub fn get_total_collateral(env: Env, asset: Symbol) -> i128 {
+        env.storage().instance().get(&DataKey::TotalCollateral(asset)).unwrap_or(0)
     }
 
     /// Get total supply for an asset
@@ -1005,6 +4996,20 @@ impl LendingPool {
         env.storage().instance().get(&DataKey::TotalBorrow(asset)).unwrap_or(0)
     }
 
+    /// Get total protocol reserves for an asset
+    pub fn get_total_reserves(env: Env, asset: Symbol) -> i128 {
+        env.storage().instance().get(&DataKey::TotalReserves(asset)).unwrap_or(0)
+    }
+
+    /// Check a market's accounting invariants, for monitoring
+    /// 
+    /// Reserves must be covered by what the pool holds or is owed
+    /// (`reserves <= cash + borrows`), so suppliers' underlying never goes
+    /// negative.
+    pub fn check_solvency(env: Env, asset: Symbol) -> SolvencyReport {
+        Self::solvency_report(&env, &asset)
+    }
+
     /// Get user's share balance for an asset
     pub fn get_user_shares(env: Env, user: Address, asset: Symbol) -> i128 {
         env.storage().persistent().get(&DataKey::UserShares(user, asset)).unwrap_or(0)
@@ -1025,6 +5030,14 @@ impl LendingPool {
         Self::get_user_debt_with_interest(&env, user, asset)
     }
 
+    /// Get user's debt as a repay would charge it right now
+    /// 
+    /// Projects interest accrual to the current timestamp without writing
+    /// storage, so repay UIs can clear a position without leaving dust.
+    pub fn get_user_debt_live(env: Env, user: Address, asset: Symbol) -> i128 {
+        Self::get_user_debt_projected(&env, user, asset)
+    }
+
     /// Get exchange rate for sTokens
     pub fn get_exchange_rate(env: Env, asset: Symbol) -> i128 {
         Self::get_exchange_rate_internal(&env, asset)
@@ -1039,7 +5052,7 @@ impl LendingPool {
             return 0;
         }
         
-        (total_borrow * SCALE) / total_supply
+        mul_div_floor(&env, total_borrow, SCALE, total_supply)
     }
 
     /// Get LTV ratio for an asset
@@ -1047,18 +5060,49 @@ impl LendingPool {
         env.storage().instance().get(&DataKey::LtvRatio(asset)).unwrap_or(0)
     }
 
+    /// Get the token decimals for an asset
+    pub fn get_decimals(env: Env, asset: Symbol) -> u32 {
+        env.storage()
+            .instance()
+            .get(&DataKey::Decimals(asset))
+            .unwrap_or(DEFAULT_TOKEN_DECIMALS)
+    }
+
     /// Get liquidation threshold for an asset
     pub fn get_liquidation_threshold(env: Env, asset: Symbol) -> i128 {
         env.storage().instance().get(&DataKey::LiquidationThreshold(asset)).unwrap_or(0)
     }
 
+    /// Get close factor for a collateral asset
+    pub fn get_close_factor(env: Env, asset: Symbol) -> i128 {
+        env.storage().instance().get(&DataKey::CloseFactor(asset)).unwrap_or(DEFAULT_CLOSE_FACTOR)
+    }
+
+    /// Get liquidation bonus for a collateral asset
+    pub fn get_liquidation_bonus(env: Env, asset: Symbol) -> i128 {
+        env.storage().instance().get(&DataKey::LiquidationBonus(asset)).unwrap_or(DEFAULT_LIQUIDATION_BONUS)
+    }
+
+    /// Get the share of the liquidation bonus kept by the protocol for a collateral asset
+    pub fn get_liquidation_protocol_fee(env: Env, asset: Symbol) -> i128 {
+        env.storage()
+            .instance()
+            .get(&DataKey::LiquidationProtocolFee(asset))
+            .unwrap_or(DEFAULT_LIQUIDATION_PROTOCOL_FEE)
+    }
+
+    /// Get reserve factor for an asset
+    pub fn get_reserve_factor(env: Env, asset: Symbol) -> i128 {
+        env.storage().instance().get(&DataKey::ReserveFactor(asset)).unwrap_or(1_000_000)
+    }
+
     /// Get the current borrow APR for an asset
     /// 
     /// Returns the annualized borrow rate based on current utilization.
     /// Scaled by 1e7, so 5% = 500_000.
     pub fn get_borrow_rate(env: Env, asset: Symbol) -> i128 {
-        let utilization = Self::get_utilization_rate(env, asset);
-        Self::calculate_borrow_rate(utilization)
+        let utilization = Self::get_utilization_rate(env.clone(), asset);
+        Self::borrow_rate_at(&env, utilization)
     }
 
     /// Get the current supply APY for an asset
@@ -1067,7 +5111,6 @@ impl LendingPool {
     /// Scaled by 1e7, so 3.2% = 320_000.
     pub fn get_supply_rate(env: Env, asset: Symbol) -> i128 {
         let utilization = Self::get_utilization_rate(env.clone(), asset.clone());
-        let borrow_rate = Self::calculate_borrow_rate(utilization);
         
         let reserve_factor: i128 = env
             .storage()
@@ -1075,9 +5118,17 @@ impl LendingPool {
             .get(&DataKey::ReserveFactor(asset))
             .unwrap_or(1_000_000);
         
+        let use_rate_model: bool = env.storage().instance().get(&DataKey::UseRateModel).unwrap_or(false);
+        if use_rate_model {
+            let model: Address = env.storage().instance().get(&DataKey::InterestRateModel).unwrap();
+            return rate_model_contract::Client::new(&env, &model).get_supply_rate(&utilization, &reserve_factor);
+        }
+        
+        let borrow_rate = Self::calculate_borrow_rate(utilization);
+        
         // Supply rate = borrow_rate * utilization * (1 - reserve_factor)
         if utilization > 0 {
-            (borrow_rate * utilization * (SCALE - reserve_factor)) / (SCALE * SCALE)
+            mul_div_floor(&env, borrow_rate, utilization * (SCALE - reserve_factor), SCALE * SCALE)
         } else {
             0
         }
@@ -1109,7 +5160,8 @@ impl LendingPool {
     /// # Returns
     /// - HF >= 1.0 (SCALE): Safe position
     /// - HF < 1.0 (SCALE): Unsafe position, eligible for liquidation
-    /// - 999 * SCALE: No debt (infinite health factor)
+    /// - `i128::MAX`: No debt (check `UserPosition.has_debt` to tell a
+    ///   debt-free position apart)
     /// 
     /// Scaled by SCALE (1e7), so HF = 1.0 is represented as 10_000_000
     pub fn get_health_factor(env: Env, user: Address) -> i128 {
@@ -1117,6 +5169,24 @@ impl LendingPool {
         position.health_factor
     }
 
+    /// Get how far a position is above or below its liquidation threshold
+    /// 
+    /// Compares threshold-weighted collateral (the health factor's numerator)
+    /// against debt, in USD (scaled by 1e7).
+    /// 
+    /// # Returns
+    /// `(surplus_usd, shortfall_usd)`; at most one is nonzero
+    pub fn get_account_liquidity(env: Env, user: Address) -> (i128, i128) {
+        let position = Self::get_user_position(env.clone(), user);
+        let weighted_collateral_usd = position.threshold_collateral_usd;
+
+        if weighted_collateral_usd >= position.debt_value_usd {
+            (weighted_collateral_usd - position.debt_value_usd, 0)
+        } else {
+            (0, position.debt_value_usd - weighted_collateral_usd)
+        }
+    }
+
     // ========================================================================
     // LIQUIDATION
     // ========================================================================
@@ -1128,18 +5198,30 @@ impl LendingPool {
     /// 
     /// # Requirements
     /// - Borrower's health factor must be < 1.0
-    /// - Liquidator can repay up to 50% of borrower's debt (close factor)
-    /// - Liquidator receives equivalent collateral value + 5% bonus
+    /// - Liquidator must not be the borrower, who would otherwise collect
+    ///   the bonus on their own collateral
+    /// - Liquidator can repay up to the collateral asset's close factor
+    ///   of the borrower's debt (default 50%), or all of it once the debt
+    ///   exceeds the collateral's value
+    /// - Liquidator receives equivalent collateral value plus the collateral
+    ///   asset's liquidation bonus (default 5%), less the protocol's share
+    ///   of that bonus (default 10%), which is credited to reserves
+    /// - If the collateral can't cover that, all of it is seized and the
+    ///   repayment is scaled down to its value; debt left once the
+    ///   borrower's collateral is gone is written off as bad debt
     /// 
     /// # Arguments
     /// * `liquidator` - Address calling the liquidation (repaying debt)
     /// * `borrower` - Address being liquidated (underwater position)
     /// * `repay_asset` - Asset to repay (e.g., USDC)
-    /// * `repay_amount` - Amount of debt to repay
-    /// * `collateral_asset` - Collateral asset to seize (e.g., XLM)
+    /// * `repay_amount` - Amount of debt to repay (`i128::MAX` = the most
+    ///   the close factor allows)
+    /// * `collateral_asset` - Collateral asset to seize (e.g., XLM). May equal
+    ///   `repay_asset`: deposited collateral is tracked apart from
+    ///   supply-side cash, so the two legs never net against each other.
     /// 
     /// # Returns
-    /// Amount of collateral seized
+    /// Amount of collateral seized from the borrower, protocol fee included
     pub fn liquidate(
         env: Env,
         liquidator: Address,
@@ -1149,132 +5231,617 @@ impl LendingPool {
         collateral_asset: Symbol,
     ) -> i128 {
         liquidator.require_auth();
-        
+
+        Self::liquidate_internal(env, liquidator, borrower, repay_asset, repay_amount, collateral_asset, false)
+    }
+
+    /// Liquidate an undercollateralized position, seizing sTokens
+    /// 
+    /// Same as `liquidate`, but the seized value (repay plus bonus) is taken
+    /// from the borrower's supplied `collateral_asset` shares at the current
+    /// exchange rate and credited to the liquidator's shares. No underlying
+    /// leaves the pool, so the seizure never depends on available cash and
+    /// the liquidator's position keeps earning supply interest. Only shares
+    /// the borrower opted in via `set_shares_as_collateral` can be seized.
+    /// 
+    /// # Arguments
+    /// * `liquidator` - Address calling the liquidation (repaying debt)
+    /// * `borrower` - Address being liquidated (underwater position)
+    /// * `repay_asset` - Asset to repay (e.g., USDC)
+    /// * `repay_amount` - Amount of debt to repay (`i128::MAX` = the most
+    ///   the close factor allows)
+    /// * `collateral_asset` - Supplied asset whose sTokens are seized
+    /// 
+    /// # Returns
+    /// Amount of sToken shares seized from the borrower, protocol fee included
+    pub fn liquidate_shares(
+        env: Env,
+        liquidator: Address,
+        borrower: Address,
+        repay_asset: Symbol,
+        repay_amount: i128,
+        collateral_asset: Symbol,
+    ) -> i128 {
+        liquidator.require_auth();
+
+        Self::liquidate_internal(env, liquidator, borrower, repay_asset, repay_amount, collateral_asset, true)
+    }
+
+    /// Internal: Shared liquidation flow, seizing either raw collateral or
+    /// the borrower's sToken shares of `collateral_asset`
+    fn liquidate_internal(
+        env: Env,
+        liquidator: Address,
+        borrower: Address,
+        repay_asset: Symbol,
+        repay_amount: i128,
+        collateral_asset: Symbol,
+        seize_shares: bool,
+    ) -> i128 {
         if repay_amount <= 0 {
-            panic!("Repay amount must be positive");
+            panic_with_error!(&env, PoolError::AmountNotPositive);
+        }
+        // Protocol debt is settled by the treasury, never liquidated
+        if liquidator == borrower || borrower == env.current_contract_address() {
+            panic_with_error!(&env, PoolError::SelfLiquidation);
         }
 
         // ====================================================================
         // STEP 1: Check borrower's health factor
         // ====================================================================
         
-        // Accrue interest first to get accurate debt
+        // Accrue interest first to get accurate debt in every market the
+        // borrower owes, and an up-to-date exchange rate for
+        // interest-bearing collateral
         Self::accrue_interest(&env, repay_asset.clone());
+        Self::accrue_debt_markets(&env, &borrower);
+        Self::accrue_interest(&env, collateral_asset.clone());
         
-        let borrower_position = Self::get_user_position(env.clone(), borrower.clone());
+        let borrower_position = Self::get_liquidation_position(&env, &borrower);
         
         // Health factor must be < 1.0 to be liquidatable
-        if borrower_position.health_factor >= SCALE {
-            panic!("Position is healthy, cannot liquidate");
+        if !borrower_position.has_debt || borrower_position.health_factor >= SCALE {
+            panic_with_error!(&env, PoolError::PositionHealthy);
+        }
+
+        // ====================================================================
+        // STEPS 2-3: Cap the repayment and size the seizure
+        // ====================================================================
+        
+        let (borrower_debt, actual_repay, collateral_to_seize, borrower_collateral) = Self::calculate_liquidation(
+            &env,
+            &borrower,
+            &borrower_position,
+            &repay_asset,
+            repay_amount,
+            &collateral_asset,
+            seize_shares,
+        );
+
+        // ====================================================================
+        // STEP 4: Execute liquidation
+        // ====================================================================
+        
+        // Transfer repay_asset from liquidator to pool
+        let repay_token: Address = env.storage().instance().get(&DataKey::TokenAddress(repay_asset.clone())).unwrap();
+        let repay_token_client = token::Client::new(&env, &repay_token);
+        repay_token_client.transfer(&liquidator, &env.current_contract_address(), &actual_repay);
+        Self::adjust_cash(&env, &repay_asset, actual_repay);
+        
+        // Reduce borrower's debt and total borrows
+        Self::reduce_debt(&env, &borrower, &repay_asset, actual_repay, borrower_debt);
+        
+        // The protocol keeps its share of the bonus; the liquidator gets the rest
+        let protocol_fee = Self::liquidation_protocol_fee(&env, &collateral_asset, collateral_to_seize);
+        let liquidator_amount = collateral_to_seize - protocol_fee;
+
+        let new_borrower_collateral = borrower_collateral - collateral_to_seize;
+        if seize_shares {
+            // Move sToken shares from borrower to liquidator
+            env.storage()
+                .persistent()
+                .set(&DataKey::UserShares(borrower.clone(), collateral_asset.clone()), &new_borrower_collateral);
+            let liquidator_shares: i128 = env
+                .storage()
+                .persistent()
+                .get(&DataKey::UserShares(liquidator.clone(), collateral_asset.clone()))
+                .unwrap_or(0);
+            env.storage().persistent().set(
+                &DataKey::UserShares(liquidator.clone(), collateral_asset.clone()),
+                &(liquidator_shares + liquidator_amount),
+            );
+
+            // Burn the fee shares into reserves: supply moves to reserves
+            // and cash stays put
+            if protocol_fee > 0 {
+                let fee_underlying = mul_div_floor(
+                    &env,
+                    protocol_fee,
+                    Self::get_exchange_rate_projected(&env, &collateral_asset),
+                    INITIAL_EXCHANGE_RATE,
+                );
+                let total_supply: i128 =
+                    env.storage().instance().get(&DataKey::TotalSupply(collateral_asset.clone())).unwrap_or(0);
+                let total_shares: i128 =
+                    env.storage().instance().get(&DataKey::TotalShares(collateral_asset.clone())).unwrap_or(0);
+                let total_reserves: i128 =
+                    env.storage().instance().get(&DataKey::TotalReserves(collateral_asset.clone())).unwrap_or(0);
+                env.storage()
+                    .instance()
+                    .set(&DataKey::TotalSupply(collateral_asset.clone()), &(total_supply - fee_underlying));
+                env.storage()
+                    .instance()
+                    .set(&DataKey::TotalShares(collateral_asset.clone()), &(total_shares - protocol_fee));
+                env.storage()
+                    .instance()
+                    .set(&DataKey::TotalReserves(collateral_asset.clone()), &(total_reserves + fee_underlying));
+            }
+        } else {
+            // Transfer collateral from borrower to liquidator
+            env.storage()
+                .persistent()
+                .set(&DataKey::UserCollateral(borrower.clone(), collateral_asset.clone()), &new_borrower_collateral);
+            let total_collateral: i128 = env
+                .storage()
+                .instance()
+                .get(&DataKey::TotalCollateral(collateral_asset.clone()))
+                .unwrap_or(0);
+            env.storage()
+                .instance()
+                .set(&DataKey::TotalCollateral(collateral_asset.clone()), &(total_collateral - collateral_to_seize));
+
+            // Transfer collateral tokens to liquidator
+            let collateral_token: Address = env.storage().instance().get(&DataKey::TokenAddress(collateral_asset.clone())).unwrap();
+            let collateral_token_client = token::Client::new(&env, &collateral_token);
+            collateral_token_client.transfer(&env.current_contract_address(), &liquidator, &liquidator_amount);
+
+            // The fee stays in the pool as reserves
+            if protocol_fee > 0 {
+                let total_reserves: i128 =
+                    env.storage().instance().get(&DataKey::TotalReserves(collateral_asset.clone())).unwrap_or(0);
+                env.storage()
+                    .instance()
+                    .set(&DataKey::TotalReserves(collateral_asset.clone()), &(total_reserves + protocol_fee));
+                Self::adjust_cash(&env, &collateral_asset, protocol_fee);
+            }
+        }
+        Self::debug_check_solvency(&env, &collateral_asset);
+        if protocol_fee > 0 {
+            env.events().publish((symbol_short!("liq_fee"), collateral_asset.clone()), protocol_fee);
+        }
+
+        // ====================================================================
+        // STEP 5: Socialize bad debt once collateral is exhausted
+        // ====================================================================
+        
+        // With no collateral left, every market the borrower still owes is
+        // written off, not just the one being repaid: that debt could never
+        // be liquidated and would keep inflating its market's total borrows.
+        // Deposits opted out of collateral still count as collateral here:
+        // they stay seizable, and writing off first would hand them back.
+        let mut new_position = Self::get_liquidation_position(&env, &borrower);
+        let mut bad_debt = 0;
+        if new_position.collateral_value_usd == 0
+            && new_position.has_debt
+            && !Self::has_deposited_collateral(&env, &borrower)
+        {
+            for asset in Self::get_market_list(&env).iter() {
+                let written_off = Self::write_off_bad_debt(&env, &borrower, &asset);
+                if asset == repay_asset {
+                    bad_debt = written_off;
+                }
+                Self::debug_check_solvency(&env, &asset);
+            }
+            new_position = Self::get_liquidation_position(&env, &borrower);
+        }
+        Self::debug_check_solvency(&env, &repay_asset);
+
+        // ====================================================================
+        // STEP 6: Verify the liquidation improved the borrower's health
+        // ====================================================================
+        
+        // Seizing collateral plus bonus from a nearly insolvent position can
+        // lower its health factor. Only allow that when the liquidation took
+        // everything left of the seized asset and no collateral backs the
+        // position, so liquidators can't farm the bonus without restoring
+        // health, including from opted-out deposits once HF is already 0.
+        let seized_all = collateral_to_seize == borrower_collateral;
+        if new_position.health_factor <= borrower_position.health_factor
+            && (new_position.collateral_value_usd > 0 || !seized_all)
+        {
+            panic_with_error!(&env, PoolError::LiquidationNoImprovement);
         }
 
         // ====================================================================
-        // STEP 2: Calculate maximum repayable amount (close factor)
+        // STEP 7: Record history, emit event and return
         // ====================================================================
         
-        let borrower_debt = Self::get_user_debt_with_interest(&env, borrower.clone(), repay_asset.clone());
+        let liquidation_count: u32 = env
+            .storage()
+            .persistent()
+            .get(&DataKey::LiquidationCount(borrower.clone()))
+            .unwrap_or(0)
+            + 1;
+        env.storage()
+            .persistent()
+            .set(&DataKey::LiquidationCount(borrower.clone()), &liquidation_count);
+        env.storage().persistent().set(
+            &DataKey::LastLiquidation(borrower.clone()),
+            &(env.ledger().timestamp(), actual_repay, collateral_to_seize),
+        );
+
+        let oracle: Address = env.storage().instance().get(&DataKey::PriceOracle).unwrap();
+        let repay_price = Self::get_liquidation_price(&env, &oracle, &repay_asset);
+        let collateral_price = Self::get_liquidation_price(&env, &oracle, &collateral_asset);
+        let topic = if seize_shares { symbol_short!("liq_share") } else { symbol_short!("liquidate") };
+        Self::publish_event(
+            &env,
+            topic,
+            LiquidationEvent {
+                liquidator,
+                borrower,
+                repay_asset,
+                repay_amount: actual_repay,
+                collateral_asset,
+                seized: collateral_to_seize,
+                repay_price,
+                collateral_price,
+                bad_debt,
+                health_factor: new_position.health_factor,
+            },
+        );
+
+        collateral_to_seize
+    }
+
+    /// Internal: Protocol's cut of the bonus within a seizure
+    /// 
+    /// The seized amount is `base * (1 + bonus)`; the fee is the protocol
+    /// share of the `base * bonus` part, rounded down in the liquidator's
+    /// favor. Denominated in whatever unit was seized (tokens or shares).
+    fn liquidation_protocol_fee(env: &Env, collateral_asset: &Symbol, seized: i128) -> i128 {
+        let liquidation_bonus = Self::get_liquidation_bonus(env.clone(), collateral_asset.clone());
+        let fee = Self::get_liquidation_protocol_fee(env.clone(), collateral_asset.clone());
+        let bonus_part = mul_div_floor(env, seized, liquidation_bonus, SCALE + liquidation_bonus);
+        mul_div_floor(env, bonus_part, fee, SCALE)
+    }
+
+    /// Preview a liquidation without executing it
+    /// 
+    /// Applies the close factor, liquidation bonus and available collateral
+    /// exactly as `liquidate` would, with interest projected to the current
+    /// timestamp. Read-only: no auth and no state changes.
+    /// 
+    /// # Arguments
+    /// * `borrower` - Address being liquidated
+    /// * `repay_asset` - Asset to repay
+    /// * `repay_amount` - Amount of debt the liquidator offers to repay
+    ///   (`i128::MAX` = the most the close factor allows)
+    /// * `collateral_asset` - Collateral asset to seize
+    /// 
+    /// # Returns
+    /// `(actual_repay, collateral_to_seize)`, or `(0, 0)` if the position is healthy
+    pub fn preview_liquidation(
+        env: Env,
+        borrower: Address,
+        repay_asset: Symbol,
+        repay_amount: i128,
+        collateral_asset: Symbol,
+    ) -> (i128, i128) {
+        if repay_amount <= 0 {
+            panic_with_error!(&env, PoolError::AmountNotPositive);
+        }
+
+        let position = Self::get_liquidation_position(&env, &borrower);
+        if !position.has_debt || position.health_factor >= SCALE {
+            return (0, 0);
+        }
+
+        let (_, actual_repay, collateral_to_seize, _) = Self::calculate_liquidation(
+            &env,
+            &borrower,
+            &position,
+            &repay_asset,
+            repay_amount,
+            &collateral_asset,
+            false,
+        );
+        (actual_repay, collateral_to_seize)
+    }
+
+    /// Internal: A borrower's position as valued for liquidation, at the
+    /// last known price of assets whose staleness doesn't block liquidations
+    fn get_liquidation_position(env: &Env, borrower: &Address) -> UserPosition {
+        Self::calculate_position(env, borrower, &Self::liquidation_prices(env), &Vec::new(env), None)
+    }
+
+    /// Internal: Size a liquidation of an unhealthy `position`
+    /// 
+    /// Debt and share exchange rates are projected to the current timestamp,
+    /// so this matches post-accrual state without writing it.
+    /// 
+    /// # Returns
+    /// `(borrower_debt, actual_repay, collateral_to_seize, borrower_collateral)`,
+    /// with collateral in shares when `seize_shares` is set
+    fn calculate_liquidation(
+        env: &Env,
+        borrower: &Address,
+        position: &UserPosition,
+        repay_asset: &Symbol,
+        repay_amount: i128,
+        collateral_asset: &Symbol,
+        seize_shares: bool,
+    ) -> (i128, i128, i128, i128) {
+        // Maximum repayable amount (close factor)
+        let borrower_debt = Self::get_user_debt_projected(env, borrower.clone(), repay_asset.clone());
         
         if borrower_debt == 0 {
-            panic!("Borrower has no debt in this asset");
+            panic_with_error!(env, PoolError::NoDebt);
         }
         
-        // Maximum repayable = 50% of borrower's debt
-        let max_repay = (borrower_debt * CLOSE_FACTOR) / SCALE;
+        // Maximum repayable = close factor (default 50%) of borrower's debt
+        let close_factor: i128 = env
+            .storage()
+            .instance()
+            .get(&DataKey::CloseFactor(collateral_asset.clone()))
+            .unwrap_or(DEFAULT_CLOSE_FACTOR);
+        let mut max_repay = mul_div_floor(env, borrower_debt, close_factor, SCALE);
+
+        // Insolvent positions can only be cleaned up by seizing everything:
+        // any partial seizure plus bonus would leave them less healthy
+        if position.collateral_value_usd < position.debt_value_usd {
+            max_repay = borrower_debt;
+        }
+
+        // Targeted deleveraging: repay no more than what restores HF to 1.0,
+        // or to the configured target HF
+        let targeted: bool = env.storage().instance().get(&DataKey::TargetedLiquidation).unwrap_or(false);
+        let target_hf: i128 = env.storage().instance().get(&DataKey::LiquidationTargetHf).unwrap_or(0);
+        let target_hf = if targeted { SCALE } else { target_hf };
+        if target_hf > 0 {
+            let repay_to_target =
+                Self::calculate_repay_to_health(env, position, repay_asset, collateral_asset, target_hf);
+            if repay_to_target > 0 && repay_to_target < max_repay {
+                max_repay = repay_to_target;
+            }
+        }
         
-        // Cap repay_amount to max allowed
-        let actual_repay = if repay_amount > max_repay {
+        // Cap repay_amount to max allowed; i128::MAX asks for exactly the cap
+        let mut actual_repay = if repay_amount == i128::MAX || repay_amount > max_repay {
             max_repay
         } else {
             repay_amount
         };
 
-        // ====================================================================
-        // STEP 3: Calculate collateral to seize
-        // ====================================================================
-        
+        // Collateral to seize
         let oracle: Address = env.storage().instance().get(&DataKey::PriceOracle).unwrap();
         
         // Get prices
-        let repay_price = Self::get_asset_price(&env, &oracle, &repay_asset);
-        let collateral_price = Self::get_asset_price(&env, &oracle, &collateral_asset);
+        let repay_price = Self::get_liquidation_price(env, &oracle, repay_asset);
+        let collateral_price = Self::get_liquidation_price(env, &oracle, collateral_asset);
         
         // Calculate repay value in USD
-        let repay_value_usd = (actual_repay * repay_price) / SCALE;
+        let repay_value_usd = mul_div_floor(env, actual_repay, repay_price, Self::token_unit(env, repay_asset));
         
-        // Add liquidation bonus (5%)
-        let bonus_value_usd = (repay_value_usd * LIQUIDATION_BONUS) / SCALE;
+        // Add liquidation bonus (default 5%)
+        let liquidation_bonus: i128 = env
+            .storage()
+            .instance()
+            .get(&DataKey::LiquidationBonus(collateral_asset.clone()))
+            .unwrap_or(DEFAULT_LIQUIDATION_BONUS);
+        let bonus_value_usd = mul_div_floor(env, repay_value_usd, liquidation_bonus, SCALE);
         let total_value_usd = repay_value_usd + bonus_value_usd;
         
-        // Convert to collateral amount
-        let collateral_to_seize = (total_value_usd * SCALE) / collateral_price;
+        // Convert to collateral amount (rounded down, in the borrower's favor)
+        let mut collateral_to_seize =
+            mul_div_floor(env, total_value_usd, Self::token_unit(env, collateral_asset), collateral_price);
         
-        // Check borrower has sufficient collateral
-        let borrower_collateral: i128 = env
-            .storage()
-            .persistent()
-            .get(&DataKey::UserCollateral(borrower.clone(), collateral_asset.clone()))
-            .unwrap_or(0);
+        // Check borrower has sufficient collateral (or shares)
+        let borrower_collateral: i128 = if seize_shares {
+            // Only shares the borrower opted in back their debt
+            if !Self::shares_used_as_collateral(env, borrower, collateral_asset) {
+                panic_with_error!(env, PoolError::SharesNotCollateral);
+            }
+
+            // Value the shares at the post-accrual exchange rate
+            let exchange_rate = Self::get_exchange_rate_projected(env, collateral_asset);
+            collateral_to_seize = mul_div_floor(env, collateral_to_seize, INITIAL_EXCHANGE_RATE, exchange_rate);
+
+            env.storage()
+                .persistent()
+                .get(&DataKey::UserShares(borrower.clone(), collateral_asset.clone()))
+                .unwrap_or(0)
+        } else {
+            env.storage()
+                .persistent()
+                .get(&DataKey::UserCollateral(borrower.clone(), collateral_asset.clone()))
+                .unwrap_or(0)
+        };
         
+        // Not enough collateral to cover the repayment plus bonus: seize all
+        // of it and scale the repayment down to match (rounded up, so the
+        // liquidator never gets collateral for less than its price)
         if borrower_collateral < collateral_to_seize {
-            panic!("Insufficient collateral to seize");
+            actual_repay = mul_div_ceil(env, actual_repay, borrower_collateral, collateral_to_seize);
+            collateral_to_seize = borrower_collateral;
         }
 
-        // ====================================================================
-        // STEP 4: Execute liquidation
-        // ====================================================================
-        
-        // Transfer repay_asset from liquidator to pool
-        let repay_token: Address = env.storage().instance().get(&DataKey::TokenAddress(repay_asset.clone())).unwrap();
-        let repay_token_client = token::Client::new(&env, &repay_token);
-        repay_token_client.transfer(&liquidator, &env.current_contract_address(), &actual_repay);
-        
-        // Reduce borrower's debt
-        let borrower_debt_principal: i128 = env
+        (borrower_debt, actual_repay, collateral_to_seize, borrower_collateral)
+    }
+
+    /// Get the repay needed to restore a borrower's health factor to 1.0
+    /// 
+    /// Accounts for the collateral (plus liquidation bonus) that a
+    /// liquidation seizes alongside the repayment.
+    /// 
+    /// # Arguments
+    /// * `borrower` - The borrower's address
+    /// * `repay_asset` - Debt asset being repaid
+    /// * `collateral_asset` - Collateral asset being seized
+    /// 
+    /// # Returns
+    /// Amount of `repay_asset` (0 if healthy or no repay can restore health)
+    pub fn get_repay_to_health(env: Env, borrower: Address, repay_asset: Symbol, collateral_asset: Symbol) -> i128 {
+        let position = Self::get_liquidation_position(&env, &borrower);
+        if !position.has_debt || position.health_factor >= SCALE {
+            return 0;
+        }
+
+        Self::calculate_repay_to_health(&env, &position, &repay_asset, &collateral_asset, SCALE)
+    }
+
+    /// Internal: Repay (in `repay_asset` units) that brings HF up to `target_hf`
+    /// 
+    /// Repaying `r` USD of debt seizes `r * (1 + bonus)` USD of collateral:
+    /// 
+    /// `T - r(1 + bonus) * LT = H(D - r)  =>  r = (HD - T) / (H - (1 + bonus) * LT)`
+    /// 
+    /// where `T` is the threshold-weighted collateral, `LT` is the seized
+    /// collateral's own liquidation threshold and `H` is the target.
+    /// 
+    /// Rounded up so the repay always reaches the target. Returns 0 when the
+    /// bonus is so large that liquidating cannot improve the position.
+    fn calculate_repay_to_health(
+        env: &Env,
+        position: &UserPosition,
+        repay_asset: &Symbol,
+        collateral_asset: &Symbol,
+        target_hf: i128,
+    ) -> i128 {
+        let liq_threshold: i128 = env
             .storage()
-            .persistent()
-            .get(&DataKey::UserDebt(borrower.clone(), repay_asset.clone()))
+            .instance()
+            .get(&DataKey::LiquidationThreshold(collateral_asset.clone()))
             .unwrap_or(0);
-        let new_debt = if actual_repay >= borrower_debt {
-            0
-        } else {
-            // Calculate new principal based on repayment
-            let debt_reduction_ratio = (actual_repay * INITIAL_EXCHANGE_RATE) / borrower_debt;
-            borrower_debt_principal - (borrower_debt_principal * debt_reduction_ratio) / INITIAL_EXCHANGE_RATE
-        };
+        let liquidation_bonus: i128 = env
+            .storage()
+            .instance()
+            .get(&DataKey::LiquidationBonus(collateral_asset.clone()))
+            .unwrap_or(DEFAULT_LIQUIDATION_BONUS);
+
+        let shortfall_usd = mul_div_ceil(env, position.debt_value_usd, target_hf, SCALE) - position.threshold_collateral_usd;
+        let denominator = target_hf - ((SCALE + liquidation_bonus) * liq_threshold) / SCALE;
+        if shortfall_usd <= 0 || denominator <= 0 {
+            return 0;
+        }
+        let repay_value_usd = mul_div_ceil(env, shortfall_usd, SCALE, denominator);
+
+        let oracle: Address = env.storage().instance().get(&DataKey::PriceOracle).unwrap();
+        let repay_price = Self::get_liquidation_price(env, &oracle, repay_asset);
+        mul_div_ceil(env, repay_value_usd, Self::token_unit(env, repay_asset), repay_price)
+    }
+
+    /// Internal: Whether a user has deposited collateral in any market,
+    /// opted in or not
+    fn has_deposited_collateral(env: &Env, user: &Address) -> bool {
+        Self::get_market_list(env).iter().any(|asset| {
+            env.storage()
+                .persistent()
+                .get::<_, i128>(&DataKey::UserCollateral(user.clone(), asset))
+                .unwrap_or(0)
+                > 0
+        })
+    }
+
+    /// Internal: Write off a borrower's remaining debt with no collateral behind it
+    /// 
+    /// The residual is removed from both `TotalBorrow` and `TotalSupply`, so
+    /// the loss is shared by all suppliers through a lower exchange rate.
+    /// Returns the amount written off.
+    fn write_off_bad_debt(env: &Env, borrower: &Address, asset: &Symbol) -> i128 {
+        let residual = Self::get_user_debt_with_interest(env, borrower.clone(), asset.clone());
+        if residual == 0 {
+            return 0;
+        }
+
+        Self::write_debt(env, borrower, asset, 0);
+
+        let total_borrow: i128 = env.storage().instance().get(&DataKey::TotalBorrow(asset.clone())).unwrap_or(0);
+        let new_total_borrow = if total_borrow > residual { total_borrow - residual } else { 0 };
+        env.storage().instance().set(&DataKey::TotalBorrow(asset.clone()), &new_total_borrow);
+
+        let total_supply: i128 = env.storage().instance().get(&DataKey::TotalSupply(asset.clone())).unwrap_or(0);
+        let new_total_supply = if total_supply > residual { total_supply - residual } else { 0 };
+        env.storage().instance().set(&DataKey::TotalSupply(asset.clone()), &new_total_supply);
+
+        let bad_debt: i128 = env.storage().instance().get(&DataKey::BadDebt(asset.clone())).unwrap_or(0);
+        env.storage().instance().set(&DataKey::BadDebt(asset.clone()), &(bad_debt + residual));
+
+        env.events().publish((symbol_short!("bad_debt"), asset.clone()), residual);
+        residual
+    }
+
+    /// Get the bad debt written off for an asset and not yet covered by reserves
+    pub fn get_bad_debt(env: Env, asset: Symbol) -> i128 {
+        env.storage().instance().get(&DataKey::BadDebt(asset)).unwrap_or(0)
+    }
+
+    /// Cover recorded bad debt with protocol reserves (admin only)
+    /// 
+    /// The write-off took the loss out of `TotalSupply`; covering moves
+    /// reserves back into it, so the supplier exchange rate recovers. No
+    /// tokens move. Whatever the reserves can't cover stays recorded.
+    /// 
+    /// # Arguments
+    /// * `asset` - Asset symbol
+    /// * `amount` - Most to cover (`i128::MAX` = as much as possible)
+    /// 
+    /// # Returns
+    /// Amount covered
+    /// 
+    /// # Events
+    /// Emits `("bad_cov", asset)` with `(covered, remaining)`
+    pub fn cover_bad_debt(env: Env, asset: Symbol, amount: i128) -> i128 {
+        Self::require_role(&env, Role::Owner);
+
+        if amount <= 0 {
+            panic_with_error!(&env, PoolError::AmountNotPositive);
+        }
+
+        Self::accrue_interest(&env, asset.clone());
+
+        let bad_debt: i128 = env.storage().instance().get(&DataKey::BadDebt(asset.clone())).unwrap_or(0);
+        let total_reserves: i128 = env.storage().instance().get(&DataKey::TotalReserves(asset.clone())).unwrap_or(0);
+        let covered = amount.min(bad_debt).min(total_reserves);
+
+        if covered > 0 {
+            let total_supply: i128 = env.storage().instance().get(&DataKey::TotalSupply(asset.clone())).unwrap_or(0);
+            let total_covered: i128 = env
+                .storage()
+                .instance()
+                .get(&DataKey::BadDebtCovered(asset.clone()))
+                .unwrap_or(0);
+            env.storage().instance().set(&DataKey::TotalReserves(asset.clone()), &(total_reserves - covered));
+            env.storage().instance().set(&DataKey::TotalSupply(asset.clone()), &(total_supply + covered));
+            env.storage().instance().set(&DataKey::BadDebt(asset.clone()), &(bad_debt - covered));
+            env.storage()
+                .instance()
+                .set(&DataKey::BadDebtCovered(asset.clone()), &(total_covered + covered));
+            Self::debug_check_solvency(&env, &asset);
+        }
+
+        env.events().publish((symbol_short!("bad_cov"), asset), (covered, bad_debt - covered));
+
+        covered
+    }
+
+    /// Get the number of liquidations executed against a borrower
+    pub fn get_liquidation_count(env: Env, borrower: Address) -> u32 {
         env.storage()
             .persistent()
-            .set(&DataKey::UserDebt(borrower.clone(), repay_asset.clone()), &new_debt);
-        
-        // Reduce total borrows
-        let total_borrow: i128 = env.storage().instance().get(&DataKey::TotalBorrow(repay_asset.clone())).unwrap_or(0);
-        let new_total_borrow = if total_borrow > actual_repay {
-            total_borrow - actual_repay
-        } else {
-            0
-        };
-        env.storage().instance().set(&DataKey::TotalBorrow(repay_asset.clone()), &new_total_borrow);
-        
-        // Transfer collateral from borrower to liquidator
-        let new_borrower_collateral = borrower_collateral - collateral_to_seize;
+            .get(&DataKey::LiquidationCount(borrower))
+            .unwrap_or(0)
+    }
+
+    /// Get the most recent liquidation against a borrower
+    /// 
+    /// # Returns
+    /// `(timestamp, repaid, seized)`, or `(0, 0, 0)` if never liquidated
+    pub fn get_last_liquidation(env: Env, borrower: Address) -> (u64, i128, i128) {
         env.storage()
             .persistent()
-            .set(&DataKey::UserCollateral(borrower.clone(), collateral_asset.clone()), &new_borrower_collateral);
-        
-        // Transfer collateral tokens to liquidator
-        let collateral_token: Address = env.storage().instance().get(&DataKey::TokenAddress(collateral_asset.clone())).unwrap();
-        let collateral_token_client = token::Client::new(&env, &collateral_token);
-        collateral_token_client.transfer(&env.current_contract_address(), &liquidator, &collateral_to_seize);
-
-        // ====================================================================
-        // STEP 5: Emit event and return
-        // ====================================================================
-        
-        env.events().publish(
-            (symbol_short!("liquidate"), liquidator, borrower),
-            (actual_repay, collateral_to_seize)
-        );
-
-        collateral_to_seize
+            .get(&DataKey::LastLiquidation(borrower))
+            .unwrap_or((0, 0, 0))
     }
 }
 
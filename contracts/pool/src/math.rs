@@ -0,0 +1,100 @@
+//! Overflow-safe fixed-point helpers for the pool math
+//!
+//! `a * b / denominator` is the shape of almost every pool calculation
+//! (valuing amounts at a price, converting shares at an exchange rate,
+//! scaling by a ratio). The product is computed at 256-bit width when it
+//! doesn't fit in an i128, so only a result that is itself out of range
+//! panics, and then with `PoolError::MathOverflow` instead of a trap.
+
+use soroban_sdk::{panic_with_error, Env};
+
+use crate::PoolError;
+
+const LOW_MASK: u128 = u64::MAX as u128;
+
+/// `a * b / denominator`, rounded toward zero (down for non-negative inputs)
+pub(crate) fn mul_div_floor(env: &Env, a: i128, b: i128, denominator: i128) -> i128 {
+    mul_div(env, a, b, denominator, false)
+}
+
+/// `a * b / denominator`, rounded away from zero (up for non-negative inputs)
+pub(crate) fn mul_div_ceil(env: &Env, a: i128, b: i128, denominator: i128) -> i128 {
+    mul_div(env, a, b, denominator, true)
+}
+
+fn mul_div(env: &Env, a: i128, b: i128, denominator: i128, round_up: bool) -> i128 {
+    if denominator == 0 {
+        panic_with_error!(env, PoolError::DivisionByZero);
+    }
+
+    // Fast path: the product fits
+    if let Some(product) = a.checked_mul(b) {
+        let quotient = product / denominator;
+        if round_up && product % denominator != 0 {
+            let direction = if (product < 0) == (denominator < 0) { 1 } else { -1 };
+            return quotient + direction;
+        }
+        return quotient;
+    }
+
+    let negative = (a < 0) ^ (b < 0) ^ (denominator < 0);
+    let (high, low) = wide_mul(a.unsigned_abs(), b.unsigned_abs());
+    let (mut quotient, remainder) = match wide_div(high, low, denominator.unsigned_abs()) {
+        Some(result) => result,
+        None => panic_with_error!(env, PoolError::MathOverflow),
+    };
+    if round_up && remainder != 0 {
+        quotient += 1;
+    }
+    if quotient > i128::MAX as u128 {
+        panic_with_error!(env, PoolError::MathOverflow);
+    }
+
+    if negative {
+        -(quotient as i128)
+    } else {
+        quotient as i128
+    }
+}
+
+/// Full 256-bit product of two u128s as `(high, low)` halves
+fn wide_mul(a: u128, b: u128) -> (u128, u128) {
+    let (a_high, a_low) = (a >> 64, a & LOW_MASK);
+    let (b_high, b_low) = (b >> 64, b & LOW_MASK);
+
+    let low_low = a_low * b_low;
+    let low_high = a_low * b_high;
+    let high_low = a_high * b_low;
+    let high_high = a_high * b_high;
+
+    // Middle 64-bit column plus the carry out of the lowest one
+    let middle = (low_low >> 64) + (low_high & LOW_MASK) + (high_low & LOW_MASK);
+
+    let low = (low_low & LOW_MASK) | (middle << 64);
+    let high = high_high + (low_high >> 64) + (high_low >> 64) + (middle >> 64);
+    (high, low)
+}
+
+/// Divide a 256-bit `(high, low)` value by a u128, returning
+/// `(quotient, remainder)`, or `None` if the quotient needs more than 128 bits
+fn wide_div(high: u128, low: u128, divisor: u128) -> Option<(u128, u128)> {
+    if high >= divisor {
+        return None;
+    }
+
+    // Schoolbook long division, one bit of `low` at a time. The running
+    // remainder stays below `divisor`; `carry` catches the bit shifted out.
+    let mut remainder = high;
+    let mut quotient: u128 = 0;
+    for bit in (0..128).rev() {
+        let carry = remainder >> 127;
+        remainder = (remainder << 1) | ((low >> bit) & 1);
+        quotient <<= 1;
+        if carry == 1 || remainder >= divisor {
+            remainder = remainder.wrapping_sub(divisor);
+            quotient |= 1;
+        }
+    }
+
+    Some((quotient, remainder))
+}
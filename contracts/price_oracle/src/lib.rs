@@ -9,9 +9,15 @@
 //! ## Features
 //!
 //! - **Admin-only price updates**: Only authorized keeper can set prices
+//! - **Multiple keepers**: Admin-approved keepers submit prices independently;
+//!   the price is the median of their fresh submissions
 //! - **Staleness checks**: Prices can be verified as fresh
+//! - **Circuit breaker**: Optional per-asset cap on how far one update may
+//!   move the price (admin can override with `force_set_price`)
+//! - **TWAP**: Time-weighted average over the most recent price updates
 //! - **Chaos mode**: 50% price crash simulation for demos
-//! - **Multiple assets**: Supports XLM, USDC, and extensible for more
+//! - **Multiple assets**: Supports XLM, USDC, and any asset registered with
+//!   its token decimals via `register_asset`
 //!
 //! ## Price Scaling
 //!
@@ -27,7 +33,9 @@
 //! 3. Pool contract calls `get_price(XLM)` to value collateral
 //! 4. For crash demo: keeper calls `set_price(XLM, price * 0.5)` or uses --crash flag
 
-use soroban_sdk::{contract, contractimpl, contracttype, symbol_short, Address, Env, Symbol};
+use soroban_sdk::{
+    contract, contracterror, contractimpl, contracttype, panic_with_error, symbol_short, Address, Env, Symbol, Vec,
+};
 
 // ============================================================================
 // CONSTANTS
@@ -41,6 +49,21 @@ const PRICE_SCALE: i128 = 10_000_000;
 /// Default staleness threshold: 1 hour (3600 seconds)
 const DEFAULT_STALENESS_THRESHOLD: u64 = 3600;
 
+/// Token decimals assumed for unregistered assets (Stellar's native 7)
+const DEFAULT_DECIMALS: u32 = 7;
+
+/// Maximum supported token decimals
+const MAX_DECIMALS: u32 = 18;
+
+/// Number of recent price observations kept per asset for the TWAP
+const MAX_OBSERVATIONS: u32 = 12;
+
+/// Basis points denominator (10_000 = 100%)
+const BPS_SCALE: i128 = 10_000;
+
+/// Default keeper quorum: any single fresh submission updates the price
+const DEFAULT_MIN_SUBMISSIONS: u32 = 1;
+
 /// Asset symbols
 pub const XLM: Symbol = symbol_short!("XLM");
 pub const USDC: Symbol = symbol_short!("USDC");
@@ -49,6 +72,32 @@ pub const USDC: Symbol = symbol_short!("USDC");
 // STORAGE
 // ============================================================================
 
+/// Errors returned by the price oracle
+///
+/// Codes are part of the contract interface: existing variants are never
+/// renumbered, new ones are appended.
+#[contracterror]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
+#[repr(u32)]
+pub enum OracleError {
+    /// The oracle was already initialized
+    AlreadyInitialized = 1,
+    /// A price must be positive
+    PriceNotPositive = 2,
+    /// No price has been set for the asset
+    PriceNotSet = 3,
+    /// The price is older than the accepted age
+    PriceStale = 4,
+    /// The update moves the price further than the asset's max deviation
+    DeviationTooLarge = 5,
+    /// The caller is not a registered keeper
+    NotKeeper = 6,
+    /// The keeper is already registered
+    KeeperAlreadyAdded = 7,
+    /// A configuration parameter is out of range
+    InvalidParameter = 8,
+}
+
 /// Storage keys for the price oracle
 #[derive(Clone)]
 #[contracttype]
@@ -61,6 +110,34 @@ pub enum DataKey {
     LastUpdate(Symbol),
     /// Staleness threshold in seconds
     StalenessThreshold,
+    /// Token decimals for an asset (defaults to 7)
+    Decimals(Symbol),
+    /// Recent `(timestamp, price)` observations for an asset, oldest first
+    Observations(Symbol),
+    /// Maximum move per update for an asset in bps (0 = no limit)
+    MaxDeviationBps(Symbol),
+    /// Addresses allowed to submit prices via `submit_price`
+    Keepers,
+    /// A keeper's latest `(timestamp, price)` submission for an asset
+    Submission(Symbol, Address),
+    /// Every asset that has been registered or priced, in first-seen order
+    Assets,
+    /// Fresh keeper submissions required before a median is stored
+    MinSubmissions,
+}
+
+/// Event payload for a stored price change
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[contracttype]
+pub struct PriceUpdate {
+    /// Asset symbol
+    pub asset: Symbol,
+    /// Price before the update (0 if never set)
+    pub old_price: i128,
+    /// Price after the update
+    pub new_price: i128,
+    /// Ledger timestamp of the update
+    pub timestamp: u64,
 }
 
 // ============================================================================
@@ -90,7 +167,7 @@ impl PriceOracle {
     /// - Staleness threshold: 1 hour
     pub fn initialize(env: Env, admin: Address) {
         if env.storage().instance().has(&DataKey::Admin) {
-            panic!("Already initialized");
+            panic_with_error!(&env, OracleError::AlreadyInitialized);
         }
 
         // Store admin
@@ -108,6 +185,7 @@ impl PriceOracle {
         env.storage()
             .instance()
             .set(&DataKey::LastUpdate(USDC), &env.ledger().timestamp());
+        Self::track_asset(&env, &USDC);
 
         // Emit initialization event
         env.events().publish((symbol_short!("init"),), admin);
@@ -125,27 +203,25 @@ impl PriceOracle {
     /// * `asset` - Asset symbol (e.g., XLM, USDC)
     /// * `price` - Price in USD scaled by 1e7 (e.g., $0.30 = 3_000_000)
     ///
+    /// # Panics
+    /// - If the price moves further than the asset's max deviation
+    ///
     /// # Events
-    /// Emits `("set_price", asset)` with the new price
+    /// Emits `("set_price", asset)` with a `PriceUpdate`
     pub fn set_price(env: Env, asset: Symbol, price: i128) {
         // Verify admin authorization
         let admin: Address = env.storage().instance().get(&DataKey::Admin).unwrap();
         admin.require_auth();
 
         if price <= 0 {
-            panic!("Price must be positive");
+            panic_with_error!(&env, OracleError::PriceNotPositive);
         }
 
-        // Store price and timestamp
-        env.storage()
-            .instance()
-            .set(&DataKey::Price(asset.clone()), &price);
-        env.storage()
-            .instance()
-            .set(&DataKey::LastUpdate(asset.clone()), &env.ledger().timestamp());
+        Self::check_deviation(&env, &asset, price);
+        let update = Self::store_price(&env, &asset, price);
 
         // Emit event for indexers/UI
-        env.events().publish((symbol_short!("set_price"), asset), price);
+        env.events().publish((symbol_short!("set_price"), asset), update);
     }
 
     /// Set multiple prices in a single transaction
@@ -160,38 +236,65 @@ impl PriceOracle {
         admin.require_auth();
 
         if xlm_price <= 0 || usdc_price <= 0 {
-            panic!("Prices must be positive");
+            panic_with_error!(&env, OracleError::PriceNotPositive);
         }
 
-        let timestamp = env.ledger().timestamp();
-
-        // Set XLM
-        env.storage().instance().set(&DataKey::Price(XLM), &xlm_price);
-        env.storage().instance().set(&DataKey::LastUpdate(XLM), &timestamp);
+        Self::check_deviation(&env, &XLM, xlm_price);
+        Self::check_deviation(&env, &USDC, usdc_price);
 
-        // Set USDC
-        env.storage().instance().set(&DataKey::Price(USDC), &usdc_price);
-        env.storage().instance().set(&DataKey::LastUpdate(USDC), &timestamp);
+        let xlm_update = Self::store_price(&env, &XLM, xlm_price);
+        let usdc_update = Self::store_price(&env, &USDC, usdc_price);
 
         // Emit events
-        env.events().publish((symbol_short!("set_price"), XLM), xlm_price);
-        env.events().publish((symbol_short!("set_price"), USDC), usdc_price);
+        env.events().publish((symbol_short!("set_price"), XLM), xlm_update);
+        env.events().publish((symbol_short!("set_price"), USDC), usdc_update);
+    }
+
+    /// Set price for an asset, bypassing the deviation check
+    ///
+    /// Admin override for genuine large moves that the circuit breaker
+    /// would otherwise reject.
+    ///
+    /// # Arguments
+    /// * `asset` - Asset symbol
+    /// * `price` - Price in USD scaled by 1e7
+    ///
+    /// # Events
+    /// Emits `("force_px", asset)` with a `PriceUpdate`
+    pub fn force_set_price(env: Env, asset: Symbol, price: i128) {
+        let admin: Address = env.storage().instance().get(&DataKey::Admin).unwrap();
+        admin.require_auth();
+
+        if price <= 0 {
+            panic_with_error!(&env, OracleError::PriceNotPositive);
+        }
+
+        let update = Self::store_price(&env, &asset, price);
+
+        env.events().publish((symbol_short!("force_px"), asset), update);
     }
 
-    /// Simulate a price crash (50% drop) for demo purposes
+    /// Simulate a price crash for demo purposes
     ///
     /// This is a convenience function for the chaos mode demo.
-    /// It takes the CURRENT price and halves it.
+    /// It takes the CURRENT price and drops it by `drop_bps`. Like
+    /// `force_set_price`, it is not subject to the deviation check.
     ///
     /// # Arguments
     /// * `asset` - Asset to crash (typically XLM)
+    /// * `drop_bps` - Drop scaled by 1e7 (30% = 3_000_000), above 0 and at
+    ///   most 100%. The price never falls below 1, the smallest quote.
     ///
     /// # Events
-    /// Emits `("crash", asset)` with the crashed price
-    pub fn crash_price(env: Env, asset: Symbol) {
+    /// Emits `("crash", asset)` with a `PriceUpdate`
+    pub fn crash_price(env: Env, asset: Symbol, drop_bps: i128) {
         let admin: Address = env.storage().instance().get(&DataKey::Admin).unwrap();
         admin.require_auth();
 
+        if drop_bps <= 0 || drop_bps > PRICE_SCALE {
+            panic_with_error!(&env, OracleError::InvalidParameter);
+        }
+
         let current_price: i128 = env
             .storage()
             .instance()
@@ -199,22 +302,219 @@ impl PriceOracle {
             .unwrap_or(0);
 
         if current_price == 0 {
-            panic!("Cannot crash: price not set");
+            panic_with_error!(&env, OracleError::PriceNotSet);
+        }
+
+        let crashed_price = (current_price * (PRICE_SCALE - drop_bps) / PRICE_SCALE).max(1);
+
+        let update = Self::store_price(&env, &asset, crashed_price);
+
+        // Emit crash event
+        env.events()
+            .publish((symbol_short!("crash"), asset), update);
+    }
+
+    /// Simulate a 50% price crash (see `crash_price`)
+    pub fn crash_price_half(env: Env, asset: Symbol) {
+        Self::crash_price(env, asset, PRICE_SCALE / 2);
+    }
+
+    // ========================================================================
+    // KEEPERS
+    // ========================================================================
+
+    /// Authorize a keeper to submit prices (admin only)
+    pub fn add_keeper(env: Env, keeper: Address) {
+        let admin: Address = env.storage().instance().get(&DataKey::Admin).unwrap();
+        admin.require_auth();
+
+        let mut keepers = Self::get_keepers(env.clone());
+        if keepers.contains(&keeper) {
+            panic_with_error!(&env, OracleError::KeeperAlreadyAdded);
         }
+        keepers.push_back(keeper.clone());
+        env.storage().instance().set(&DataKey::Keepers, &keepers);
+
+        env.events().publish((symbol_short!("keepr_add"),), keeper);
+    }
+
+    /// Remove a keeper (admin only)
+    ///
+    /// Its past submissions stop counting toward the median immediately.
+    pub fn remove_keeper(env: Env, keeper: Address) {
+        let admin: Address = env.storage().instance().get(&DataKey::Admin).unwrap();
+        admin.require_auth();
+
+        let mut keepers = Self::get_keepers(env.clone());
+        let index = keepers.first_index_of(&keeper).unwrap_or_else(|| panic_with_error!(&env, OracleError::NotKeeper));
+        keepers.remove(index);
+        env.storage().instance().set(&DataKey::Keepers, &keepers);
+
+        env.events().publish((symbol_short!("keeper_rm"),), keeper);
+    }
+
+    /// Get the authorized keepers
+    pub fn get_keepers(env: Env) -> Vec<Address> {
+        env.storage()
+            .instance()
+            .get(&DataKey::Keepers)
+            .unwrap_or(Vec::new(&env))
+    }
+
+    /// Submit a keeper's price for an asset
+    ///
+    /// The stored price (and TWAP observation) becomes the median of all
+    /// keepers' fresh submissions, subject to the asset's deviation limit.
+    /// Until at least `get_min_submissions` fresh submissions exist the
+    /// submission is recorded but the stored price is left unchanged.
+    ///
+    /// # Arguments
+    /// * `keeper` - An authorized keeper (must authorize)
+    /// * `asset` - Asset symbol
+    /// * `price` - Price in USD scaled by 1e7
+    ///
+    /// # Events
+    /// Emits `("submit", asset, keeper)` with the submitted price
+    pub fn submit_price(env: Env, keeper: Address, asset: Symbol, price: i128) {
+        keeper.require_auth();
+
+        if !Self::get_keepers(env.clone()).contains(&keeper) {
+            panic_with_error!(&env, OracleError::NotKeeper);
+        }
+        if price <= 0 {
+            panic_with_error!(&env, OracleError::PriceNotPositive);
+        }
+
+        env.storage().instance().set(
+            &DataKey::Submission(asset.clone(), keeper.clone()),
+            &(env.ledger().timestamp(), price),
+        );
+
+        if let Some(median) = Self::median_price(&env, &asset) {
+            Self::check_deviation(&env, &asset, median);
+            Self::store_price(&env, &asset, median);
+        }
+
+        env.events()
+            .publish((symbol_short!("submit"), asset, keeper), price);
+    }
+
+    /// Internal: Median of keepers' submissions within the staleness window
+    ///
+    /// `None` unless the fresh submissions meet the configured quorum.
+    fn median_price(env: &Env, asset: &Symbol) -> Option<i128> {
+        let threshold: u64 = env
+            .storage()
+            .instance()
+            .get(&DataKey::StalenessThreshold)
+            .unwrap_or(DEFAULT_STALENESS_THRESHOLD);
+        let now = env.ledger().timestamp();
+
+        // Collect fresh submissions in ascending order
+        let mut prices: Vec<i128> = Vec::new(env);
+        for keeper in Self::get_keepers(env.clone()).iter() {
+            let submission: Option<(u64, i128)> = env
+                .storage()
+                .instance()
+                .get(&DataKey::Submission(asset.clone(), keeper));
+            if let Some((timestamp, price)) = submission {
+                if now > timestamp && now - timestamp > threshold {
+                    continue;
+                }
+                let mut index = 0;
+                while index < prices.len() && prices.get(index).unwrap() < price {
+                    index += 1;
+                }
+                prices.insert(index, price);
+            }
+        }
+
+        let count = prices.len();
+        if count == 0 || count < Self::get_min_submissions(env.clone()) {
+            return None;
+        }
+        let mid = count / 2;
+        if count % 2 == 1 {
+            Some(prices.get(mid).unwrap())
+        } else {
+            Some((prices.get(mid - 1).unwrap() + prices.get(mid).unwrap()) / 2)
+        }
+    }
+
+    /// Internal: Reject a price too far from the current one
+    fn check_deviation(env: &Env, asset: &Symbol, price: i128) {
+        let max_deviation_bps: i128 = env
+            .storage()
+            .instance()
+            .get(&DataKey::MaxDeviationBps(asset.clone()))
+            .unwrap_or(0);
+        if max_deviation_bps == 0 {
+            return;
+        }
+
+        let current_price: i128 = env
+            .storage()
+            .instance()
+            .get(&DataKey::Price(asset.clone()))
+            .unwrap_or(0);
+        if current_price == 0 {
+            return;
+        }
+
+        let deviation = (price - current_price).abs() * BPS_SCALE / current_price;
+        if deviation > max_deviation_bps {
+            panic_with_error!(env, OracleError::DeviationTooLarge);
+        }
+    }
 
-        // Apply 50% reduction
-        let crashed_price = current_price / 2;
+    /// Internal: Store a price, its timestamp and a TWAP observation
+    ///
+    /// Returns the change as a `PriceUpdate` for the caller's event.
+    fn store_price(env: &Env, asset: &Symbol, price: i128) -> PriceUpdate {
+        let timestamp = env.ledger().timestamp();
+        let old_price: i128 = env
+            .storage()
+            .instance()
+            .get(&DataKey::Price(asset.clone()))
+            .unwrap_or(0);
 
         env.storage()
             .instance()
-            .set(&DataKey::Price(asset.clone()), &crashed_price);
+            .set(&DataKey::Price(asset.clone()), &price);
         env.storage()
             .instance()
-            .set(&DataKey::LastUpdate(asset.clone()), &env.ledger().timestamp());
+            .set(&DataKey::LastUpdate(asset.clone()), &timestamp);
+        Self::track_asset(env, asset);
 
-        // Emit crash event
-        env.events()
-            .publish((symbol_short!("crash"), asset), crashed_price);
+        let mut observations: Vec<(u64, i128)> = env
+            .storage()
+            .instance()
+            .get(&DataKey::Observations(asset.clone()))
+            .unwrap_or(Vec::new(env));
+
+        // Several updates in one ledger: only the last one holds any time
+        match observations.last() {
+            Some((last_timestamp, _)) if last_timestamp == timestamp => {
+                observations.set(observations.len() - 1, (timestamp, price));
+            }
+            _ => {
+                if observations.len() >= MAX_OBSERVATIONS {
+                    observations.pop_front();
+                }
+                observations.push_back((timestamp, price));
+            }
+        }
+
+        env.storage()
+            .instance()
+            .set(&DataKey::Observations(asset.clone()), &observations);
+
+        PriceUpdate {
+            asset: asset.clone(),
+            old_price,
+            new_price: price,
+            timestamp,
+        }
     }
 
     // ========================================================================
@@ -226,6 +526,10 @@ impl PriceOracle {
     /// # Arguments
     /// * `asset` - Asset symbol
     ///
+    /// This is the stored price, the same one `get_price_safe` and
+    /// `get_price_fresh` check: keeper submissions update it to their
+    /// median as they arrive.
+    ///
     /// # Returns
     /// Price in USD (scaled by 1e7), or 0 if not set
     pub fn get_price(env: Env, asset: Symbol) -> i128 {
@@ -250,7 +554,7 @@ impl PriceOracle {
             .unwrap_or(0);
 
         if price == 0 {
-            panic!("Price not set for asset");
+            panic_with_error!(&env, OracleError::PriceNotSet);
         }
 
         let last_update: u64 = env
@@ -267,12 +571,103 @@ impl PriceOracle {
 
         let current_time = env.ledger().timestamp();
         if current_time > last_update && current_time - last_update > threshold {
-            panic!("Price is stale");
+            panic_with_error!(&env, OracleError::PriceStale);
+        }
+
+        price
+    }
+
+    /// Get price with a caller-chosen freshness bound
+    ///
+    /// Like `get_price_safe`, but consumers (e.g. the lending pool) pass
+    /// their own maximum age instead of relying on the oracle's threshold.
+    ///
+    /// # Arguments
+    /// * `asset` - Asset symbol
+    /// * `max_age` - Maximum accepted age of the price in seconds
+    ///
+    /// # Panics
+    /// - If price is not set
+    /// - If price is older than `max_age`
+    pub fn get_price_fresh(env: Env, asset: Symbol, max_age: u64) -> i128 {
+        let price: i128 = env
+            .storage()
+            .instance()
+            .get(&DataKey::Price(asset.clone()))
+            .unwrap_or(0);
+
+        if price == 0 {
+            panic_with_error!(&env, OracleError::PriceNotSet);
+        }
+
+        let last_update: u64 = env
+            .storage()
+            .instance()
+            .get(&DataKey::LastUpdate(asset))
+            .unwrap_or(0);
+
+        let current_time = env.ledger().timestamp();
+        if current_time > last_update && current_time - last_update > max_age {
+            panic_with_error!(&env, OracleError::PriceStale);
         }
 
         price
     }
 
+    /// Get the time-weighted average price over a recent window
+    ///
+    /// Each observation is weighted by how long it was the current price
+    /// within the window. Only the last 12 updates are kept, so the window
+    /// is effectively capped by how often prices are pushed.
+    ///
+    /// # Arguments
+    /// * `asset` - Asset symbol
+    /// * `window_secs` - Length of the averaging window in seconds
+    ///
+    /// # Returns
+    /// TWAP in USD (scaled by 1e7), or the latest price if fewer than
+    /// two observations exist
+    pub fn get_twap(env: Env, asset: Symbol, window_secs: u64) -> i128 {
+        if window_secs == 0 {
+            panic_with_error!(&env, OracleError::InvalidParameter);
+        }
+
+        let observations: Vec<(u64, i128)> = env
+            .storage()
+            .instance()
+            .get(&DataKey::Observations(asset.clone()))
+            .unwrap_or(Vec::new(&env));
+        if observations.len() < 2 {
+            return Self::get_price(env, asset);
+        }
+
+        let now = env.ledger().timestamp();
+        let window_start = now.saturating_sub(window_secs);
+
+        let mut weighted_sum: i128 = 0;
+        let mut total_time: i128 = 0;
+        for i in 0..observations.len() {
+            let (timestamp, price) = observations.get(i).unwrap();
+            let held_until = if i + 1 < observations.len() {
+                observations.get(i + 1).unwrap().0
+            } else {
+                now
+            };
+            let held_from = if timestamp > window_start { timestamp } else { window_start };
+
+            if held_until > held_from {
+                let duration = (held_until - held_from) as i128;
+                weighted_sum += price * duration;
+                total_time += duration;
+            }
+        }
+
+        if total_time == 0 {
+            return Self::get_price(env, asset);
+        }
+        weighted_sum / total_time
+    }
+
     /// Get timestamp of last price update
     pub fn get_last_update(env: Env, asset: Symbol) -> u64 {
         env.storage()
@@ -299,6 +694,123 @@ impl PriceOracle {
         current_time > last_update && current_time - last_update > threshold
     }
 
+    /// Find the asset whose price was updated longest ago
+    ///
+    /// # Returns
+    /// `(asset, last_update)` for the least recently updated asset
+    ///
+    /// # Panics
+    /// - If no asset has been registered or priced
+    pub fn oldest_update(env: Env) -> (Symbol, u64) {
+        let mut oldest: Option<(Symbol, u64)> = None;
+        for asset in Self::get_assets(env.clone()).iter() {
+            let last_update = Self::get_last_update(env.clone(), asset.clone());
+            let is_older = match &oldest {
+                Some((_, oldest_update)) => last_update < *oldest_update,
+                None => true,
+            };
+            if is_older {
+                oldest = Some((asset, last_update));
+            }
+        }
+
+        oldest.unwrap_or_else(|| panic_with_error!(&env, OracleError::PriceNotSet))
+    }
+
+    /// Check if any tracked asset's price is stale
+    pub fn any_stale(env: Env) -> bool {
+        Self::get_assets(env.clone())
+            .iter()
+            .any(|asset| Self::is_stale(env.clone(), asset))
+    }
+
+    // ========================================================================
+    // ASSET REGISTRY
+    // ========================================================================
+
+    /// Register an asset's token decimals (admin only)
+    ///
+    /// # Arguments
+    /// * `asset` - Asset symbol
+    /// * `decimals` - Number of decimals of the asset's token (0 to 18)
+    ///
+    /// # Events
+    /// Emits `("reg_asset", asset)` with the decimals
+    pub fn register_asset(env: Env, asset: Symbol, decimals: u32) {
+        let admin: Address = env.storage().instance().get(&DataKey::Admin).unwrap();
+        admin.require_auth();
+
+        if decimals > MAX_DECIMALS {
+            panic_with_error!(&env, OracleError::InvalidParameter);
+        }
+
+        env.storage()
+            .instance()
+            .set(&DataKey::Decimals(asset.clone()), &decimals);
+        Self::track_asset(&env, &asset);
+
+        env.events().publish((symbol_short!("reg_asset"), asset), decimals);
+    }
+
+    /// Get every asset that has been registered or priced
+    pub fn get_assets(env: Env) -> Vec<Symbol> {
+        env.storage()
+            .instance()
+            .get(&DataKey::Assets)
+            .unwrap_or(Vec::new(&env))
+    }
+
+    /// Internal: Add an asset to the asset list if not already present
+    fn track_asset(env: &Env, asset: &Symbol) {
+        let mut assets = Self::get_assets(env.clone());
+        if !assets.contains(asset) {
+            assets.push_back(asset.clone());
+            env.storage().instance().set(&DataKey::Assets, &assets);
+        }
+    }
+
+    /// Get an asset's token decimals (7 if not registered)
+    pub fn get_decimals(env: Env, asset: Symbol) -> u32 {
+        env.storage()
+            .instance()
+            .get(&DataKey::Decimals(asset))
+            .unwrap_or(DEFAULT_DECIMALS)
+    }
+
+    /// Convert an asset amount to USD value
+    ///
+    /// # Arguments
+    /// * `asset` - Asset symbol
+    /// * `amount` - Amount in the asset's base units (per its decimals)
+    ///
+    /// # Returns
+    /// USD value (scaled by 1e7), or 0 if the price is not set
+    pub fn asset_to_usd(env: Env, asset: Symbol, amount: i128) -> i128 {
+        let price = Self::get_price(env.clone(), asset.clone());
+        if price == 0 {
+            return 0;
+        }
+        let unit = 10i128.pow(Self::get_decimals(env, asset));
+        (amount * price) / unit
+    }
+
+    /// Convert a USD value to an asset amount
+    ///
+    /// # Arguments
+    /// * `asset` - Asset symbol
+    /// * `usd_amount` - USD value (scaled by 1e7)
+    ///
+    /// # Returns
+    /// Amount in the asset's base units (per its decimals)
+    pub fn usd_to_asset(env: Env, asset: Symbol, usd_amount: i128) -> i128 {
+        let price = Self::get_price(env.clone(), asset.clone());
+        if price == 0 {
+            panic_with_error!(&env, OracleError::PriceNotSet);
+        }
+        let unit = 10i128.pow(Self::get_decimals(env, asset));
+        (usd_amount * unit) / price
+    }
+
     // ========================================================================
     // CONVENIENCE FUNCTIONS
     // ========================================================================
@@ -321,11 +833,7 @@ impl PriceOracle {
     /// # Returns
     /// USD value (scaled by 1e7)
     pub fn xlm_to_usd(env: Env, xlm_amount: i128) -> i128 {
-        let price = Self::get_price(env, XLM);
-        if price == 0 {
-            return 0;
-        }
-        (xlm_amount * price) / PRICE_SCALE
+        Self::asset_to_usd(env, XLM, xlm_amount)
     }
 
     /// Convert USD value to XLM amount
@@ -336,11 +844,7 @@ impl PriceOracle {
     /// # Returns
     /// XLM amount (in base units)
     pub fn usd_to_xlm(env: Env, usd_amount: i128) -> i128 {
-        let price = Self::get_price(env, XLM);
-        if price == 0 {
-            panic!("XLM price not set");
-        }
-        (usd_amount * PRICE_SCALE) / price
+        Self::usd_to_asset(env, XLM, usd_amount)
     }
 
     /// Get both XLM and USDC prices
@@ -394,6 +898,61 @@ impl PriceOracle {
             .get(&DataKey::StalenessThreshold)
             .unwrap_or(DEFAULT_STALENESS_THRESHOLD)
     }
+
+    /// Set the maximum price move per update for an asset
+    ///
+    /// # Arguments
+    /// * `asset` - Asset symbol
+    /// * `max_deviation_bps` - Cap in basis points (e.g. 2000 = 20%), 0 disables
+    pub fn set_max_deviation(env: Env, asset: Symbol, max_deviation_bps: i128) {
+        let admin: Address = env.storage().instance().get(&DataKey::Admin).unwrap();
+        admin.require_auth();
+
+        if max_deviation_bps < 0 {
+            panic_with_error!(&env, OracleError::InvalidParameter);
+        }
+
+        env.storage()
+            .instance()
+            .set(&DataKey::MaxDeviationBps(asset.clone()), &max_deviation_bps);
+
+        env.events().publish((symbol_short!("max_dev"), asset), max_deviation_bps);
+    }
+
+    /// Get the maximum price move per update for an asset in bps (0 = no limit)
+    pub fn get_max_deviation(env: Env, asset: Symbol) -> i128 {
+        env.storage()
+            .instance()
+            .get(&DataKey::MaxDeviationBps(asset))
+            .unwrap_or(0)
+    }
+
+    /// Set the number of fresh keeper submissions needed to update a price
+    ///
+    /// # Arguments
+    /// * `min_submissions` - Quorum, at least 1 (e.g. 2 of 3 keepers tolerates one faulty keeper)
+    pub fn set_min_submissions(env: Env, min_submissions: u32) {
+        let admin: Address = env.storage().instance().get(&DataKey::Admin).unwrap();
+        admin.require_auth();
+
+        if min_submissions == 0 {
+            panic_with_error!(&env, OracleError::InvalidParameter);
+        }
+
+        env.storage()
+            .instance()
+            .set(&DataKey::MinSubmissions, &min_submissions);
+
+        env.events().publish((symbol_short!("min_subs"),), min_submissions);
+    }
+
+    /// Get the number of fresh keeper submissions needed to update a price
+    pub fn get_min_submissions(env: Env) -> u32 {
+        env.storage()
+            .instance()
+            .get(&DataKey::MinSubmissions)
+            .unwrap_or(DEFAULT_MIN_SUBMISSIONS)
+    }
 }
 
 // ============================================================================
@@ -403,7 +962,7 @@ impl PriceOracle {
 #[cfg(test)]
 mod test {
     use super::*;
-    use soroban_sdk::{testutils::Address as _, Env};
+    use soroban_sdk::{testutils::{Address as _, Events, Ledger}, Env, IntoVal};
 
     #[test]
     fn test_initialize() {
@@ -475,10 +1034,91 @@ mod test {
         assert_eq!(client.get_xlm_price(), 3_000_000);
 
         // Crash it (50% drop)
-        client.crash_price(&XLM);
+        client.crash_price_half(&XLM);
         assert_eq!(client.get_xlm_price(), 1_500_000); // $0.15
     }
 
+    #[test]
+    fn test_crash_price_by_percentage() {
+        let env = Env::default();
+        env.mock_all_auths();
+
+        let contract_id = env.register_contract(None, PriceOracle);
+        let client = PriceOracleClient::new(&env, &contract_id);
+        client.initialize(&Address::generate(&env));
+
+        // 30% off $0.30
+        client.set_price(&XLM, &3_000_000);
+        client.crash_price(&XLM, &3_000_000);
+        assert_eq!(client.get_xlm_price(), 2_100_000); // $0.21
+
+        // 90% off $0.21
+        client.crash_price(&XLM, &9_000_000);
+        assert_eq!(client.get_xlm_price(), 210_000); // $0.021
+
+        // A full drop bottoms out at the smallest quote
+        client.crash_price(&XLM, &10_000_000);
+        assert_eq!(client.get_xlm_price(), 1);
+
+        let invalid = soroban_sdk::Error::from_contract_error(OracleError::InvalidParameter as u32);
+        assert_eq!(client.try_crash_price(&XLM, &0), Err(Ok(invalid)));
+        assert_eq!(client.try_crash_price(&XLM, &10_000_001), Err(Ok(invalid)));
+    }
+
+    #[test]
+    fn test_price_update_event_payload() {
+        let env = Env::default();
+        env.mock_all_auths();
+
+        let contract_id = env.register_contract(None, PriceOracle);
+        let client = PriceOracleClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        client.initialize(&admin);
+        env.ledger().with_mut(|li| li.timestamp = 1_000);
+        client.set_price(&XLM, &3_000_000);
+
+        client.set_price(&XLM, &2_800_000);
+        let (emitter, topics, data) = env.events().all().last().unwrap();
+        assert_eq!(emitter, contract_id);
+        assert_eq!(topics, (symbol_short!("set_price"), XLM).into_val(&env));
+        let update: PriceUpdate = data.into_val(&env);
+        assert_eq!(
+            update,
+            PriceUpdate { asset: XLM, old_price: 3_000_000, new_price: 2_800_000, timestamp: 1_000 }
+        );
+
+        client.crash_price_half(&XLM);
+        let (_, topics, data) = env.events().all().last().unwrap();
+        assert_eq!(topics, (symbol_short!("crash"), XLM).into_val(&env));
+        let update: PriceUpdate = data.into_val(&env);
+        assert_eq!(update.old_price, 2_800_000);
+        assert_eq!(update.new_price, 1_400_000);
+    }
+
+    #[test]
+    fn test_oldest_update_reports_lagging_feed() {
+        let env = Env::default();
+        env.mock_all_auths();
+
+        let contract_id = env.register_contract(None, PriceOracle);
+        let client = PriceOracleClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        env.ledger().with_mut(|li| li.timestamp = 1_000);
+        client.initialize(&admin);
+        client.set_price(&XLM, &3_000_000);
+        assert!(!client.any_stale());
+
+        // Only XLM keeps being updated
+        env.ledger().with_mut(|li| li.timestamp += DEFAULT_STALENESS_THRESHOLD + 1);
+        client.set_price(&XLM, &3_100_000);
+
+        assert_eq!(client.oldest_update(), (USDC, 1_000));
+        assert!(client.any_stale());
+        assert!(!client.is_stale(&XLM));
+    }
+
     #[test]
     fn test_xlm_to_usd_conversion() {
         let env = Env::default();
@@ -520,7 +1160,223 @@ mod test {
     }
 
     #[test]
-    #[should_panic(expected = "Already initialized")]
+    fn test_asset_to_usd_respects_decimals() {
+        let env = Env::default();
+        env.mock_all_auths();
+
+        let contract_id = env.register_contract(None, PriceOracle);
+        let client = PriceOracleClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        client.initialize(&admin);
+
+        // 6-decimal token at $2.00
+        let eurc = symbol_short!("EURC");
+        client.register_asset(&eurc, &6);
+        client.set_price(&eurc, &20_000_000);
+        assert_eq!(client.get_decimals(&eurc), 6);
+
+        // 50 tokens = 50_000_000 base units = $100
+        assert_eq!(client.asset_to_usd(&eurc, &50_000_000), 100 * PRICE_SCALE);
+        assert_eq!(client.usd_to_asset(&eurc, &(100 * PRICE_SCALE)), 50_000_000);
+
+        // 7-decimal XLM at $0.30 (unregistered assets default to 7)
+        client.set_price(&XLM, &3_000_000);
+        assert_eq!(client.get_decimals(&XLM), 7);
+        assert_eq!(client.asset_to_usd(&XLM, &(100 * PRICE_SCALE)), 30 * PRICE_SCALE);
+        assert_eq!(client.asset_to_usd(&XLM, &(100 * PRICE_SCALE)), client.xlm_to_usd(&(100 * PRICE_SCALE)));
+    }
+
+    #[test]
+    fn test_twap() {
+        let env = Env::default();
+        env.mock_all_auths();
+
+        let contract_id = env.register_contract(None, PriceOracle);
+        let client = PriceOracleClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        client.initialize(&admin);
+        env.ledger().with_mut(|li| li.timestamp = 1_000);
+
+        // A single observation falls back to the spot price
+        client.set_price(&XLM, &3_000_000);
+        assert_eq!(client.get_twap(&XLM, &600), 3_000_000);
+
+        // $0.30 held for 600s, then a $0.20 spike held for 60s
+        env.ledger().with_mut(|li| li.timestamp += 600);
+        client.set_price(&XLM, &2_000_000);
+        env.ledger().with_mut(|li| li.timestamp += 60);
+
+        // (0.30 * 600 + 0.20 * 60) / 660 ~= $0.2909
+        let twap = client.get_twap(&XLM, &660);
+        assert_eq!(twap, 2_909_090);
+        assert!(twap > 2_000_000 && twap < 3_000_000);
+        assert!(twap > 2_500_000); // weighted toward the longer-held price
+
+        // A short window only sees the latest price
+        assert_eq!(client.get_twap(&XLM, &60), 2_000_000);
+    }
+
+    #[test]
+    fn test_deviation_circuit_breaker() {
+        let env = Env::default();
+        env.mock_all_auths();
+
+        let contract_id = env.register_contract(None, PriceOracle);
+        let client = PriceOracleClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        client.initialize(&admin);
+        client.set_price(&XLM, &3_000_000);
+
+        // 20% cap per update
+        client.set_max_deviation(&XLM, &2_000);
+        assert_eq!(client.get_max_deviation(&XLM), 2_000);
+
+        // A 60% drop is rejected...
+        assert!(client.try_set_price(&XLM, &1_200_000).is_err());
+        assert_eq!(client.get_xlm_price(), 3_000_000);
+
+        // ...a move within the cap is accepted
+        client.set_price(&XLM, &2_500_000);
+        assert_eq!(client.get_xlm_price(), 2_500_000);
+
+        // ...and the admin can force the large move through
+        client.force_set_price(&XLM, &1_000_000);
+        assert_eq!(client.get_xlm_price(), 1_000_000);
+
+        // The crash demo is exempt
+        client.crash_price_half(&XLM);
+        assert_eq!(client.get_xlm_price(), 500_000);
+    }
+
+    #[test]
+    fn test_keeper_median_ignores_outlier() {
+        let env = Env::default();
+        env.mock_all_auths();
+
+        let contract_id = env.register_contract(None, PriceOracle);
+        let client = PriceOracleClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        client.initialize(&admin);
+
+        let keepers = [Address::generate(&env), Address::generate(&env), Address::generate(&env)];
+        for keeper in keepers.iter() {
+            client.add_keeper(keeper);
+        }
+        assert_eq!(client.get_keepers().len(), 3);
+
+        // One faulty keeper reports $0.01
+        client.submit_price(&keepers[0], &XLM, &3_000_000);
+        client.submit_price(&keepers[1], &XLM, &100_000);
+        client.submit_price(&keepers[2], &XLM, &3_100_000);
+        assert_eq!(client.get_price(&XLM), 3_000_000);
+
+        // Unauthorized addresses cannot submit
+        assert!(client.try_submit_price(&Address::generate(&env), &XLM, &1).is_err());
+    }
+
+    #[test]
+    fn test_keeper_median_excludes_stale_submission() {
+        let env = Env::default();
+        env.mock_all_auths();
+
+        let contract_id = env.register_contract(None, PriceOracle);
+        let client = PriceOracleClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        client.initialize(&admin);
+        env.ledger().with_mut(|li| li.timestamp = 1_000);
+
+        let keepers = [Address::generate(&env), Address::generate(&env), Address::generate(&env)];
+        for keeper in keepers.iter() {
+            client.add_keeper(keeper);
+        }
+
+        // First keeper goes offline after one submission
+        client.submit_price(&keepers[0], &XLM, &1_000_000);
+        env.ledger().with_mut(|li| li.timestamp += DEFAULT_STALENESS_THRESHOLD + 1);
+
+        client.submit_price(&keepers[1], &XLM, &3_000_000);
+        client.submit_price(&keepers[2], &XLM, &3_200_000);
+
+        // Median of the two fresh submissions only
+        assert_eq!(client.get_price(&XLM), 3_100_000);
+        assert_eq!(client.get_price_safe(&XLM), 3_100_000);
+    }
+
+    #[test]
+    fn test_keeper_median_respects_deviation_cap() {
+        let env = Env::default();
+        env.mock_all_auths();
+
+        let contract_id = env.register_contract(None, PriceOracle);
+        let client = PriceOracleClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        client.initialize(&admin);
+        client.set_price(&XLM, &3_000_000);
+        client.set_max_deviation(&XLM, &2_000); // 20%
+
+        let keepers = [Address::generate(&env), Address::generate(&env)];
+        for keeper in keepers.iter() {
+            client.add_keeper(keeper);
+        }
+
+        // A lone keeper can't move the stored price 60% in one step
+        assert_eq!(
+            client.try_submit_price(&keepers[0], &XLM, &1_200_000),
+            Err(Ok(soroban_sdk::Error::from_contract_error(OracleError::DeviationTooLarge as u32)))
+        );
+        assert_eq!(client.get_price(&XLM), 3_000_000);
+
+        // A median within the cap is stored and read back by every getter
+        client.submit_price(&keepers[0], &XLM, &3_200_000);
+        client.submit_price(&keepers[1], &XLM, &3_400_000);
+        assert_eq!(client.get_price(&XLM), 3_300_000);
+        assert_eq!(client.get_price_safe(&XLM), 3_300_000);
+    }
+
+    #[test]
+    fn test_keeper_quorum_blocks_lone_submission() {
+        let env = Env::default();
+        env.mock_all_auths();
+
+        let contract_id = env.register_contract(None, PriceOracle);
+        let client = PriceOracleClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        client.initialize(&admin);
+        env.ledger().with_mut(|li| li.timestamp = 1_000);
+
+        let keepers = [Address::generate(&env), Address::generate(&env), Address::generate(&env)];
+        for keeper in keepers.iter() {
+            client.add_keeper(keeper);
+        }
+        assert!(client.try_set_min_submissions(&0).is_err());
+        client.set_min_submissions(&2);
+        assert_eq!(client.get_min_submissions(), 2);
+
+        for keeper in keepers.iter() {
+            client.submit_price(keeper, &XLM, &3_000_000);
+        }
+        assert_eq!(client.get_price(&XLM), 3_000_000);
+
+        // Two keepers go stale; the remaining one alone cannot move the price
+        env.ledger().with_mut(|li| li.timestamp += DEFAULT_STALENESS_THRESHOLD + 1);
+        client.submit_price(&keepers[2], &XLM, &2_500_000);
+        assert_eq!(client.get_price(&XLM), 3_000_000);
+        assert_eq!(client.get_last_update(&XLM), 1_000);
+
+        // A second fresh submission restores the quorum
+        client.submit_price(&keepers[1], &XLM, &2_600_000);
+        assert_eq!(client.get_price(&XLM), 2_550_000);
+    }
+
+    #[test]
+    #[should_panic(expected = "Error(Contract, #1)")]
     fn test_double_initialize() {
         let env = Env::default();
         let contract_id = env.register_contract(None, PriceOracle);
@@ -532,7 +1388,7 @@ mod test {
     }
 
     #[test]
-    #[should_panic(expected = "Price must be positive")]
+    #[should_panic(expected = "Error(Contract, #2)")]
     fn test_zero_price() {
         let env = Env::default();
         env.mock_all_auths();
@@ -544,4 +1400,24 @@ mod test {
         client.initialize(&admin);
         client.set_price(&XLM, &0); // Should panic
     }
+
+    #[test]
+    fn test_get_price_fresh() {
+        let env = Env::default();
+        env.mock_all_auths();
+
+        let contract_id = env.register_contract(None, PriceOracle);
+        let client = PriceOracleClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        client.initialize(&admin);
+        client.set_price(&XLM, &3_000_000);
+
+        env.ledger().with_mut(|li| li.timestamp += 120);
+
+        // Fresh by the oracle's own threshold, but not by a 60s bound
+        assert!(!client.is_stale(&XLM));
+        assert_eq!(client.get_price_fresh(&XLM, &300), 3_000_000);
+        assert!(client.try_get_price_fresh(&XLM, &60).is_err());
+    }
 }
@@ -338,9 +338,9 @@ class SorobanContractService {
       ])
       const usdcShares = sharesResult ? fromContractAmount(sharesResult as bigint) : 0
 
-      // Get health factor
+      // Get health factor (the contract returns i128::MAX when there is no debt)
       const hfResult = await this.callContract("get_health_factor", [userAddr.toScVal()])
-      const healthFactor = hfResult ? fromScaled(hfResult as bigint) : 999
+      const healthFactor = usdcDebt > 0 && hfResult ? fromScaled(hfResult as bigint) : 999
 
       return {
         collateral: { xlm: xlmCollateral },
@@ -64,6 +64,14 @@ const WASM_FILES = {
   interestRateModel: "stellend_interest_rate_model.wasm",
 };
 
+// Simulation errors for re-running `initialize` on an initialized contract
+// (each contract's `AlreadyInitialized` error code)
+const ALREADY_INITIALIZED = {
+  pool: "Error(Contract, #15)",
+  oracle: "Error(Contract, #1)",
+  interestRateModel: "Error(Contract, #1)",
+};
+
 // ============================================================================
 // DEPLOYMENT FUNCTIONS
 // ============================================================================
@@ -280,7 +288,7 @@ async function initializeOracle(
   const simulation = await server.simulateTransaction(transaction);
 
   if (SorobanRpc.Api.isSimulationError(simulation)) {
-    if (simulation.error.includes("Already initialized")) {
+    if (simulation.error.includes(ALREADY_INITIALIZED.oracle)) {
       console.log("   ⚠️  Oracle already initialized");
       return;
     }
@@ -367,7 +375,10 @@ async function initializeInterestRateModel(
   const contract = new StellarSdk.Contract(modelContractId);
 
   // Use initialize_default for the default parameters
-  const operation = contract.call("initialize_default");
+  const operation = contract.call(
+    "initialize_default",
+    StellarSdk.nativeToScVal(publicKey, { type: "address" })
+  );
 
   let transaction = new StellarSdk.TransactionBuilder(sourceAccount, {
     fee: "100000",
@@ -380,7 +391,7 @@ async function initializeInterestRateModel(
   const simulation = await server.simulateTransaction(transaction);
 
   if (SorobanRpc.Api.isSimulationError(simulation)) {
-    if (simulation.error.includes("Already initialized")) {
+    if (simulation.error.includes(ALREADY_INITIALIZED.interestRateModel)) {
       console.log("   ⚠️  Interest Rate Model already initialized");
       return;
     }
@@ -441,7 +452,7 @@ async function initializePool(
   const simulation = await server.simulateTransaction(transaction);
 
   if (SorobanRpc.Api.isSimulationError(simulation)) {
-    if (simulation.error.includes("Already initialized")) {
+    if (simulation.error.includes(ALREADY_INITIALIZED.pool)) {
       console.log("   ⚠️  Pool already initialized");
       return;
     }